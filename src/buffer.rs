@@ -1,19 +1,113 @@
 pub mod text_buffer;
+pub mod history;
+pub mod undofile;
+pub mod diagnostics;
+pub mod edit;
+pub mod snippet;
 
+use anyhow::anyhow;
 use ropey::Rope;
 
-use crate::lsp::{client::ResponseReceiver, method::{completion::CompletionFetch, hover::HoverFetch}};
+use history::UndoTarget;
+
+use crate::lsp::method::{completion::{CompletionFetch, CompletionResolveFetch}, goto::{GotoFetch, GotoKind}, hover::HoverFetch, references::ReferencesFetch, rename::{PrepareRenameFetch, RenameFetch}, code_action::CodeActionFetch, formatting::FormattingFetch, signature_help::SignatureHelpFetch};
 
 pub type CursorPos = (usize, usize);
 
+/// Per-buffer settings changed with `:set`.
+#[derive(Debug, Clone)]
+pub struct BufferOptions {
+    pub tabstop: usize,
+    pub expandtab: bool,
+    pub readonly: bool,
+    pub undofile: bool,
+}
+
+impl Default for BufferOptions {
+    fn default() -> Self {
+        Self { tabstop: 4, expandtab: false, readonly: false, undofile: false }
+    }
+}
+
+impl BufferOptions {
+    /// `value` is `None` for the bare `:set name` / `:set noname` forms.
+    pub fn set(&mut self, name: &str, value: Option<&str>) -> anyhow::Result<()> {
+        let (name, enable) = match name.strip_prefix("no") {
+            Some(rest) if value.is_none() && Self::is_bool(rest) => (rest, false),
+            _ => (name, true),
+        };
+        match (name, value) {
+            ("tabstop" | "ts", Some(v)) => {
+                let n = v.parse::<usize>().map_err(|_| anyhow!("invalid number: {}", v))?;
+                if n == 0 {
+                    return Err(anyhow!("tabstop must be positive"));
+                }
+                self.tabstop = n;
+            }
+            ("expandtab" | "et", None) => self.expandtab = enable,
+            ("readonly" | "ro", None) => self.readonly = enable,
+            ("undofile" | "udf", None) => self.undofile = enable,
+            ("tabstop" | "ts", None) => return Err(anyhow!("{} needs a value", name)),
+            ("expandtab" | "et" | "readonly" | "ro" | "undofile" | "udf", Some(_)) => return Err(anyhow!("{} does not take a value", name)),
+            _ => return Err(anyhow!("unknown option: {}", name)),
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> anyhow::Result<String> {
+        let bool_str = |name: &str, b: bool| if b { name.to_owned() } else { format!("no{}", name) };
+        match name {
+            "tabstop" | "ts" => Ok(format!("tabstop={}", self.tabstop)),
+            "expandtab" | "et" => Ok(bool_str("expandtab", self.expandtab)),
+            "readonly" | "ro" => Ok(bool_str("readonly", self.readonly)),
+            "undofile" | "udf" => Ok(bool_str("undofile", self.undofile)),
+            _ => Err(anyhow!("unknown option: {}", name)),
+        }
+    }
+
+    pub fn is_bool(name: &str) -> bool {
+        matches!(name, "expandtab" | "et" | "readonly" | "ro" | "undofile" | "udf")
+    }
+}
+
 pub trait Buffer {
+    fn filename(&self) -> Option<&str>;
     fn rope_clone(&self) -> Rope;
     fn len_lines(&self) -> usize;
     fn len_line_chars(&self, i: usize) -> usize;
-    fn insert_char(&mut self, cursor: CursorPos, c: char) -> impl std::future::Future<Output=anyhow::Result<CursorPos>>;
-    fn newline(&mut self, cursor: CursorPos) -> impl std::future::Future<Output=anyhow::Result<CursorPos>>;
-    fn backspace(&mut self, cursor: CursorPos) -> impl std::future::Future<Output=anyhow::Result<CursorPos>>;
-    fn edit(&mut self, start: CursorPos, end: CursorPos, text: &str) -> impl std::future::Future<Output=anyhow::Result<CursorPos>>;
-    fn hover(&self, cursor: CursorPos) -> impl std::future::Future<Output = anyhow::Result<Option<HoverFetch>>>;
-    fn completion(&self, cursor: CursorPos) -> impl std::future::Future<Output = anyhow::Result<Option<CompletionFetch>>>;
+    fn insert_char(&mut self, cursor: CursorPos, c: char) -> anyhow::Result<CursorPos>;
+    fn newline(&mut self, cursor: CursorPos) -> anyhow::Result<CursorPos>;
+    fn backspace(&mut self, cursor: CursorPos) -> anyhow::Result<CursorPos>;
+    fn edit(&mut self, start: CursorPos, end: CursorPos, text: &str) -> anyhow::Result<CursorPos>;
+    /// Apply edits computed against the current text as one undo step and
+    /// one document version. Returns where `cursor` moved to.
+    fn apply_edits(&mut self, edits: &[edit::Edit], cursor: CursorPos) -> anyhow::Result<CursorPos>;
+    fn begin_undo_group(&mut self, cursor: CursorPos);
+    fn end_undo_group(&mut self, cursor: CursorPos);
+    /// Returns the cursor to restore, or `None` when there is nothing to undo.
+    fn undo(&mut self) -> anyhow::Result<Option<CursorPos>>;
+    fn redo(&mut self) -> anyhow::Result<Option<CursorPos>>;
+    fn undo_travel(&mut self, target: UndoTarget) -> anyhow::Result<Option<CursorPos>>;
+    fn options(&self) -> &BufferOptions;
+    fn options_mut(&mut self) -> &mut BufferOptions;
+    fn is_dirty(&self) -> bool;
+    /// Goes up with every change of the text.
+    fn version(&self) -> i32;
+    fn save(&mut self) -> anyhow::Result<()>;
+    fn hover(&mut self, cursor: CursorPos) -> anyhow::Result<Option<HoverFetch>>;
+    fn completion(&mut self, cursor: CursorPos) -> anyhow::Result<Option<CompletionFetch>>;
+    /// `None` when there is no server or it has nothing to add to items.
+    fn resolve_completion(&self, item: lsp_types::CompletionItem) -> anyhow::Result<Option<CompletionResolveFetch>>;
+    /// `None` when there is no server or `typed` does not trigger signature help.
+    /// `active` is the help shown now, if any.
+    fn signature_help(&mut self, cursor: CursorPos, typed: Option<char>, active: Option<&lsp_types::SignatureHelp>) -> anyhow::Result<Option<SignatureHelpFetch>>;
+    fn goto(&mut self, kind: GotoKind, cursor: CursorPos) -> anyhow::Result<Option<GotoFetch>>;
+    fn references(&mut self, cursor: CursorPos) -> anyhow::Result<Option<ReferencesFetch>>;
+    fn prepare_rename(&mut self, cursor: CursorPos) -> anyhow::Result<Option<PrepareRenameFetch>>;
+    fn rename(&mut self, cursor: CursorPos, new_name: &str) -> anyhow::Result<Option<RenameFetch>>;
+    fn code_actions(&mut self, range: (CursorPos, CursorPos)) -> anyhow::Result<Option<CodeActionFetch>>;
+    /// Format `range`, or the whole document when it is `None`.
+    fn formatting(&mut self, range: Option<(CursorPos, CursorPos)>) -> anyhow::Result<Option<FormattingFetch>>;
+    /// Latest diagnostics from the language server, sorted by position.
+    fn diagnostics(&self) -> &[lsp_types::Diagnostic];
 }
@@ -0,0 +1,163 @@
+use lsp_types::{Diagnostic, DiagnosticSeverity};
+
+use super::CursorPos;
+
+pub fn start(d: &Diagnostic) -> CursorPos {
+    (d.range.start.line as usize, d.range.start.character as usize)
+}
+
+pub fn end(d: &Diagnostic) -> CursorPos {
+    (d.range.end.line as usize, d.range.end.character as usize)
+}
+
+/// Servers may leave the severity out; it is then taken as an error.
+pub fn severity(d: &Diagnostic) -> DiagnosticSeverity {
+    d.severity.unwrap_or(DiagnosticSeverity::ERROR)
+}
+
+/// Lower is more severe, as in the protocol.
+fn rank(severity: DiagnosticSeverity) -> i32 {
+    match severity {
+        DiagnosticSeverity::ERROR => 1,
+        DiagnosticSeverity::WARNING => 2,
+        DiagnosticSeverity::INFORMATION => 3,
+        _ => 4,
+    }
+}
+
+pub fn label(severity: DiagnosticSeverity) -> &'static str {
+    match severity {
+        DiagnosticSeverity::ERROR => "error",
+        DiagnosticSeverity::WARNING => "warning",
+        DiagnosticSeverity::INFORMATION => "info",
+        _ => "hint",
+    }
+}
+
+fn worse(a: Option<DiagnosticSeverity>, b: DiagnosticSeverity) -> Option<DiagnosticSeverity> {
+    match a {
+        Some(a) if rank(a) <= rank(b) => Some(a),
+        _ => Some(b),
+    }
+}
+
+/// Sort by position so that `next` can walk them in order.
+pub fn sort(diagnostics: &mut [Diagnostic]) {
+    diagnostics.sort_by_key(|d| (start(d), rank(severity(d))));
+}
+
+/// The most severe diagnostic under `cursor`, or else the first one on its line.
+pub fn at(diagnostics: &[Diagnostic], cursor: CursorPos) -> Option<&Diagnostic> {
+    let covering = diagnostics.iter()
+        .filter(|d| start(d) <= cursor && (cursor < end(d) || start(d) == cursor))
+        .min_by_key(|d| rank(severity(d)));
+    covering.or_else(|| diagnostics.iter().find(|d| d.range.start.line as usize == cursor.0))
+}
+
+/// The worst severity starting on `line`, shown in the sign column.
+pub fn sign(diagnostics: &[Diagnostic], line: usize) -> Option<DiagnosticSeverity> {
+    diagnostics.iter()
+        .filter(|d| d.range.start.line as usize == line)
+        .fold(None, |acc, d| worse(acc, severity(d)))
+}
+
+/// The severity to underline each of the `len` columns of `line` with.
+/// An empty range marks the single character it points at.
+pub fn line_marks(diagnostics: &[Diagnostic], line: usize, len: usize) -> Vec<Option<DiagnosticSeverity>> {
+    let mut marks = vec![None; len];
+    for d in diagnostics {
+        let (start, end) = (start(d), end(d));
+        if line < start.0 || end.0 < line {
+            continue;
+        }
+        let from = if start.0 == line { start.1 } else { 0 };
+        let to = if end.0 == line { end.1 } else { len };
+        let to = if start == end { from + 1 } else { to };
+        for mark in marks.iter_mut().take(to.min(len)).skip(from) {
+            *mark = worse(*mark, severity(d));
+        }
+    }
+    marks
+}
+
+/// The diagnostics touching `start..end`, sent along when asking for code actions.
+/// An empty range counts as the character under it.
+pub fn overlapping(diagnostics: &[Diagnostic], from: CursorPos, to: CursorPos) -> Vec<Diagnostic> {
+    let to = if from == to { (to.0, to.1 + 1) } else { to };
+    diagnostics.iter()
+        .filter(|d| start(d) < to && (from < end(d) || start(d) == from))
+        .cloned()
+        .collect()
+}
+
+/// Start of the next (or previous) diagnostic after `cursor`, wrapping around.
+pub fn next(diagnostics: &[Diagnostic], cursor: CursorPos, forward: bool) -> Option<CursorPos> {
+    let mut starts = diagnostics.iter().map(start).collect::<Vec<_>>();
+    starts.sort();
+    starts.dedup();
+    if forward {
+        starts.iter().find(|&&p| p > cursor).or(starts.first()).copied()
+    }
+    else {
+        starts.iter().rev().find(|&&p| p < cursor).or(starts.last()).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Diagnostic, DiagnosticSeverity, Position, Range};
+
+    use super::{at, line_marks, next, overlapping, sign};
+
+    fn diag(start: (u32, u32), end: (u32, u32), severity: DiagnosticSeverity, message: &str) -> Diagnostic {
+        Diagnostic {
+            range: Range { start: Position::new(start.0, start.1), end: Position::new(end.0, end.1) },
+            severity: Some(severity),
+            message: message.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn find_and_walk() {
+        let diags = vec![
+            diag((1, 2), (1, 5), DiagnosticSeverity::WARNING, "w"),
+            diag((1, 3), (1, 4), DiagnosticSeverity::ERROR, "e"),
+            diag((4, 0), (4, 0), DiagnosticSeverity::HINT, "h"),
+        ];
+        assert_eq!(at(&diags, (1, 3)).unwrap().message, "e");
+        assert_eq!(at(&diags, (1, 4)).unwrap().message, "w");
+        assert_eq!(at(&diags, (1, 0)).unwrap().message, "w");
+        assert_eq!(at(&diags, (4, 0)).unwrap().message, "h");
+        assert!(at(&diags, (2, 0)).is_none());
+
+        assert_eq!(next(&diags, (0, 0), true), Some((1, 2)));
+        assert_eq!(next(&diags, (1, 2), true), Some((1, 3)));
+        assert_eq!(next(&diags, (4, 0), true), Some((1, 2)));
+        assert_eq!(next(&diags, (1, 2), false), Some((4, 0)));
+        assert_eq!(next(&[], (0, 0), true), None);
+
+        let messages = |start, end| overlapping(&diags, start, end).into_iter().map(|d| d.message).collect::<String>();
+        assert_eq!(messages((1, 3), (1, 3)), "we");
+        assert_eq!(messages((1, 4), (1, 4)), "w");
+        assert_eq!(messages((0, 0), (1, 3)), "w");
+        assert_eq!(messages((4, 0), (4, 0)), "h");
+        assert_eq!(messages((2, 0), (3, 9)), "");
+    }
+
+    #[test]
+    fn marks() {
+        let diags = vec![
+            diag((0, 1), (0, 3), DiagnosticSeverity::WARNING, "w"),
+            diag((0, 2), (2, 1), DiagnosticSeverity::ERROR, "e"),
+            diag((3, 2), (3, 2), DiagnosticSeverity::HINT, "h"),
+        ];
+        let (w, e, h) = (Some(DiagnosticSeverity::WARNING), Some(DiagnosticSeverity::ERROR), Some(DiagnosticSeverity::HINT));
+        assert_eq!(line_marks(&diags, 0, 5), vec![None, w, e, e, e]);
+        assert_eq!(line_marks(&diags, 1, 2), vec![e, e]);
+        assert_eq!(line_marks(&diags, 2, 3), vec![e, None, None]);
+        assert_eq!(line_marks(&diags, 3, 4), vec![None, None, h, None]);
+        assert_eq!(sign(&diags, 0), e);
+        assert_eq!(sign(&diags, 1), None);
+    }
+}
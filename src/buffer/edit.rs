@@ -0,0 +1,140 @@
+use lsp_types::TextEdit;
+
+use super::CursorPos;
+
+/// Replace `start..end` with the text.
+pub type Edit = (CursorPos, CursorPos, String);
+
+/// `edit` with its positions taken as they are, columns left in the
+/// server's units.
+pub fn from_lsp(edit: &TextEdit) -> Edit {
+    let start = (edit.range.start.line as usize, edit.range.start.character as usize);
+    let end = (edit.range.end.line as usize, edit.range.end.character as usize);
+    (start, end, edit.new_text.clone())
+}
+
+/// Sort `edits` so that applying them one by one leaves the positions of
+/// the rest valid: from the end of the document backwards. Edits at the
+/// same position keep their order in the text.
+pub fn apply_order(edits: &[Edit]) -> Vec<Edit> {
+    let mut indexed = edits.iter().cloned().enumerate().collect::<Vec<_>>();
+    indexed.sort_by(|(i, a), (j, b)| (b.0, b.1, j).cmp(&(a.0, a.1, i)));
+    indexed.into_iter().map(|(_, e)| e).collect()
+}
+
+/// Where `text` ends when inserted at `start`.
+pub fn end_of_insert(start: CursorPos, text: &str) -> CursorPos {
+    match text.rfind('\n') {
+        Some(i) => (start.0 + text.matches('\n').count(), text[i + 1..].chars().count()),
+        None => (start.0, start.1 + text.chars().count()),
+    }
+}
+
+/// Where `pos` ends up after `edit`. Text after the edit moves with it, a
+/// position inside the replaced range goes to the end of the new text, and
+/// an insertion right at `pos` is placed before it.
+pub fn transform(pos: CursorPos, edit: &Edit) -> CursorPos {
+    let (start, end, ref text) = *edit;
+    if pos < start || (pos == start && start < end) {
+        return pos;
+    }
+    let new_end = end_of_insert(start, text);
+    if pos < end {
+        return new_end;
+    }
+    if pos.0 == end.0 {
+        (new_end.0, new_end.1 + pos.1 - end.1)
+    }
+    else {
+        (pos.0 + new_end.0 - end.0, pos.1)
+    }
+}
+
+/// Where `pos` ends up after all of `edits`, computed against the same text.
+pub fn transform_all(pos: CursorPos, edits: &[Edit]) -> CursorPos {
+    apply_order(edits).iter().fold(pos, transform)
+}
+
+/// Like `transform`, but a position inside the replaced range keeps its
+/// line and column in the new text as far as it reaches. Formatters often
+/// replace whole blocks, and the cursor should not jump to their end.
+pub fn transform_within(pos: CursorPos, edit: &Edit) -> CursorPos {
+    let (start, end, ref text) = *edit;
+    if !(start < pos && pos < end) {
+        return transform(pos, edit);
+    }
+    let lines = text.split('\n').collect::<Vec<_>>();
+    let line = (pos.0 - start.0).min(lines.len() - 1);
+    let base = if line == 0 { start.1 } else { 0 };
+    let col = if pos.0 == start.0 { pos.1 - start.1 } else { pos.1 };
+    (start.0 + line, base + col.min(lines[line].chars().count()))
+}
+
+/// Where `pos` ends up after all of `edits`, computed against the same text.
+pub fn transform_all_within(pos: CursorPos, edits: &[Edit]) -> CursorPos {
+    apply_order(edits).iter().fold(pos, transform_within)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_order, end_of_insert, transform, transform_all, transform_all_within};
+
+    #[test]
+    fn order() {
+        let edits = vec![
+            ((0, 1), (0, 2), "a".to_owned()),
+            ((2, 0), (2, 0), "b".to_owned()),
+            ((2, 0), (2, 0), "c".to_owned()),
+            ((1, 0), (1, 3), "d".to_owned()),
+        ];
+        let texts = apply_order(&edits).into_iter().map(|e| e.2).collect::<String>();
+        assert_eq!(texts, "cbda");
+    }
+
+    #[test]
+    fn positions() {
+        assert_eq!(end_of_insert((2, 3), "ab"), (2, 5));
+        assert_eq!(end_of_insert((2, 3), "ab\ncde\nf"), (4, 1));
+
+        let insert = ((1, 2), (1, 2), "xy".to_owned());
+        assert_eq!(transform((1, 2), &insert), (1, 4));
+        assert_eq!(transform((1, 1), &insert), (1, 1));
+        assert_eq!(transform((2, 0), &insert), (2, 0));
+
+        // an import added above the cursor pushes it down
+        let import = ((0, 0), (0, 0), "use a;\n".to_owned());
+        assert_eq!(transform((3, 4), &import), (4, 4));
+
+        let join = ((1, 3), (2, 1), "-".to_owned());
+        assert_eq!(transform((1, 3), &join), (1, 3));
+        assert_eq!(transform((1, 5), &join), (1, 4));
+        assert_eq!(transform((2, 4), &join), (1, 7));
+        assert_eq!(transform((5, 0), &join), (4, 0));
+    }
+
+    #[test]
+    fn completion_with_imports() {
+        // one import at the top and one at the start of the completed line,
+        // both computed against the text before either
+        let imports = vec![
+            ((0, 0), (0, 0), "use a;\n".to_owned()),
+            ((3, 0), (3, 0), "b::".to_owned()),
+        ];
+        assert_eq!(transform_all((3, 4), &imports), (4, 7));
+    }
+
+    #[test]
+    fn positions_in_reformatted_text() {
+        // the whole file replaced: the cursor stays on its line
+        let whole = [((0, 0), (3, 0), "fn f() {\n    g();\n}\n".to_owned())];
+        assert_eq!(transform_all_within((1, 2), &whole), (1, 2));
+        assert_eq!(transform_all_within((1, 9), &whole), (1, 8));
+
+        let edits = vec![
+            ((0, 0), (0, 0), "// a\n".to_owned()),
+            ((2, 1), (2, 5), "  ".to_owned()),
+        ];
+        assert_eq!(transform_all_within((2, 3), &edits), (3, 3));
+        assert_eq!(transform_all_within((2, 7), &edits), (3, 5));
+    }
+}
@@ -0,0 +1,384 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::CursorPos;
+
+/// `removed` at char index `at` was replaced by `inserted`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    pub at: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Change {
+    pub fn inverse(&self) -> Change {
+        Change { at: self.at, removed: self.inserted.clone(), inserted: self.removed.clone() }
+    }
+}
+
+/// Changes undone and redone as one step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeGroup {
+    pub changes: Vec<Change>,
+    pub cursor_before: CursorPos,
+    pub cursor_after: CursorPos,
+}
+
+impl ChangeGroup {
+    /// The changes that revert this group, in the order they must be applied.
+    pub fn inverse(&self) -> Vec<Change> {
+        self.changes.iter().rev().map(Change::inverse).collect()
+    }
+}
+
+/// Where to move in the undo tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndoTarget {
+    /// Move by this many states in the order they were created, across branches (`g-`, `g+`).
+    Steps(isize),
+    /// Move to the state the buffer had this many seconds earlier (negative) or later.
+    Seconds(i64),
+}
+
+/// The changes to apply to go from one state to another.
+#[derive(Debug)]
+pub struct Travel {
+    pub changes: Vec<Change>,
+    pub cursor: CursorPos,
+}
+
+/// A state of the buffer, reached from `parent` by applying `group`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UndoNode {
+    parent: usize,
+    group: ChangeGroup,
+    /// Seconds since the epoch.
+    time: u64,
+    /// The child redo moves into; the most recently visited branch.
+    redo_child: Option<usize>,
+}
+
+/// Undo tree. Node 0 is the state the buffer was opened with and node
+/// indices double as the order in which the states were created.
+#[derive(Serialize, Deserialize)]
+pub struct History {
+    nodes: Vec<UndoNode>,
+    current: usize,
+    #[serde(skip)]
+    pending: Option<ChangeGroup>,
+    #[serde(skip)]
+    depth: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        let root = UndoNode {
+            parent: 0,
+            group: ChangeGroup { changes: vec![], cursor_before: (0, 0), cursor_after: (0, 0) },
+            time: now(),
+            redo_child: None,
+        };
+        Self { nodes: vec![root], current: 0, pending: None, depth: 0 }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl History {
+    /// Groups may nest; changes are collected until the outermost group ends.
+    pub fn begin_group(&mut self, cursor: CursorPos) {
+        if self.depth == 0 {
+            self.pending = Some(ChangeGroup { changes: vec![], cursor_before: cursor, cursor_after: cursor });
+        }
+        self.depth += 1;
+    }
+
+    pub fn end_group(&mut self, cursor: CursorPos) {
+        if self.depth == 0 {
+            return;
+        }
+        self.depth -= 1;
+        if self.depth == 0 {
+            self.commit_pending(Some(cursor));
+        }
+    }
+
+    fn commit_pending(&mut self, cursor: Option<CursorPos>) {
+        self.depth = 0;
+        if let Some(mut group) = self.pending.take() {
+            if !group.changes.is_empty() {
+                if let Some(cursor) = cursor {
+                    group.cursor_after = cursor;
+                }
+                self.push_node(group);
+            }
+        }
+    }
+
+    fn push_node(&mut self, group: ChangeGroup) {
+        let idx = self.nodes.len();
+        self.nodes.push(UndoNode { parent: self.current, group, time: now(), redo_child: None });
+        self.nodes[self.current].redo_child = Some(idx);
+        self.current = idx;
+    }
+
+    pub fn record(&mut self, change: Change, cursor_before: CursorPos, cursor_after: CursorPos) {
+        match self.pending {
+            Some(ref mut group) => {
+                group.changes.push(change);
+                group.cursor_after = cursor_after;
+            }
+            None => {
+                self.push_node(ChangeGroup { changes: vec![change], cursor_before, cursor_after });
+            }
+        }
+    }
+
+    pub fn pop_undo(&mut self) -> Option<ChangeGroup> {
+        self.commit_pending(None);
+        if self.current == 0 {
+            return None;
+        }
+        let node = &self.nodes[self.current];
+        let (parent, group) = (node.parent, node.group.clone());
+        self.nodes[parent].redo_child = Some(self.current);
+        self.current = parent;
+        Some(group)
+    }
+
+    pub fn pop_redo(&mut self) -> Option<ChangeGroup> {
+        self.commit_pending(None);
+        let child = self.nodes[self.current].redo_child?;
+        self.current = child;
+        Some(self.nodes[child].group.clone())
+    }
+
+    /// Jump to another state, possibly on a different branch.
+    pub fn travel(&mut self, target: UndoTarget) -> Option<Travel> {
+        self.commit_pending(None);
+        let last = self.nodes.len() - 1;
+        let to = match target {
+            UndoTarget::Steps(n) => self.current.saturating_add_signed(n).min(last),
+            UndoTarget::Seconds(secs) => {
+                let time = self.nodes[self.current].time.saturating_add_signed(secs);
+                let found = self.nodes.iter().rposition(|node| node.time <= time).unwrap_or(0);
+                if secs < 0 { found.min(self.current) } else { found.max(self.current) }
+            }
+        };
+        if to == self.current {
+            return None;
+        }
+        Some(self.goto(to))
+    }
+
+    /// The nodes from `from` up to, but not including, the closest state both
+    /// `from` and `to` descend from, and likewise from `to`. A parent is always
+    /// older than its children, so the newer of the two steps up until they meet.
+    fn paths_to_common(&self, from: usize, to: usize) -> (Vec<usize>, Vec<usize>) {
+        let (mut up, mut down) = (from, to);
+        let (mut up_path, mut down_path) = (vec![], vec![]);
+        while up != down {
+            if up > down {
+                up_path.push(up);
+                up = self.nodes[up].parent;
+            }
+            else {
+                down_path.push(down);
+                down = self.nodes[down].parent;
+            }
+        }
+        (up_path, down_path)
+    }
+
+    fn goto(&mut self, to: usize) -> Travel {
+        let (up_path, down_path) = self.paths_to_common(self.current, to);
+
+        let mut changes = vec![];
+        let mut cursor = self.nodes[self.current].group.cursor_after;
+        for &idx in up_path.iter() {
+            let node = &self.nodes[idx];
+            changes.extend(node.group.inverse());
+            cursor = node.group.cursor_before;
+            let parent = node.parent;
+            self.nodes[parent].redo_child = Some(idx);
+        }
+        for &idx in down_path.iter().rev() {
+            let node = &self.nodes[idx];
+            changes.extend(node.group.changes.iter().cloned());
+            cursor = node.group.cursor_after;
+            let parent = node.parent;
+            self.nodes[parent].redo_child = Some(idx);
+        }
+        self.current = to;
+        Travel { changes, cursor }
+    }
+
+    /// Number of leaves, i.e. branches that can be reached with `g-`/`g+`.
+    pub fn branches(&self) -> usize {
+        let mut has_child = vec![false; self.nodes.len()];
+        for node in self.nodes.iter().skip(1) {
+            has_child[node.parent] = true;
+        }
+        has_child.iter().filter(|&&b| !b).count()
+    }
+
+    pub fn current_state(&self) -> usize {
+        self.current
+    }
+
+    /// Whether an open group already holds changes, i.e. the contents are
+    /// ahead of the current state.
+    pub fn has_pending(&self) -> bool {
+        self.pending.as_ref().is_some_and(|group| !group.changes.is_empty())
+    }
+
+    pub fn states(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether a deserialized tree is one this module could have built: every
+    /// index is in range and parents are older than their children, which
+    /// `paths_to_common` relies on to terminate.
+    pub fn is_valid(&self) -> bool {
+        let len = self.nodes.len();
+        len > 0
+            && self.current < len
+            && self.nodes[0].parent == 0
+            && self.nodes.iter().enumerate().all(|(idx, node)| {
+                (idx == 0 || node.parent < idx)
+                    && node.redo_child.is_none_or(|child| child < len && child != 0 && self.nodes[child].parent == idx)
+            })
+    }
+
+    /// Close any open group so that the tree describes the current contents.
+    pub fn flush(&mut self) {
+        self.commit_pending(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Change, History, UndoTarget};
+
+    fn insert(at: usize, s: &str) -> Change {
+        Change { at, removed: String::new(), inserted: s.to_owned() }
+    }
+
+    /// Apply `changes` to `text` the way TextBuffer applies them to its rope.
+    fn apply(text: &mut String, changes: &[Change]) {
+        for change in changes {
+            let at = text.char_indices().nth(change.at).map(|(i, _)| i).unwrap_or(text.len());
+            let end = at + change.removed.len();
+            assert_eq!(&text[at..end], change.removed);
+            text.replace_range(at..end, &change.inserted);
+        }
+    }
+
+    #[test]
+    fn group_is_one_step() {
+        let mut history = History::default();
+        history.begin_group((0, 0));
+        history.record(insert(0, "a"), (0, 0), (0, 1));
+        history.record(insert(1, "b"), (0, 1), (0, 2));
+        history.end_group((0, 2));
+        history.record(insert(2, "c"), (0, 2), (0, 3));
+
+        let group = history.pop_undo().unwrap();
+        assert_eq!(group.changes, vec![insert(2, "c")]);
+        let group = history.pop_undo().unwrap();
+        assert_eq!(group.changes.len(), 2);
+        assert_eq!(group.cursor_before, (0, 0));
+        assert_eq!(group.inverse()[0], Change { at: 1, removed: "b".to_owned(), inserted: String::new() });
+        assert!(history.pop_undo().is_none());
+    }
+
+    #[test]
+    fn nested_groups_merge() {
+        let mut history = History::default();
+        history.begin_group((0, 0));
+        history.record(insert(0, "a"), (0, 0), (0, 1));
+        history.begin_group((0, 1));
+        history.record(insert(1, "b"), (0, 1), (0, 2));
+        history.end_group((0, 2));
+        history.end_group((0, 2));
+        assert_eq!(history.pop_undo().unwrap().changes.len(), 2);
+    }
+
+    #[test]
+    fn edit_after_undo_keeps_old_branch() {
+        let mut history = History::default();
+        let mut text = String::new();
+        for (at, s) in [(0, "a"), (1, "b")] {
+            history.record(insert(at, s), (0, at), (0, at + 1));
+            apply(&mut text, &[insert(at, s)]);
+        }
+        apply(&mut text, &history.pop_undo().unwrap().inverse());
+        history.record(insert(1, "c"), (0, 1), (0, 2));
+        apply(&mut text, &[insert(1, "c")]);
+        assert_eq!(text, "ac");
+        assert_eq!(history.branches(), 2);
+
+        // redo follows the newest branch
+        apply(&mut text, &history.pop_undo().unwrap().inverse());
+        apply(&mut text, &history.pop_redo().unwrap().changes);
+        assert_eq!(text, "ac");
+
+        // "ab" was state 2, "ac" is state 3
+        let travel = history.travel(UndoTarget::Steps(-1)).unwrap();
+        apply(&mut text, &travel.changes);
+        assert_eq!(text, "ab");
+        assert_eq!(travel.cursor, (0, 2));
+        let travel = history.travel(UndoTarget::Steps(-2)).unwrap();
+        apply(&mut text, &travel.changes);
+        assert_eq!(text, "");
+        assert!(history.travel(UndoTarget::Steps(-1)).is_none());
+        let travel = history.travel(UndoTarget::Steps(10)).unwrap();
+        apply(&mut text, &travel.changes);
+        assert_eq!(text, "ac");
+    }
+
+    #[test]
+    fn travel_by_time() {
+        let mut history = History::default();
+        history.record(insert(0, "a"), (0, 0), (0, 1));
+        history.record(insert(1, "b"), (0, 1), (0, 2));
+        history.nodes[0].time = 100;
+        history.nodes[1].time = 200;
+        history.nodes[2].time = 300;
+
+        let travel = history.travel(UndoTarget::Seconds(-100)).unwrap();
+        assert_eq!(history.current_state(), 1);
+        assert_eq!(travel.changes, vec![insert(1, "b").inverse()]);
+        history.travel(UndoTarget::Seconds(-1000)).unwrap();
+        assert_eq!(history.current_state(), 0);
+        assert!(history.travel(UndoTarget::Seconds(-10)).is_none());
+        history.travel(UndoTarget::Seconds(1000)).unwrap();
+        assert_eq!(history.current_state(), 2);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let mut history = History::default();
+        history.record(insert(0, "a"), (0, 0), (0, 1));
+        let json = serde_json::to_string(&history).unwrap();
+        let mut restored: History = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.current_state(), 1);
+        assert_eq!(restored.pop_undo().unwrap().changes, vec![insert(0, "a")]);
+    }
+
+    #[test]
+    fn invalid_indices() {
+        let mut history = History::default();
+        history.record(insert(0, "a"), (0, 0), (0, 1));
+        let json = serde_json::to_string(&history).unwrap();
+        assert!(serde_json::from_str::<History>(&json).unwrap().is_valid());
+        for (from, to) in [("\"current\":1", "\"current\":2"), ("\"redo_child\":1", "\"redo_child\":5"), ("\"parent\":0", "\"parent\":1")] {
+            let json = json.replacen(from, to, if from.contains("parent") { 2 } else { 1 });
+            assert!(!serde_json::from_str::<History>(&json).unwrap().is_valid(), "{}", json);
+        }
+    }
+}
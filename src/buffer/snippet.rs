@@ -0,0 +1,469 @@
+//! LSP snippets: `$1`, `${1:placeholder}`, `${1|one,two|}`, `$VAR` and
+//! `${VAR:default}`, expanded to plain text and the tab stops in it.
+
+use std::collections::BTreeMap;
+
+use ropey::Rope;
+
+use super::{CursorPos, edit::{Edit, end_of_insert, transform}};
+
+/// A tab stop of an expanded snippet; its mirrors share it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TabStop {
+    pub number: u32,
+    /// Char offsets into the text, one range per occurrence.
+    pub ranges: Vec<(usize, usize)>,
+    pub choices: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    pub text: String,
+    /// In the order Tab visits them. `$0`, the end of the text when the
+    /// snippet has none, comes last.
+    pub stops: Vec<TabStop>,
+}
+
+enum Node {
+    Text(String),
+    Stop { number: u32, children: Vec<Node>, choices: Vec<String> },
+    Variable { name: String, default: Option<Vec<Node>> },
+}
+
+struct Parser {
+    chars: Vec<char>,
+    i: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.i).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.i += 1;
+        }
+        found
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let start = self.i;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.i += 1;
+        }
+        self.chars[start..self.i].iter().collect::<String>().parse().ok()
+    }
+
+    fn name(&mut self) -> Option<String> {
+        if !self.peek().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') {
+            return None;
+        }
+        let start = self.i;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+            self.i += 1;
+        }
+        Some(self.chars[start..self.i].iter().collect())
+    }
+
+    /// Text and markup up to the end, or up to the `}` closing the
+    /// placeholder being parsed, which is left for the caller.
+    fn nodes(&mut self, nested: bool) -> Vec<Node> {
+        let mut nodes = vec![];
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            if c == '}' && nested {
+                break;
+            }
+            if c == '\\' && self.chars.get(self.i + 1).is_some_and(|c| matches!(c, '$' | '}' | '\\')) {
+                text.push(self.chars[self.i + 1]);
+                self.i += 2;
+                continue;
+            }
+            let start = self.i;
+            match self.dollar() {
+                Some(node) => {
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+                    nodes.push(node);
+                }
+                // not markup after all; the `$` is text
+                None => {
+                    self.i = start + 1;
+                    text.push(c);
+                }
+            }
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        nodes
+    }
+
+    /// The markup starting at `$`, if it is well formed.
+    fn dollar(&mut self) -> Option<Node> {
+        if !self.eat('$') {
+            return None;
+        }
+        if let Some(number) = self.number() {
+            return Some(Node::Stop { number, children: vec![], choices: vec![] });
+        }
+        if let Some(name) = self.name() {
+            return Some(Node::Variable { name, default: None });
+        }
+        if !self.eat('{') {
+            return None;
+        }
+        if let Some(number) = self.number() {
+            let (children, choices) = if self.eat(':') {
+                (self.nodes(true), vec![])
+            }
+            else if self.eat('|') {
+                (vec![], self.choices()?)
+            }
+            else {
+                (vec![], vec![])
+            };
+            return self.eat('}').then_some(Node::Stop { number, children, choices });
+        }
+        let name = self.name()?;
+        let default = if self.eat(':') {
+            Some(self.nodes(true))
+        }
+        else {
+            // a transform of the value is not applied; the value is used as it is
+            if self.eat('/') {
+                self.skip_transform()?;
+            }
+            None
+        };
+        self.eat('}').then_some(Node::Variable { name, default })
+    }
+
+    /// `one,two|` of a choice, with `\,` and `\|` escaped.
+    fn choices(&mut self) -> Option<Vec<String>> {
+        let mut choices = vec![String::new()];
+        loop {
+            match self.peek()? {
+                '\\' if self.chars.get(self.i + 1).is_some_and(|c| matches!(c, ',' | '|' | '\\')) => {
+                    choices.last_mut()?.push(self.chars[self.i + 1]);
+                    self.i += 1;
+                }
+                ',' => choices.push(String::new()),
+                '|' => {
+                    self.i += 1;
+                    return Some(choices);
+                }
+                c => choices.last_mut()?.push(c),
+            }
+            self.i += 1;
+        }
+    }
+
+    /// `regex/format/options` up to the closing `}`.
+    fn skip_transform(&mut self) -> Option<()> {
+        let mut slashes = 0;
+        while slashes < 2 {
+            match self.peek()? {
+                '\\' => self.i += 1,
+                '/' => slashes += 1,
+                _ => {}
+            }
+            self.i += 1;
+        }
+        while self.peek()? != '}' {
+            self.i += 1;
+        }
+        Some(())
+    }
+}
+
+struct Expander<'a> {
+    variable: &'a dyn Fn(&str) -> Option<String>,
+    /// The text of each tab stop, from its first placeholder.
+    defaults: BTreeMap<u32, String>,
+    text: String,
+    len: usize,
+    stops: BTreeMap<u32, TabStop>,
+}
+
+impl Expander<'_> {
+    fn push(&mut self, text: &str) {
+        self.text.push_str(text);
+        self.len += text.chars().count();
+    }
+
+    /// Note the text of every placeholder, for the mirrors that come before it.
+    fn collect_defaults(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            match node {
+                Node::Text(_) => {}
+                Node::Stop { number, children, choices } => {
+                    self.collect_defaults(children);
+                    if !self.defaults.contains_key(number) && (!children.is_empty() || !choices.is_empty()) {
+                        let text = self.plain(children, choices);
+                        self.defaults.insert(*number, text);
+                    }
+                }
+                Node::Variable { default, .. } => self.collect_defaults(default.as_deref().unwrap_or(&[])),
+            }
+        }
+    }
+
+    fn plain(&self, children: &[Node], choices: &[String]) -> String {
+        if let Some(first) = choices.first() {
+            return first.clone();
+        }
+        children.iter().map(|node| match node {
+            Node::Text(text) => text.clone(),
+            Node::Stop { number, children, choices } if children.is_empty() && choices.is_empty() => {
+                self.defaults.get(number).cloned().unwrap_or_default()
+            }
+            Node::Stop { children, choices, .. } => self.plain(children, choices),
+            Node::Variable { name, default } => (self.variable)(name)
+                .or_else(|| default.as_ref().map(|default| self.plain(default, &[])))
+                .unwrap_or_else(|| name.clone()),
+        }).collect()
+    }
+
+    fn expand(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => self.push(text),
+                Node::Stop { number, children, choices } => {
+                    let start = self.len;
+                    if !children.is_empty() {
+                        self.expand(children);
+                    }
+                    else {
+                        let text = choices.first().or(self.defaults.get(number)).cloned().unwrap_or_default();
+                        self.push(&text);
+                    }
+                    let stop = self.stops.entry(*number).or_insert_with(|| TabStop { number: *number, ranges: vec![], choices: vec![] });
+                    stop.ranges.push((start, self.len));
+                    if stop.choices.is_empty() {
+                        stop.choices = choices.clone();
+                    }
+                }
+                Node::Variable { name, default } => match ((self.variable)(name), default) {
+                    (Some(value), _) => self.push(&value),
+                    (None, Some(default)) => self.expand(default),
+                    (None, None) => self.push(name),
+                },
+            }
+        }
+    }
+}
+
+/// Expand `snippet`, taking the values of variables from `variable`.
+/// Unknown variables without a default are replaced by their name.
+pub fn expand(snippet: &str, variable: &dyn Fn(&str) -> Option<String>) -> Snippet {
+    let nodes = Parser { chars: snippet.chars().collect(), i: 0 }.nodes(false);
+    let mut expander = Expander { variable, defaults: BTreeMap::new(), text: String::new(), len: 0, stops: BTreeMap::new() };
+    expander.collect_defaults(&nodes);
+    expander.expand(&nodes);
+    let end = expander.len;
+    let mut stops = expander.stops;
+    let last = stops.remove(&0).unwrap_or(TabStop { number: 0, ranges: vec![(end, end)], choices: vec![] });
+    let mut stops = stops.into_values().collect::<Vec<_>>();
+    stops.push(last);
+    Snippet { text: expander.text, stops }
+}
+
+/// The variables of the text editing specification that make sense here.
+pub fn variable(name: &str, filename: Option<&str>, rope: &Rope, cursor: CursorPos) -> Option<String> {
+    let path = filename.map(std::path::Path::new);
+    let line = rope.line(cursor.0).to_string();
+    let line = line.trim_end_matches(['\n', '\r']);
+    let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+    Some(match name {
+        "TM_SELECTED_TEXT" => String::new(),
+        "TM_CURRENT_LINE" => line.to_owned(),
+        "TM_CURRENT_WORD" => {
+            let chars = line.chars().collect::<Vec<_>>();
+            let at = cursor.1.min(chars.len());
+            let start = at - chars[..at].iter().rev().take_while(|c| is_word(c)).count();
+            let end = at + chars[at..].iter().take_while(|c| is_word(c)).count();
+            chars[start..end].iter().collect()
+        }
+        "TM_LINE_INDEX" => cursor.0.to_string(),
+        "TM_LINE_NUMBER" => (cursor.0 + 1).to_string(),
+        "TM_FILENAME" => path?.file_name()?.to_str()?.to_owned(),
+        "TM_FILENAME_BASE" => path?.file_stem()?.to_str()?.to_owned(),
+        "TM_DIRECTORY" => path?.parent()?.to_str()?.to_owned(),
+        "TM_FILEPATH" => filename?.to_owned(),
+        _ => return None,
+    })
+}
+
+/// Where the char `offset` of `text` is when `text` is inserted at `start`.
+fn position(start: CursorPos, text: &str, offset: usize) -> CursorPos {
+    let prefix = text.chars().take(offset).collect::<String>();
+    end_of_insert(start, &prefix)
+}
+
+/// A snippet being filled in: where its tab stops are in the buffer, kept
+/// up to date as the text is edited.
+pub struct SnippetSession {
+    stops: Vec<Vec<(CursorPos, CursorPos)>>,
+    choices: Vec<Vec<String>>,
+    current: usize,
+    /// Whether the current stop still holds its placeholder, which typing replaces.
+    fresh: bool,
+}
+
+impl SnippetSession {
+    /// The session of `snippet` inserted at `start`, at its first tab stop.
+    pub fn new(snippet: &Snippet, start: CursorPos) -> Self {
+        let stops = snippet.stops.iter()
+            .map(|stop| stop.ranges.iter().map(|&(s, e)| (position(start, &snippet.text, s), position(start, &snippet.text, e))).collect())
+            .collect();
+        let choices = snippet.stops.iter().map(|stop| stop.choices.clone()).collect();
+        Self { stops, choices, current: 0, fresh: true }
+    }
+
+    /// The ranges of the current tab stop; the cursor is placed in the first.
+    pub fn current(&self) -> &[(CursorPos, CursorPos)] {
+        &self.stops[self.current]
+    }
+
+    pub fn choices(&self) -> &[String] {
+        &self.choices[self.current]
+    }
+
+    /// Whether the current stop is the final one, `$0`.
+    pub fn is_last(&self) -> bool {
+        self.current + 1 == self.stops.len()
+    }
+
+    /// Move to the next tab stop, or the previous one. Returns `false`
+    /// when there is none.
+    pub fn jump(&mut self, forward: bool) -> bool {
+        let next = if forward { self.current + 1 } else { self.current.wrapping_sub(1) };
+        if next >= self.stops.len() {
+            return false;
+        }
+        self.current = next;
+        self.fresh = true;
+        true
+    }
+
+    /// Whether `pos` is in or at the edge of a range of the current stop.
+    pub fn contains(&self, pos: CursorPos) -> bool {
+        self.current().iter().any(|&(start, end)| start <= pos && pos <= end)
+    }
+
+    /// The placeholder the next edit replaces, taken only once after a jump.
+    /// Stops nested in it go away with it.
+    pub fn take_placeholder(&mut self) -> Option<(CursorPos, CursorPos)> {
+        if !std::mem::take(&mut self.fresh) {
+            return None;
+        }
+        let (start, end) = self.current()[0];
+        if start == end {
+            return None;
+        }
+        let nested = |k: usize, ranges: &Vec<(CursorPos, CursorPos)>| {
+            k != self.current && k + 1 != self.stops.len() && ranges.iter().all(|&(s, e)| start <= s && e <= end)
+        };
+        let keep = self.stops.iter().enumerate().map(|(k, ranges)| !nested(k, ranges)).collect::<Vec<_>>();
+        self.current -= keep[..self.current].iter().filter(|&&kept| !kept).count();
+        let mut kept = keep.iter();
+        self.stops.retain(|_| *kept.next().unwrap());
+        let mut kept = keep.iter();
+        self.choices.retain(|_| *kept.next().unwrap());
+        Some((start, end))
+    }
+
+    /// Follow `edit` of the text. The current stop grows with edits within
+    /// it; the other ranges move with the text around them.
+    pub fn edited(&mut self, edit: &Edit) {
+        let (start, end, _) = *edit;
+        for (k, ranges) in self.stops.iter_mut().enumerate() {
+            for range in ranges.iter_mut() {
+                let within = k == self.current && range.0 <= start && end <= range.1;
+                let s = if within { range.0 } else { transform(range.0, edit) };
+                *range = (s, transform(range.1, edit).max(s));
+            }
+        }
+    }
+
+    /// The edits that copy `text`, now in the first range of the current
+    /// stop, to its mirrors.
+    pub fn mirror_edits(&self, text: &str, rope: &Rope) -> Vec<Edit> {
+        let text_of = |(start, end): (CursorPos, CursorPos)| {
+            let idx = |pos: CursorPos| rope.line_to_char(pos.0) + pos.1;
+            rope.slice(idx(start)..idx(end)).to_string()
+        };
+        self.current()[1..].iter()
+            .filter(|&&range| text_of(range) != text)
+            .map(|&(start, end)| (start, end, text.to_owned()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ropey::Rope;
+
+    use super::{expand, Snippet, SnippetSession, TabStop};
+
+    fn plain(snippet: &str) -> Snippet {
+        expand(snippet, &|name| (name == "TM_FILENAME").then(|| "a.cpp".to_owned()))
+    }
+
+    fn stop(number: u32, ranges: &[(usize, usize)]) -> TabStop {
+        TabStop { number, ranges: ranges.to_vec(), choices: vec![] }
+    }
+
+    #[test]
+    fn tab_stops_and_placeholders() {
+        let snippet = plain("foo(${1:int a}, ${2:b})$0;");
+        assert_eq!(snippet.text, "foo(int a, b);");
+        assert_eq!(snippet.stops, vec![stop(1, &[(4, 9)]), stop(2, &[(11, 12)]), stop(0, &[(13, 13)])]);
+        // without `$0` the snippet ends at the end of its text
+        let snippet = plain("$2 = $1");
+        assert_eq!(snippet.text, " = ");
+        assert_eq!(snippet.stops, vec![stop(1, &[(3, 3)]), stop(2, &[(0, 0)]), stop(0, &[(3, 3)])]);
+    }
+
+    #[test]
+    fn nesting_and_mirrors() {
+        let snippet = plain("$1 ${1:x${2:y}} ${2}");
+        assert_eq!(snippet.text, "xy xy y");
+        assert_eq!(snippet.stops, vec![stop(1, &[(0, 2), (3, 5)]), stop(2, &[(4, 5), (6, 7)]), stop(0, &[(7, 7)])]);
+    }
+
+    #[test]
+    fn choices_variables_and_escapes() {
+        let snippet = plain("${1|one,t\\,wo|} $TM_FILENAME ${UNKNOWN} ${NONE:def} \\$1 $ ${X/a/b/g}");
+        assert_eq!(snippet.text, "one a.cpp UNKNOWN def $1 $ X");
+        assert_eq!(snippet.stops[0], TabStop { number: 1, ranges: vec![(0, 3)], choices: vec!["one".to_owned(), "t,wo".to_owned()] });
+        // malformed markup stays as it is
+        assert_eq!(plain("${1:a").text, "${1:a");
+    }
+
+    #[test]
+    fn session_follows_edits() {
+        let snippet = plain("f(${1:a}, ${2:b}) $1");
+        let mut session = SnippetSession::new(&snippet, (3, 4));
+        assert_eq!(session.current(), &[((3, 6), (3, 7)), ((3, 12), (3, 13))]);
+        // typing replaces the placeholder and grows the stop
+        let placeholder = session.take_placeholder().unwrap();
+        session.edited(&(placeholder.0, placeholder.1, String::new()));
+        session.edited(&((3, 6), (3, 6), "xy".to_owned()));
+        assert_eq!(session.current()[0], ((3, 6), (3, 8)));
+        let rope = Rope::from_str("\n\n\n    f(xy, b) a\n");
+        let mirrors = session.mirror_edits("xy", &rope);
+        assert_eq!(mirrors, vec![((3, 13), (3, 14), "xy".to_owned())]);
+        session.edited(&mirrors[0]);
+        assert_eq!(session.current(), &[((3, 6), (3, 8)), ((3, 13), (3, 15))]);
+        assert!(session.jump(true));
+        assert_eq!(session.current(), &[((3, 10), (3, 11))]);
+        assert!(session.jump(true));
+        assert!(session.is_last());
+        assert!(!session.jump(true));
+    }
+}
@@ -1,49 +1,332 @@
-use std::{fs::File, io::BufReader, sync::Arc};
+use std::{fs::File, io::{BufReader, BufWriter, Write}, path::Path, sync::Arc};
+use anyhow::{anyhow, Context};
 use ropey::Rope;
-use crate::lsp::{client::{LspClient, ResponseReceiver, path_to_uri}, method::{completion::{CompletionFetch, CompletionParam}, didchange::DidChangeNotifyBuilder, hover::{HoverFetch, HoverParam}}};
+use crate::language::{self, Language};
+use crate::lsp::{capabilities::Feature, client::{LspClient, path_to_uri}, position::{PositionEncoding, Positions}, method::{completion::{CompletionFetch, CompletionParam, CompletionResolveFetch}, didchange::{self, PendingChanges}, goto::{GotoFetch, GotoKind, GotoParam}, hover::{HoverFetch, HoverParam}, references::{ReferencesFetch, ReferencesParam}, rename::{PrepareRenameFetch, PrepareRenameParam, RenameFetch, RenameParam}, code_action::{CodeActionFetch, CodeActionParam}, formatting::{FormattingFetch, FormattingParam}, signature_help::{self, SignatureHelpFetch, SignatureHelpParam}, save::{notify_did_save, notify_will_save}}};
 
-use super::{ Buffer, CursorPos };
+use lsp_types::Diagnostic;
+
+use super::{ Buffer, BufferOptions, CursorPos, diagnostics, edit::{self, Edit}, history::{Change, History, UndoTarget}, undofile };
 
 pub struct TextBuffer {
-    filename: String,
+    filename: Option<String>,
+    language: Option<&'static Language>,
     rope: Rope,
     lsp_client: Option<Arc<LspClient>>,
     version: i32,
+    /// The history state that was last written to or read from the file.
+    saved_state: usize,
+    /// Changes the server has not been told about yet.
+    changes: PendingChanges,
+    options: BufferOptions,
+    history: History,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl TextBuffer {
+    /// A file that does not exist yet opens as an empty buffer.
     pub fn open(filename: &str) -> anyhow::Result<Self> {
-        Ok(
-            Self {
-                filename: filename.to_owned(),
-                rope: Rope::from_reader(BufReader::new(File::open(filename)?))?,
-                lsp_client: None,
-                version: 0,
-            }
-        )
+        let rope = match File::open(filename) {
+            Ok(file) => Rope::from_reader(BufReader::new(file))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Rope::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut buffer = Self::from_rope(rope);
+        buffer.set_filename(filename);
+        Ok(buffer)
+    }
+
+    /// Pick up the history stored by an earlier session once `undofile` is
+    /// set, unless this session has already changed the buffer.
+    pub fn load_undofile(&mut self) {
+        if !self.options.undofile || self.history.states() > 1 || self.history.has_pending() {
+            return;
+        }
+        let Some(filename) = self.filename.as_deref() else {
+            return;
+        };
+        if let Some(history) = undofile::load(Path::new(filename), &self.rope) {
+            self.saved_state = history.current_state();
+            self.history = history;
+        }
+    }
+
+    /// Read the file again, dropping unsaved changes. The new contents are
+    /// one more undo step, so the changes can still be brought back.
+    pub fn reload(&mut self) -> anyhow::Result<()> {
+        let filename = self.filename.clone().ok_or_else(|| anyhow!("No file name"))?;
+        let text = std::fs::read_to_string(&filename)?;
+        if self.rope != text.as_str() {
+            let end = self.end_pos();
+            self.apply_edits(&[((0, 0), end, text)], (0, 0))?;
+        }
+        self.history.flush();
+        self.saved_state = self.history.current_state();
+        Ok(())
+    }
+
+    /// The position after the last character.
+    pub fn end_pos(&self) -> CursorPos {
+        let last = self.rope.len_lines() - 1;
+        (last, self.rope.line(last).len_chars())
     }
 
-    pub async fn open_with_lsp(filename: &str, lsp_client: Arc<LspClient>) -> anyhow::Result<Self> {
-        let text = String::from_utf8_lossy(&std::fs::read(filename)?).to_string();
+    /// A buffer without a file name, e.g. text read from stdin.
+    pub fn from_text(text: &str) -> Self {
+        Self::from_rope(Rope::from_str(text))
+    }
+
+    fn from_rope(rope: Rope) -> Self {
+        Self {
+            filename: None,
+            language: language::detect(None, &first_line(&rope)),
+            rope,
+            lsp_client: None,
+            version: 0,
+            saved_state: 0,
+            changes: PendingChanges::default(),
+            options: BufferOptions::default(),
+            history: History::default(),
+            diagnostics: vec![],
+        }
+    }
 
-        // TODO: cpp to actual language id
+    /// Open the current contents on `lsp_client`, replacing any client attached before.
+    /// Buffers without a file name or a known language are never attached.
+    pub fn attach_lsp(&mut self, lsp_client: Arc<LspClient>) -> anyhow::Result<()> {
+        let (Some(ref filename), Some(language)) = (&self.filename, self.language) else {
+            return Ok(());
+        };
         lsp_client.notify::<lsp_types::notification::DidOpenTextDocument>(
             lsp_types::DidOpenTextDocumentParams {
-                text_document: lsp_types::TextDocumentItem { uri: path_to_uri(filename)?, language_id: "cpp".to_owned(), version: 0, text: text.clone() }
-            }).await?;
+                text_document: lsp_types::TextDocumentItem { uri: path_to_uri(filename)?, language_id: language.language_id.to_owned(), version: self.version, text: self.rope.to_string() }
+            })?;
+        self.changes = PendingChanges::new(didchange::sync_kind(lsp_client.server_capabilities()));
+        self.lsp_client = Some(lsp_client);
+        Ok(())
+    }
 
-        Ok(
-            Self {
-                filename: filename.to_owned(),
-                rope: Rope::from_reader(BufReader::new(File::open(filename)?))?,
-                lsp_client: Some(lsp_client),
-                version: 0,
-            }
-        )
+    /// Send `didClose` and hand back the client, e.g. before the buffer is dropped.
+    pub fn detach_lsp(&mut self) -> anyhow::Result<Option<Arc<LspClient>>> {
+        if let Some((client, filename)) = self.lsp() {
+            client.notify::<lsp_types::notification::DidCloseTextDocument>(
+                lsp_types::DidCloseTextDocumentParams {
+                    text_document: lsp_types::TextDocumentIdentifier { uri: path_to_uri(filename)? }
+                })?;
+        }
+        self.changes.clear();
+        self.diagnostics.clear();
+        Ok(self.lsp_client.take())
+    }
+
+    /// Forget a client whose server is gone without telling it anything.
+    pub fn forget_lsp(&mut self) {
+        self.lsp_client = None;
+        self.changes.clear();
+        self.diagnostics.clear();
+    }
+
+    /// Replace the diagnostics unless they were computed for another version
+    /// of the text. Returns whether they were taken.
+    pub fn set_diagnostics(&mut self, version: Option<i32>, mut diagnostics: Vec<Diagnostic>) -> bool {
+        if version.is_some_and(|version| version != self.version) {
+            return false;
+        }
+        // kept counting chars, like everything else in the buffer
+        let positions = self.positions();
+        for d in diagnostics.iter_mut() {
+            let (start, end) = (positions.from_lsp(d.range.start), positions.from_lsp(d.range.end));
+            d.range = lsp_types::Range::new(lsp_types::Position::new(start.0 as u32, start.1 as u32), lsp_types::Position::new(end.0 as u32, end.1 as u32));
+        }
+        diagnostics::sort(&mut diagnostics);
+        self.diagnostics = diagnostics;
+        true
+    }
+
+    pub fn lsp_client(&self) -> Option<&Arc<LspClient>> {
+        self.lsp_client.as_ref()
+    }
+
+    /// Name a buffer that has none yet, e.g. on `:w file`. The language is detected again.
+    pub fn set_filename(&mut self, filename: &str) {
+        self.language = language::detect(Some(Path::new(filename)), &first_line(&self.rope));
+        self.filename = Some(filename.to_owned());
+    }
+
+    pub fn language(&self) -> Option<&'static Language> {
+        self.language
+    }
+
+    /// The attached client together with the file name it knows the buffer by.
+    fn lsp(&self) -> Option<(&LspClient, &str)> {
+        Some((self.lsp_client.as_deref()?, self.filename.as_deref()?))
+    }
+
+    /// Converts positions in the text as it is now to and from those of the server.
+    pub fn positions(&self) -> Positions {
+        let encoding = self.lsp_client.as_ref().map_or_else(PositionEncoding::default, |client| client.position_encoding());
+        Positions::new(self.rope.clone(), encoding)
+    }
+
+    /// Send the changes not sent yet, so that the server answers from the
+    /// text as it is now.
+    pub fn flush_changes(&mut self) -> anyhow::Result<()> {
+        if let (Some(client), Some(filename)) = (&self.lsp_client, &self.filename) {
+            self.changes.flush(client, filename, self.version, || self.rope.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Whether the changes not sent yet waited long enough for more.
+    pub fn changes_due(&self, now: std::time::Instant) -> bool {
+        self.changes.is_due(now)
+    }
+
+    /// Like `lsp`, but only when the server supports `feature`.
+    fn lsp_for(&self, feature: Feature) -> Option<(&LspClient, &str)> {
+        self.lsp().filter(|(client, _)| client.supports(feature))
+    }
+
+    /// Send the changes a `feature` request about to be sent depends on.
+    fn flush_for(&mut self, feature: Feature) -> anyhow::Result<()> {
+        if self.supports(feature) {
+            self.flush_changes()?;
+        }
+        Ok(())
+    }
+
+    /// Whether a `feature` request can be sent for this buffer.
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.lsp_for(feature).is_some()
+    }
+
+    /// The identifier around `cursor`, as the range to replace.
+    pub fn word_at(&self, cursor: CursorPos) -> Option<(CursorPos, CursorPos)> {
+        let line = self.rope.get_line(cursor.0)?.chars().collect::<Vec<_>>();
+        let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+        if !line.get(cursor.1).is_some_and(is_word) {
+            return None;
+        }
+        let start = line[..cursor.1].iter().rposition(|c| !is_word(c)).map_or(0, |i| i + 1);
+        let end = line[cursor.1..].iter().position(|c| !is_word(c)).map_or(line.len(), |i| cursor.1 + i);
+        Some(((cursor.0, start), (cursor.0, end)))
+    }
+
+    pub fn text_range(&self, start: CursorPos, end: CursorPos) -> String {
+        let (start, end) = (self.pos_to_char(self.clamp(start)), self.pos_to_char(self.clamp(end)));
+        self.rope.slice(start..end.max(start)).to_string()
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Write the contents to another file without changing the buffer's name.
+    pub fn write_copy<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        write_atomic(path.as_ref(), &self.rope)
+    }
+}
+
+/// Enough of the first line to recognise a `#!` line.
+fn first_line(rope: &Rope) -> String {
+    rope.line(0).chars().take(256).collect()
+}
+
+/// Write `rope` next to `path` and rename it over the original, so a crash
+/// mid-write never leaves a truncated file behind.
+fn write_atomic(path: &Path, rope: &Rope) -> anyhow::Result<()> {
+    // follow symlinks so that the link itself is kept
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => std::path::PathBuf::from("."),
+    };
+    let name = path.file_name().context("no file name")?.to_string_lossy();
+    let tmp = dir.join(format!(".{}.{}.tmp", name, std::process::id()));
+    let permissions = std::fs::metadata(&path).ok().map(|meta| meta.permissions());
+
+    let write = || -> anyhow::Result<()> {
+        let file = File::create(&tmp)?;
+        let mut writer = BufWriter::new(file);
+        rope.write_to(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        if let Some(permissions) = permissions {
+            std::fs::set_permissions(&tmp, permissions)?;
+        }
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    };
+    write().inspect_err(|_| { let _ = std::fs::remove_file(&tmp); })
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+impl TextBuffer {
+    fn pos_to_char(&self, pos: CursorPos) -> usize {
+        self.rope.line_to_char(pos.0) + pos.1
+    }
+
+    /// Servers may point past the end of a line or of the text; take the nearest position.
+    fn clamp(&self, pos: CursorPos) -> CursorPos {
+        if pos.0 >= self.rope.len_lines() {
+            return self.char_to_pos(self.rope.len_chars());
+        }
+        let line = self.rope.line(pos.0).to_string();
+        let len = line.trim_end_matches(['\n', '\r']).chars().count();
+        (pos.0, pos.1.min(len))
+    }
+
+    fn char_to_pos(&self, idx: usize) -> CursorPos {
+        let line = self.rope.char_to_line(idx);
+        (line, idx - self.rope.line_to_char(line))
+    }
+
+    /// Change the rope only; the callers record the change and tell the server.
+    fn splice(&mut self, start: CursorPos, end: CursorPos, text: &str) -> (Change, CursorPos) {
+        let sdx = self.pos_to_char(start);
+        let edx = self.pos_to_char(end);
+        let removed = self.rope.slice(sdx..edx).to_string();
+        self.rope.remove(sdx..edx);
+        self.rope.insert(sdx, text);
+        let after = self.char_to_pos(sdx + text.chars().count());
+        (Change { at: sdx, removed, inserted: text.to_owned() }, after)
+    }
+
+    /// Every edit goes through here or `apply_edits` so that the undo
+    /// history and the language server see the same changes as the rope.
+    fn replace(&mut self, start: CursorPos, end: CursorPos, text: &str, cursor: CursorPos) -> anyhow::Result<CursorPos> {
+        // the range as the server counts it, in the text before the change
+        let range = self.positions().range_to_lsp(start, end);
+        let (change, after) = self.splice(start, end, text);
+        self.version += 1;
+        self.changes.edit(range, text.to_owned());
+        self.history.record(change, cursor, after);
+        Ok(after)
+    }
+
+    /// Apply changes from the history as a single document version.
+    fn apply_changes(&mut self, changes: &[Change]) -> anyhow::Result<()> {
+        self.version += 1;
+        let mut edits = vec![];
+        for change in changes {
+            let start = self.char_to_pos(change.at);
+            let end_idx = change.at + change.removed.chars().count();
+            let end = self.char_to_pos(end_idx);
+            edits.push((self.positions().range_to_lsp(start, end), change.inserted.clone()));
+            self.rope.remove(change.at..end_idx);
+            self.rope.insert(change.at, &change.inserted);
+        }
+        for (range, text) in edits {
+            self.changes.edit(range, text);
+        }
+        Ok(())
     }
 }
 
 impl Buffer for TextBuffer {
+    fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
     fn rope_clone(&self) -> Rope {
         self.rope.clone()
     }
@@ -53,99 +336,248 @@ impl Buffer for TextBuffer {
     fn len_line_chars(&self, i: usize) -> usize {
         self.rope.line(i).len_chars()
     }
-    async fn insert_char(&mut self, mut cursor: CursorPos, c: char) -> anyhow::Result<CursorPos> {
+    fn insert_char(&mut self, cursor: CursorPos, c: char) -> anyhow::Result<CursorPos> {
+        self.replace(cursor, cursor, &c.to_string(), cursor)
+    }
+    fn newline(&mut self, cursor: CursorPos) -> anyhow::Result<CursorPos> {
+        // TODO: インデントがここに入るかもしれない
+        // どう実装すればいい？
+        self.replace(cursor, cursor, "\n", cursor)
+    }
+    fn backspace(&mut self, cursor: CursorPos) -> anyhow::Result<CursorPos> {
+        if cursor.1 == 0 {
+            if cursor.0 > 0 {
+                let start = (cursor.0 - 1, self.rope.line(cursor.0 - 1).len_chars() - 1);
+                return self.replace(start, cursor, "", cursor).map(|_| start);
+            }
+            Ok(cursor)
+        }
+        else {
+            let start = (cursor.0, cursor.1 - 1);
+            self.replace(start, cursor, "", cursor).map(|_| start)
+        }
+    }
+
+    fn edit(&mut self, start: CursorPos, end: CursorPos, text: &str) -> anyhow::Result<CursorPos> {
+        self.replace(start, end, text, start)
+    }
 
-        if let Some(client) = &self.lsp_client {
-            self.version += 1;
-            DidChangeNotifyBuilder::new(&self.filename, self.version)?
-                .insert(cursor, c.to_string())
-                .notify(&client).await?;
+    fn apply_edits(&mut self, edits: &[Edit], cursor: CursorPos) -> anyhow::Result<CursorPos> {
+        if edits.is_empty() {
+            return Ok(cursor);
+        }
+        // from the end backwards, so that every range is still valid when
+        // its turn comes, both here and on the server
+        let edits = edit::apply_order(edits).into_iter()
+            .map(|(start, end, text)| {
+                let (start, end) = (self.clamp(start), self.clamp(end));
+                (start, end.max(start), text)
+            })
+            .collect::<Vec<_>>();
+        let mut moved = cursor;
+        let mut changes = vec![];
+        let mut ranges = vec![];
+        for e in edits.iter() {
+            ranges.push(self.positions().range_to_lsp(e.0, e.1));
+            let (change, _) = self.splice(e.0, e.1, &e.2);
+            changes.push(change);
+            moved = edit::transform(moved, e);
+        }
+        self.version += 1;
+        self.history.begin_group(cursor);
+        for change in changes {
+            self.history.record(change, cursor, moved);
         }
+        self.history.end_group(moved);
+        for (range, (_, _, text)) in ranges.into_iter().zip(edits) {
+            self.changes.edit(range, text);
+        }
+        Ok(moved)
+    }
 
-        let idx = self.rope.line_to_char(cursor.0);
-        self.rope.insert(idx + cursor.1, &c.to_string());
-        cursor.1 += 1;
+    fn begin_undo_group(&mut self, cursor: CursorPos) {
+        self.history.begin_group(cursor);
+    }
+    fn end_undo_group(&mut self, cursor: CursorPos) {
+        self.history.end_group(cursor);
+    }
 
-        Ok(cursor)
+    fn undo(&mut self) -> anyhow::Result<Option<CursorPos>> {
+        match self.history.pop_undo() {
+            Some(group) => {
+                self.apply_changes(&group.inverse())?;
+                Ok(Some(group.cursor_before))
+            }
+            None => Ok(None),
+        }
     }
-    async fn newline(&mut self, mut cursor: CursorPos) -> anyhow::Result<CursorPos> {
-        // TODO: インデントがここに入るかもしれない
-        // どう実装すればいい？
-        
-        if let Some(client) = &self.lsp_client {
-            self.version += 1;
-            DidChangeNotifyBuilder::new(&self.filename, self.version)?
-                .insert(cursor, '\n'.to_string())
-                .notify(&client).await?;
+
+    fn redo(&mut self) -> anyhow::Result<Option<CursorPos>> {
+        match self.history.pop_redo() {
+            Some(group) => {
+                self.apply_changes(&group.changes)?;
+                Ok(Some(group.cursor_after))
+            }
+            None => Ok(None),
         }
+    }
 
-        let idx = self.rope.line_to_char(cursor.0);
-        self.rope.insert(idx + cursor.1, "\n");
-        cursor.0 += 1;
-        cursor.1 = 0;
-        Ok(cursor)
+    fn undo_travel(&mut self, target: UndoTarget) -> anyhow::Result<Option<CursorPos>> {
+        match self.history.travel(target) {
+            Some(travel) => {
+                self.apply_changes(&travel.changes)?;
+                Ok(Some(travel.cursor))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn options(&self) -> &BufferOptions {
+        &self.options
+    }
+    fn options_mut(&mut self) -> &mut BufferOptions {
+        &mut self.options
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.history.has_pending() || self.history.current_state() != self.saved_state
+    }
+
+    fn version(&self) -> i32 {
+        self.version
+    }
+
+    fn save(&mut self) -> anyhow::Result<()> {
+        let filename = self.filename.clone().ok_or_else(|| anyhow!("No file name"))?;
+        self.flush_changes()?;
+        if let Some((client, filename)) = self.lsp() {
+            notify_will_save(client, filename)?;
+        }
+        write_atomic(Path::new(&filename), &self.rope)?;
+        self.history.flush();
+        self.saved_state = self.history.current_state();
+        if self.options.undofile {
+            undofile::store(Path::new(&filename), &self.rope, &self.history)?;
+        }
+        if let Some((client, filename)) = self.lsp() {
+            notify_did_save(client, filename, || self.rope.to_string())?;
+        }
+        Ok(())
     }
-    async fn backspace(&mut self, mut cursor: CursorPos) -> anyhow::Result<CursorPos> {
-        if cursor.1 == 0 {
-            if cursor.0 > 0 {
-                let end = cursor.clone();
-                cursor.1 = self.rope.line(cursor.0 - 1).len_chars() - 1;
-                let idx = self.rope.line_to_char(cursor.0);
-                self.rope.remove(idx - 1..idx);
-                cursor.0 -= 1;
 
-                if let Some(client) = &self.lsp_client {
-                    self.version += 1;
-                    DidChangeNotifyBuilder::new(&self.filename, self.version)?
-                        .delete(cursor, end)
-                        .notify(&client).await?;
-                }
+    fn hover(&mut self, cursor: CursorPos) -> anyhow::Result<Option<HoverFetch>> {
+        self.flush_for(Feature::Hover)?;
+        match self.lsp_for(Feature::Hover) {
+            Some((lsp_client, filename)) => {
+                let param = HoverParam::new(filename, cursor)?;
+                Ok(Some(HoverFetch::in_text(lsp_client, self.rope.clone(), self.version, param)?))
+            }
+            None => {
+                Ok(None)
             }
         }
-        else {
-            let idx = self.rope.line_to_char(cursor.0);
-            self.rope.remove(idx + cursor.1 - 1..idx + cursor.1);
-            cursor.1 -= 1;
+    }
+
+    fn completion(&mut self, cursor: CursorPos) -> anyhow::Result<Option<CompletionFetch>> {
+        self.flush_for(Feature::Completion)?;
+        match self.lsp_for(Feature::Completion) {
+            Some((lsp_client, filename)) => {
+                let param = CompletionParam::new(filename, cursor)?;
+                Ok(Some(CompletionFetch::in_text(lsp_client, self.rope.clone(), self.version, param)?))
+            }
+            None => {
+                Ok(None)
+            }
+        }
+    }
+
+    fn resolve_completion(&self, item: lsp_types::CompletionItem) -> anyhow::Result<Option<CompletionResolveFetch>> {
+        match self.lsp_for(Feature::CompletionResolve) {
+            Some((lsp_client, _)) => {
+                Ok(Some(CompletionResolveFetch::new(lsp_client, item)?))
+            }
+            None => {
+                Ok(None)
+            }
+        }
+    }
+
+    fn signature_help(&mut self, cursor: CursorPos, typed: Option<char>, active: Option<&lsp_types::SignatureHelp>) -> anyhow::Result<Option<SignatureHelpFetch>> {
+        // most keys do not trigger it, and need not wait for the server to hear about them
+        let Some(context) = self.lsp_for(Feature::SignatureHelp).and_then(|(lsp_client, _)| signature_help::context(lsp_client, typed, active)) else {
+            return Ok(None);
+        };
+        self.flush_for(Feature::SignatureHelp)?;
+        match self.lsp_for(Feature::SignatureHelp) {
+            Some((lsp_client, filename)) => {
+                let param = SignatureHelpParam::new(filename, cursor, context)?;
+                Ok(Some(SignatureHelpFetch::in_text(lsp_client, self.rope.clone(), self.version, param)?))
+            }
+            None => {
+                Ok(None)
+            }
+        }
+    }
 
+    fn goto(&mut self, kind: GotoKind, cursor: CursorPos) -> anyhow::Result<Option<GotoFetch>> {
+        self.flush_for(kind.feature())?;
+        match self.lsp_for(kind.feature()) {
+            Some((lsp_client, filename)) => {
+                let param = GotoParam::new(filename, cursor)?;
+                Ok(Some(GotoFetch::new(lsp_client, kind, self.rope.clone(), self.version, param)?))
+            }
+            None => {
+                Ok(None)
+            }
+        }
+    }
 
-            if let Some(client) = &self.lsp_client {
-                self.version += 1;
-                DidChangeNotifyBuilder::new(&self.filename, self.version)?
-                    .delete(cursor, (cursor.0, cursor.1 + 1))
-                    .notify(&client).await?;
+    fn references(&mut self, cursor: CursorPos) -> anyhow::Result<Option<ReferencesFetch>> {
+        self.flush_for(Feature::References)?;
+        match self.lsp_for(Feature::References) {
+            Some((lsp_client, filename)) => {
+                let param = ReferencesParam::new(filename, cursor)?;
+                Ok(Some(ReferencesFetch::in_text(lsp_client, self.rope.clone(), self.version, param)?))
+            }
+            None => {
+                Ok(None)
             }
         }
-        Ok(cursor)
     }
 
-    async fn edit(&mut self, mut start: CursorPos, end: CursorPos, text: &str) -> anyhow::Result<CursorPos> {
-        let sdx = self.rope.line_to_char(start.0);
-        let edx = self.rope.line_to_char(end.0);
-        self.rope.remove(sdx + start.1 .. edx + end.1);
-        self.rope.insert(sdx + start.1, text);
-        if let Some(client) = self.lsp_client.as_ref() {
-            self.version += 1;
-            DidChangeNotifyBuilder::new(&self.filename, self.version)?
-                .edit(start, end, text.to_owned())
-                .notify(client).await?;
+    fn prepare_rename(&mut self, cursor: CursorPos) -> anyhow::Result<Option<PrepareRenameFetch>> {
+        self.flush_for(Feature::PrepareRename)?;
+        match self.lsp_for(Feature::PrepareRename) {
+            Some((lsp_client, filename)) => {
+                let param = PrepareRenameParam::new(filename, cursor)?;
+                Ok(Some(PrepareRenameFetch::in_text(lsp_client, self.rope.clone(), self.version, param)?))
+            }
+            None => {
+                Ok(None)
+            }
         }
-        for c in text.chars() {
-            if c == '\n' {
-                start.0 += 1;
-                start.1 = 0;
+    }
+
+    fn rename(&mut self, cursor: CursorPos, new_name: &str) -> anyhow::Result<Option<RenameFetch>> {
+        self.flush_for(Feature::Rename)?;
+        match self.lsp_for(Feature::Rename) {
+            Some((lsp_client, filename)) => {
+                let param = RenameParam::new(filename, cursor, new_name)?;
+                Ok(Some(RenameFetch::in_text(lsp_client, self.rope.clone(), self.version, param)?))
             }
-            else {
-                start.1 += 1;
+            None => {
+                Ok(None)
             }
         }
-        Ok(start)
     }
 
-    async fn hover(&self, cursor: CursorPos) -> anyhow::Result<Option<HoverFetch>> {
-        match self.lsp_client {
-            Some(ref lsp_client) => {
-                let param = HoverParam::new(&self.filename, cursor)?;
-                Ok(Some(HoverFetch::new(lsp_client, param).await?))
+    fn code_actions(&mut self, range: (CursorPos, CursorPos)) -> anyhow::Result<Option<CodeActionFetch>> {
+        self.flush_for(Feature::CodeAction)?;
+        match self.lsp_for(Feature::CodeAction) {
+            Some((lsp_client, filename)) => {
+                let diagnostics = diagnostics::overlapping(&self.diagnostics, range.0, range.1);
+                let param = CodeActionParam::new(filename, range, diagnostics)?;
+                Ok(Some(CodeActionFetch::in_text(lsp_client, self.rope.clone(), self.version, param)?))
             }
             None => {
                 Ok(None)
@@ -153,15 +585,82 @@ impl Buffer for TextBuffer {
         }
     }
 
-    async fn completion(&self, cursor: CursorPos) -> anyhow::Result<Option<CompletionFetch>> {
-        match self.lsp_client {
-            Some(ref lsp_client) => {
-                let param = CompletionParam::new(&self.filename, cursor)?;
-                Ok(Some(CompletionFetch::new(lsp_client, param).await?))
+    fn formatting(&mut self, range: Option<(CursorPos, CursorPos)>) -> anyhow::Result<Option<FormattingFetch>> {
+        let feature = if range.is_some() { Feature::RangeFormatting } else { Feature::Formatting };
+        self.flush_for(feature)?;
+        match self.lsp_for(feature) {
+            Some((lsp_client, filename)) => {
+                let param = FormattingParam::new(filename, &self.options, range)?;
+                Ok(Some(FormattingFetch::new(lsp_client, self.rope.clone(), self.version, param)?))
             }
             None => {
                 Ok(None)
             }
         }
     }
+
+    fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TextBuffer;
+    use crate::buffer::Buffer;
+
+    #[test]
+    fn word_at() {
+        let buffer = TextBuffer::from_text("let foo_bar = baz(1);\n");
+        assert_eq!(buffer.word_at((0, 6)), Some(((0, 4), (0, 11))));
+        assert_eq!(buffer.word_at((0, 4)), Some(((0, 4), (0, 11))));
+        assert_eq!(buffer.word_at((0, 11)), None);
+        assert_eq!(buffer.text_range((0, 14), (0, 17)), "baz");
+    }
+
+    #[test]
+    fn apply_edits_as_one_undo_step() {
+        let mut buffer = TextBuffer::from_text("foo(foo);\nfoo\n");
+        let edits = vec![
+            ((0, 0), (0, 3), "bar".to_owned()),
+            ((0, 4), (0, 7), "bar".to_owned()),
+            // past the end of the line and of the text
+            ((1, 0), (1, 99), "bar".to_owned()),
+            ((9, 0), (9, 0), "!".to_owned()),
+        ];
+        // the cursor was inside the second `foo`
+        assert_eq!(buffer.apply_edits(&edits, (0, 5)).unwrap(), (0, 7));
+        assert_eq!(buffer.rope_clone().to_string(), "bar(bar);\nbar\n!");
+        buffer.undo().unwrap();
+        assert_eq!(buffer.rope_clone().to_string(), "foo(foo);\nfoo\n");
+    }
+
+    #[test]
+    fn reload_discards_changes() {
+        let path = std::env::temp_dir().join(format!("editor-reload-{}.txt", std::process::id()));
+        std::fs::write(&path, "foo\n").unwrap();
+        let mut buffer = TextBuffer::open(path.to_str().unwrap()).unwrap();
+        buffer.apply_edits(&[((0, 0), (0, 0), "a".to_owned())], (0, 0)).unwrap();
+        assert!(buffer.is_dirty());
+        buffer.reload().unwrap();
+        assert_eq!(buffer.rope_clone().to_string(), "foo\n");
+        assert!(!buffer.is_dirty());
+        buffer.undo().unwrap();
+        assert_eq!(buffer.rope_clone().to_string(), "afoo\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn clean_after_undo_to_saved_text() {
+        let mut buffer = TextBuffer::from_text("foo\n");
+        assert!(!buffer.is_dirty());
+        buffer.begin_undo_group((0, 0));
+        buffer.apply_edits(&[((0, 0), (0, 0), "a".to_owned())], (0, 0)).unwrap();
+        assert!(buffer.is_dirty());
+        buffer.end_undo_group((0, 1));
+        buffer.undo().unwrap();
+        assert!(!buffer.is_dirty());
+        buffer.redo().unwrap();
+        assert!(buffer.is_dirty());
+    }
 }
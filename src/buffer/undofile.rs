@@ -0,0 +1,67 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+
+use super::history::History;
+
+#[derive(Serialize)]
+struct UndoFileRef<'a> {
+    path: &'a str,
+    hash: u64,
+    history: &'a History,
+}
+
+#[derive(Deserialize)]
+struct UndoFile {
+    path: String,
+    hash: u64,
+    history: History,
+}
+
+/// FNV-1a, so that the hash stays stable across builds.
+pub fn content_hash(rope: &Rope) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for chunk in rope.chunks() {
+        for b in chunk.bytes() {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+fn undo_dir() -> Option<PathBuf> {
+    let state = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state.join("editor/undo"))
+}
+
+/// Undo histories are kept under `$XDG_STATE_HOME/editor/undo`, one file per
+/// edited file, named after its absolute path.
+fn sidecar_path(path: &Path) -> Option<(String, PathBuf)> {
+    let path = std::path::absolute(path).ok()?.to_str()?.to_owned();
+    let name = path.replace('%', "%%").replace('/', "%");
+    Some((path, undo_dir()?.join(name)))
+}
+
+/// Only restores the history if the file still has the contents it had when
+/// the history was stored. A damaged undo file is treated as missing.
+pub fn load(path: &Path, rope: &Rope) -> Option<History> {
+    let (path, sidecar) = sidecar_path(path)?;
+    let text = std::fs::read_to_string(sidecar).ok()?;
+    let file: UndoFile = serde_json::from_str(&text).ok()?;
+    (file.path == path && file.hash == content_hash(rope) && file.history.is_valid()).then_some(file.history)
+}
+
+pub fn store(path: &Path, rope: &Rope, history: &History) -> anyhow::Result<()> {
+    let (path, sidecar) = sidecar_path(path).context("no undo directory")?;
+    let text = serde_json::to_string(&UndoFileRef { path: &path, hash: content_hash(rope), history })?;
+    if let Some(dir) = sidecar.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&sidecar, text).with_context(|| format!("failed to write undo file {}", sidecar.display()))
+}
@@ -0,0 +1,190 @@
+use anyhow::anyhow;
+
+use crate::buffer::CursorPos;
+use crate::language::LANGUAGES;
+use crate::lsp::{client::split_command, manager::ServerOverride};
+
+pub const USAGE: &str = "\
+usage: editor [options] [+line[:col]] [file|-]...
+
+  +line[:col]  put the cursor at line (and column) of the next file
+  -R           open files read-only
+  --no-lsp     do not start a language server
+  --lsp [lang=]<cmd>
+               use this language server for one language, e.g. \"cpp=clangd --log=verbose\",
+               or without lang= for every language; may be repeated. The command is
+               split into words with quotes and backslashes as in a shell
+  --format-on-save <langs>
+               format files of these languages, e.g. \"rust,cpp\", before writing them
+  -            read the text from stdin
+  --           treat the remaining arguments as files
+  -h, --help   show this help";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileSource {
+    Path(String),
+    Stdin,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileArg {
+    pub source: FileSource,
+    /// Zero-based cursor to jump to after opening.
+    pub jump: Option<CursorPos>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LspMode {
+    Default,
+    Disabled,
+    Override(Vec<ServerOverride>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Args {
+    pub files: Vec<FileArg>,
+    pub lsp: LspMode,
+    pub readonly: bool,
+    /// Names of the languages formatted by their server before saving.
+    pub format_on_save: Vec<&'static str>,
+    pub help: bool,
+}
+
+/// Parse a comma separated list of language names.
+fn parse_languages(s: &str) -> anyhow::Result<Vec<&'static str>> {
+    s.split(',')
+        .filter(|name| !name.is_empty())
+        .map(|name| LANGUAGES.iter().find(|lang| lang.name == name).map(|lang| lang.name).ok_or_else(|| anyhow!("unknown language: {}", name)))
+        .collect()
+}
+
+/// Parse `[lang=]cmd`; without a known language name before `=` the whole
+/// argument is the command for every language.
+fn parse_server(s: String) -> anyhow::Result<ServerOverride> {
+    let (language, command) = match s.split_once('=') {
+        Some((name, command)) => match LANGUAGES.iter().find(|lang| lang.name == name) {
+            Some(lang) => (Some(lang.name), command.to_owned()),
+            None => (None, s),
+        },
+        None => (None, s),
+    };
+    split_command(&command)?;
+    Ok(ServerOverride { language, command })
+}
+
+/// Parse `+line[:col]`, both one-based, into a zero-based cursor.
+fn parse_jump(s: &str) -> anyhow::Result<CursorPos> {
+    let parse = |n: &str| n.parse::<usize>().ok().filter(|&n| n > 0).ok_or_else(|| anyhow!("invalid position: +{}", s));
+    match s.split_once(':') {
+        Some((line, col)) => Ok((parse(line)? - 1, parse(col)? - 1)),
+        None if s.is_empty() => Err(anyhow!("invalid position: +")),
+        None => Ok((parse(s)? - 1, 0)),
+    }
+}
+
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> anyhow::Result<Args> {
+    let mut parsed = Args { files: vec![], lsp: LspMode::Default, readonly: false, format_on_save: vec![], help: false };
+    let mut jump = None;
+    let mut only_files = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let source = if only_files {
+            FileSource::Path(arg)
+        }
+        else if let Some(pos) = arg.strip_prefix('+') {
+            jump = Some(parse_jump(pos)?);
+            continue;
+        }
+        else {
+            match arg.as_str() {
+                "--" => { only_files = true; continue; }
+                "-h" | "--help" => { parsed.help = true; continue; }
+                "-R" => { parsed.readonly = true; continue; }
+                "--no-lsp" => { parsed.lsp = LspMode::Disabled; continue; }
+                "--lsp" => {
+                    let server = parse_server(args.next().ok_or_else(|| anyhow!("--lsp needs a command"))?)?;
+                    match parsed.lsp {
+                        LspMode::Override(ref mut servers) => servers.push(server),
+                        _ => parsed.lsp = LspMode::Override(vec![server]),
+                    }
+                    continue;
+                }
+                "--format-on-save" => {
+                    let langs = args.next().ok_or_else(|| anyhow!("--format-on-save needs a list of languages"))?;
+                    parsed.format_on_save.extend(parse_languages(&langs)?);
+                    continue;
+                }
+                "-" => FileSource::Stdin,
+                _ if arg.starts_with('-') => return Err(anyhow!("unknown option: {}", arg)),
+                _ => FileSource::Path(arg),
+            }
+        };
+        if source == FileSource::Stdin && parsed.files.iter().any(|f| f.source == FileSource::Stdin) {
+            return Err(anyhow!("stdin can only be read once"));
+        }
+        parsed.files.push(FileArg { source, jump: jump.take() });
+    }
+    if jump.is_some() {
+        return Err(anyhow!("+line needs a file after it"));
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_args, split_command, Args, FileArg, FileSource, LspMode, ServerOverride};
+
+    fn parse(args: &[&str]) -> anyhow::Result<Args> {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    fn path(p: &str, jump: Option<(usize, usize)>) -> FileArg {
+        FileArg { source: FileSource::Path(p.to_owned()), jump }
+    }
+
+    #[test]
+    fn files_and_jumps() {
+        let args = parse(&["a.cpp", "+10:3", "b.cpp", "+2", "c.cpp"]).unwrap();
+        assert_eq!(args.files, vec![path("a.cpp", None), path("b.cpp", Some((9, 2))), path("c.cpp", Some((1, 0)))]);
+        assert_eq!(args.lsp, LspMode::Default);
+        assert!(!args.readonly);
+    }
+
+    #[test]
+    fn options() {
+        let args = parse(&["-R", "--lsp", "clangd --log=verbose", "-", "--", "-x"]).unwrap();
+        assert!(args.readonly);
+        assert_eq!(args.lsp, LspMode::Override(vec![ServerOverride { language: None, command: "clangd --log=verbose".to_owned() }]));
+        assert_eq!(args.files, vec![FileArg { source: FileSource::Stdin, jump: None }, path("-x", None)]);
+        assert_eq!(parse(&["--no-lsp"]).unwrap().lsp, LspMode::Disabled);
+        let args = parse(&["--lsp", "rust=ra-multiplex client", "--lsp", "cpp=clangd", "--lsp", "x=y"]).unwrap();
+        assert_eq!(args.lsp, LspMode::Override(vec![
+            ServerOverride { language: Some("rust"), command: "ra-multiplex client".to_owned() },
+            ServerOverride { language: Some("cpp"), command: "clangd".to_owned() },
+            ServerOverride { language: None, command: "x=y".to_owned() },
+        ]));
+        assert_eq!(parse(&["--format-on-save", "rust,cpp", "a.rs"]).unwrap().format_on_save, vec!["rust", "cpp"]);
+    }
+
+    #[test]
+    fn lsp_command_words() {
+        let words = split_command(r#"clangd  --log=verbose "--query-driver=/opt/my cc/*" a\ b 'x "y"' """#).unwrap();
+        assert_eq!(words, ["clangd", "--log=verbose", "--query-driver=/opt/my cc/*", "a b", "x \"y\"", ""]);
+        assert!(split_command("a 'b").is_err());
+        assert!(split_command("a \\").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse(&["--lsp"]).is_err());
+        assert!(parse(&["-x"]).is_err());
+        assert!(parse(&["+0", "a"]).is_err());
+        assert!(parse(&["+1:x", "a"]).is_err());
+        assert!(parse(&["+3"]).is_err());
+        assert!(parse(&["a", "+3"]).is_err());
+        assert!(parse(&["--lsp", "clangd '--log=verbose"]).is_err());
+        assert!(parse(&["-", "-"]).is_err());
+        assert!(parse(&["--format-on-save"]).is_err());
+        assert!(parse(&["--format-on-save", "rust,cobol"]).is_err());
+    }
+}
@@ -1,20 +1,158 @@
+mod command;
+mod jumplist;
+mod grep;
+mod workspace_edit;
+mod code_action;
+mod format;
+
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::buffer::text_buffer::TextBuffer;
-use crate::lsp::client::{LspClient, LspClientStartArg, path_to_uri};
-use crate::viewer::hover_viewer::HoverViewer;
-use crate::viewer::{ Draw, Input, Viewer, ViewerRect, text_viewer::TextViewer };
+use crate::buffer::{Buffer, BufferOptions, history::UndoTarget, text_buffer::TextBuffer};
+use crate::buffer::diagnostics;
+use crate::lsp::capabilities::Feature;
+use crate::lsp::client::uri_to_path;
+use crate::lsp::dispatch::ServerEvent;
+use crate::lsp::position::PositionEncoding;
+use crate::lsp::method::goto::{GotoFetch, GotoKind, Location, LspLocation};
+use crate::lsp::method::references::ReferencesFetch;
+use crate::lsp::method::rename::{PrepareRenameFetch, RenameFetch};
+use crate::lsp::msg::{ErrorCode, ResponseError};
+use crate::lsp::method::symbols::{SymbolsFetch, SymbolsParam};
+use crate::viewer::location_list_viewer::{ListItem, LocationListViewer};
+use jumplist::JumpList;
+use code_action::CodeActionState;
+use format::PendingFormat;
+use crate::viewer::text_viewer::diagnostic_color;
+use crate::lsp::manager::{LspManager, Restarted};
+use lsp_types::{ApplyWorkspaceEditResponse, DiagnosticSeverity, MessageType, NumberOrString, ProgressParamsValue, WorkDoneProgress};
+use crate::terminal::Color;
+use crate::viewer::prompt_viewer::PromptViewer;
+use crate::viewer::{ Draw, Input, ViewerRect, text_viewer::TextViewer };
 use crate::rawmode::RawMode;
 use crate::terminal::Terminal;
 use anyhow::{ anyhow, Context };
 use crate::key::Key;
+use crate::buffer::CursorPos;
+use crate::cli::{Args, FileSource, LspMode};
+use crate::rawmode::reopen_tty_as_stdin;
+use command::{CommandRegistry, parse_command_line};
+use std::io::Read;
+
+const NO_NAME: &str = "[No Name]";
+
+/// `title: message 42%`, leaving out what the server did not send.
+fn format_progress(title: &str, message: Option<&str>, percentage: Option<u32>) -> String {
+    let mut text = title.to_owned();
+    if let Some(message) = message {
+        if !text.is_empty() {
+            text.push_str(": ");
+        }
+        text.push_str(message);
+    }
+    if let Some(percentage) = percentage {
+        text.push_str(&format!(" {}%", percentage));
+    }
+    text
+}
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 enum Mode {
     Normal,
     Insert,
+    Command,
+    /// Picking an entry of the location list.
+    List,
+    /// Selecting text from the anchor to the cursor.
+    Visual,
+    /// Picking a code action from the popup menu.
+    Menu,
+}
+
+/// A position in a named buffer, remembered by the jump list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Jump {
+    filename: String,
+    pos: CursorPos,
+}
+
+/// Where the cursor was when a request about it was sent. The answer is
+/// dropped once the cursor has moved, another buffer is shown or the text
+/// has changed.
+struct Origin {
+    buffer: Rc<RefCell<TextBuffer>>,
+    cursor: CursorPos,
+    version: i32,
+}
+
+/// A request whose answer is shown in the location list.
+enum ListFetch {
+    Goto(GotoFetch),
+    References(ReferencesFetch),
+    Symbols(String, SymbolsFetch),
+}
+
+impl ListFetch {
+    fn abort(self) {
+        match self {
+            ListFetch::Goto(fetch) => fetch.abort(),
+            ListFetch::References(fetch) => fetch.abort(),
+            ListFetch::Symbols(_, fetch) => fetch.abort(),
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        match self {
+            ListFetch::Goto(fetch) => fetch.is_cancelled(),
+            ListFetch::References(fetch) => fetch.is_cancelled(),
+            ListFetch::Symbols(_, fetch) => fetch.is_cancelled(),
+        }
+    }
+}
+
+/// A rename waiting for the server: first for what may be renamed, then for the edit.
+enum PendingRename {
+    Prepare { buffer: Rc<RefCell<TextBuffer>>, cursor: CursorPos, fetch: PrepareRenameFetch },
+    Rename { new_name: String, fetch: RenameFetch },
+}
+
+impl PendingRename {
+    fn abort(self) {
+        match self {
+            PendingRename::Prepare { fetch, .. } => fetch.abort(),
+            PendingRename::Rename { fetch, .. } => fetch.abort(),
+        }
+    }
+}
+
+/// What the text typed on the bottom line is for.
+enum Prompt {
+    Command,
+    Rename { buffer: Rc<RefCell<TextBuffer>>, cursor: CursorPos },
+}
+
+/// How long a message stays before the diagnostic under the cursor shows again.
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct MessageLine {
+    text: String,
+    error: bool,
+    shown: Option<Instant>,
+}
+
+impl MessageLine {
+    fn new(text: String, error: bool) -> Self {
+        Self { text, error, shown: Some(Instant::now()) }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        self.shown.is_some_and(|shown| now.duration_since(shown) >= MESSAGE_TIMEOUT)
+    }
 }
 
 pub struct Editor {
@@ -24,53 +162,158 @@ pub struct Editor {
 
     insert_char_buffer: Vec<u8>,
     mode: Mode,
+    /// First key of a two-key normal mode command such as `g-`.
+    normal_prefix: Option<Key>,
+    message: MessageLine,
+    quit_confirmed: bool,
+    quit: bool,
+    command_line: PromptViewer,
+    prompt: Prompt,
+    commands: CommandRegistry,
 
-    lsp_client: Option<Arc<LspClient>>,
+    lsp: LspManager,
+    jumps: JumpList<Jump>,
+    /// Waiting for the server to answer `gd`, `gr` or `:symbols`.
+    /// Goto and references requests are about the cursor, so they keep an origin.
+    pending_list: Option<(ListFetch, Option<Origin>)>,
+    location_list: Option<LocationListViewer>,
+    pending_rename: Option<PendingRename>,
+    code_action: Option<CodeActionState>,
+    pending_format: Option<PendingFormat>,
+    /// Languages formatted before their files are written.
+    format_on_save: Vec<&'static str>,
+    /// Titles of work done progress reported by the servers, by token.
+    progress_titles: HashMap<NumberOrString, String>,
     buffers: Vec<Rc<RefCell<TextBuffer>>>,
+    /// `+line:col` from the command line, applied when the buffer is first shown.
+    pending_jumps: Vec<(Rc<RefCell<TextBuffer>>, CursorPos)>,
     viewers: Vec<(TextViewer<TextBuffer>, ViewerRect)>,
     active: usize,
 }
 
 impl Editor {
-    pub fn new() -> anyhow::Result<Editor> {
+    pub async fn from_args(args: Args) -> anyhow::Result<Editor> {
+        let mut stdin_text = None;
+        if args.files.iter().any(|file| file.source == FileSource::Stdin) {
+            let mut text = String::new();
+            std::io::stdin().read_to_string(&mut text).context("cannot read stdin")?;
+            reopen_tty_as_stdin().context("cannot reopen the terminal")?;
+            stdin_text = Some(text);
+        }
+
+        let mut buffers = vec![];
+        for file in args.files.iter() {
+            let buffer = match file.source {
+                FileSource::Path(ref path) => TextBuffer::open(path).with_context(|| format!("cannot open {}", path))?,
+                FileSource::Stdin => TextBuffer::from_text(stdin_text.as_deref().unwrap_or("")),
+            };
+            buffers.push(buffer);
+        }
+        if buffers.is_empty() {
+            buffers.push(TextBuffer::from_text(""));
+        }
+        for buffer in buffers.iter_mut() {
+            buffer.options_mut().readonly = args.readonly;
+        }
+
+        let mut editor = Self::with_buffers(args.lsp, buffers)?;
+        editor.format_on_save = args.format_on_save;
+        for buffer in editor.buffers.clone() {
+            editor.attach_lsp(&buffer).await?;
+        }
+        editor.pending_jumps = editor.buffers.iter().cloned()
+            .zip(args.files.iter())
+            .filter_map(|(buffer, file)| Some((buffer, file.jump?)))
+            .collect();
+        editor.viewers[0].0 = editor.view_buffer(editor.buffers[0].clone())?;
+        Ok(editor)
+    }
+
+    fn with_buffers(lsp_mode: LspMode, buffers: Vec<TextBuffer>) -> anyhow::Result<Editor> {
         let terminal = Terminal::new()?;
-        let rect = ViewerRect { h: terminal.height(), w: terminal.width(), i: 0, j: 0 };
-        let buffer = Rc::new(RefCell::new(TextBuffer::open("./test.txt")?));
-        Ok(Editor {
+        let buffers = buffers.into_iter().map(|buffer| Rc::new(RefCell::new(buffer))).collect::<Vec<_>>();
+        let mut editor = Editor {
             _mode: RawMode::enable_raw_mode().context("enable raw mode failed")?,
             stdin: std::io::stdin(),
             terminal,
             insert_char_buffer: vec![],
             mode: Mode::Normal,
+            normal_prefix: None,
+            message: MessageLine::default(),
+            quit_confirmed: false,
+            quit: false,
+            command_line: PromptViewer::new(),
+            prompt: Prompt::Command,
+            commands: CommandRegistry::builtin(),
 
-            lsp_client: None,
-            buffers: vec![buffer.clone()],
-            viewers: vec![(TextViewer::open(buffer.clone())?, rect)],
+            lsp: match lsp_mode {
+                LspMode::Default => LspManager::new(vec![], false),
+                LspMode::Override(servers) => LspManager::new(servers, false),
+                LspMode::Disabled => LspManager::new(vec![], true),
+            },
+            viewers: vec![(TextViewer::open(buffers[0].clone())?, ViewerRect { h: 0, w: 0, i: 0, j: 0 })],
+            buffers,
+            pending_jumps: vec![],
+            jumps: JumpList::default(),
+            pending_list: None,
+            location_list: None,
+            pending_rename: None,
+            code_action: None,
+            pending_format: None,
+            format_on_save: vec![],
+            progress_titles: HashMap::new(),
             active: 0,
-        })
+        };
+        editor.layout();
+        Ok(editor)
     }
 
-    pub async fn new_clangd() -> anyhow::Result<Editor> {
-        let terminal = Terminal::new()?;
-        let rect = ViewerRect { h: terminal.height(), w: terminal.width(), i: 0, j: 0 };
+    /// Stack the viewers vertically, each with a status line below it.
+    /// The last row of the terminal is kept for the message line.
+    fn layout(&mut self) {
+        let h = self.terminal.height().saturating_sub(1);
+        let w = self.terminal.width();
+        let n = self.viewers.len();
+        let mut i = 0;
+        for (k, (_, rect)) in self.viewers.iter_mut().enumerate() {
+            let share = h / n + if k < h % n { 1 } else { 0 };
+            *rect = ViewerRect { h: share - 1, w, i, j: 0 };
+            i += share;
+        }
+    }
 
-        let lsp_client = LspClient::start(LspClientStartArg { program: "clangd".to_owned() }).await?;
+    fn show_message(&mut self, text: String) {
+        self.message = MessageLine::new(text, false);
+    }
 
-        let lsp_client = Arc::new(lsp_client);
+    fn show_error(&mut self, text: String) {
+        self.message = MessageLine::new(text, true);
+    }
 
-        let buffer = Rc::new(RefCell::new(TextBuffer::open_with_lsp("./1.cpp", lsp_client.clone()).await?));
-        Ok(Editor {
-            _mode: RawMode::enable_raw_mode().context("enable raw mode failed")?,
-            stdin: std::io::stdin(),
-            terminal,
-            insert_char_buffer: vec![],
-            mode: Mode::Normal,
+    fn draw_status_line(&mut self, idx: usize) -> anyhow::Result<()> {
+        let (viewer, rect) = &self.viewers[idx];
+        let buffer = viewer.buffer().borrow();
+        let left = format!(" {}{}{}",
+            buffer.filename().unwrap_or(NO_NAME),
+            if buffer.is_dirty() { " [+]" } else { "" },
+            if buffer.options().readonly { " [RO]" } else { "" });
+        let cursor = viewer.cursor();
+        let language = buffer.language().map(|lang| format!("{}  ", lang.name)).unwrap_or_default();
+        let server = match buffer.lsp_client() {
+            Some(client) if client.has_crashed() => format!("{} (exited)  ", client.start_arg().program),
+            Some(client) => format!("{}  ", client.start_arg().program),
+            None => String::new(),
+        };
+        let right = format!("{}{}{}:{} ", server, language, cursor.0 + 1, cursor.1 + 1);
+        let pad = rect.w.saturating_sub(left.chars().count() + right.chars().count());
+        let line = format!("{}{}{}", left, " ".repeat(pad), right).chars().take(rect.w).collect::<String>();
+        let row = rect.i + rect.h;
+        drop(buffer);
 
-            lsp_client: Some(lsp_client),
-            buffers: vec![buffer.clone()],
-            viewers: vec![(TextViewer::open(buffer.clone())?, rect)],
-            active: 0,
-        })
+        self.terminal.set_cursor(row, 0)?;
+        self.terminal.set_reverse()?;
+        self.terminal.write(line.as_bytes())?;
+        self.terminal.reset_style()
     }
 
     fn update_all(&mut self) -> anyhow::Result<()> {
@@ -78,27 +321,886 @@ impl Editor {
         for (viewer, rect) in self.viewers.iter_mut() {
             viewer.draw_all(rect, &mut self.terminal)?;
         }
-        let active_rect = self.viewers[self.active].1.clone();
-        self.viewers[self.active].0.draw_cursor(&active_rect, &mut self.terminal)?;
+        for idx in 0..self.viewers.len() {
+            self.draw_status_line(idx)?;
+        }
+        let bottom = ViewerRect { h: 1, w: self.terminal.width(), i: self.terminal.height().saturating_sub(1), j: 0 };
+        let list_rect = self.location_list.as_ref().map(|list| {
+            let h = list.wanted_height().min(self.terminal.height().saturating_sub(1) / 2).max(2);
+            ViewerRect { h, w: self.terminal.width(), i: self.terminal.height().saturating_sub(1 + h), j: 0 }
+        });
+        if let (Some(list), Some(rect)) = (self.location_list.as_mut(), list_rect.as_ref()) {
+            list.draw_all(rect, &mut self.terminal)?;
+        }
+        if self.mode == Mode::Command {
+            self.command_line.draw_all(&bottom, &mut self.terminal)?;
+            self.command_line.draw_cursor(&bottom, &mut self.terminal)?;
+        }
+        else {
+            self.terminal.set_cursor(bottom.i, 0)?;
+            let (text, color) = match self.cursor_diagnostic() {
+                Some((text, severity)) if self.message.text.is_empty() => (text, Some(diagnostic_color(severity))),
+                _ => (self.message.text.clone(), self.message.error.then_some(Color { r: 255, g: 80, b: 80 })),
+            };
+            let message = text.chars().take(bottom.w).collect::<String>();
+            if let Some(color) = color {
+                self.terminal.set_fg(color)?;
+            }
+            self.terminal.write(message.as_bytes())?;
+            self.terminal.reset_style()?;
+            let menu_shown = self.draw_code_action_menu(self.mode == Mode::Menu)?;
+            match (self.location_list.as_mut(), list_rect) {
+                (Some(list), Some(rect)) if self.mode == Mode::List => list.draw_cursor(&rect, &mut self.terminal)?,
+                _ if menu_shown && self.mode == Mode::Menu => {}
+                _ => {
+                    let active_rect = self.viewers[self.active].1.clone();
+                    self.viewers[self.active].0.draw_cursor(&active_rect, &mut self.terminal)?;
+                }
+            }
+        }
         self.terminal.flush()
     }
 
+    /// The diagnostic under the cursor of the active viewer, shown while there is no message.
+    fn cursor_diagnostic(&self) -> Option<(String, DiagnosticSeverity)> {
+        let viewer = &self.viewers[self.active].0;
+        let buffer = viewer.buffer().borrow();
+        let diagnostic = diagnostics::at(buffer.diagnostics(), viewer.cursor())?;
+        let severity = diagnostics::severity(diagnostic);
+        let message = diagnostic.message.lines().next().unwrap_or("");
+        Some((format!("{}: {}", diagnostics::label(severity), message), severity))
+    }
+
+    fn jump_diagnostic(&mut self, forward: bool) -> anyhow::Result<()> {
+        let viewer = &mut self.viewers[self.active].0;
+        let pos = diagnostics::next(viewer.buffer().borrow().diagnostics(), viewer.cursor(), forward);
+        match pos {
+            Some(pos) => {
+                viewer.jump_to(pos);
+                self.message = MessageLine::default();
+                Ok(())
+            }
+            None => {
+                self.show_error("No diagnostics".to_owned());
+                Ok(())
+            }
+        }
+    }
+
+    fn current_jump(&self) -> Option<Jump> {
+        let viewer = &self.viewers[self.active].0;
+        let filename = viewer.buffer().borrow().filename()?.to_owned();
+        Some(Jump { filename, pos: viewer.cursor() })
+    }
+
+    /// Show `filename` in the active viewer with the cursor at `pos`.
+    async fn show_position(&mut self, filename: &str, pos: CursorPos) -> anyhow::Result<()> {
+        let buffer = self.open_buffer(filename).await?;
+        if !Rc::ptr_eq(&buffer, &self.active_buffer()) {
+            self.viewers[self.active].0 = self.view_buffer(buffer)?;
+        }
+        self.viewers[self.active].0.jump_to(pos);
+        Ok(())
+    }
+
+    /// Jump to `location`, remembering where we came from.
+    async fn open_location(&mut self, location: &Location) -> anyhow::Result<()> {
+        let filename = location.path.to_str().ok_or_else(|| anyhow!("not a UTF-8 path: {}", location.path.display()))?;
+        if let Some(from) = self.current_jump() {
+            self.jumps.push(from);
+        }
+        self.show_position(filename, location.pos).await
+    }
+
+    async fn jump_back(&mut self) -> anyhow::Result<()> {
+        let Some(current) = self.current_jump() else {
+            return Ok(());
+        };
+        match self.jumps.back(current) {
+            Some(jump) => self.show_position(&jump.filename, jump.pos).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn jump_forward(&mut self) -> anyhow::Result<()> {
+        match self.jumps.forward() {
+            Some(jump) => self.show_position(&jump.filename, jump.pos).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Wait for `fetch`, dropping the request it replaces.
+    fn request_list(&mut self, fetch: ListFetch, origin: Option<Origin>) {
+        if let Some((old, _)) = self.pending_list.replace((fetch, origin)) {
+            old.abort();
+        }
+    }
+
+    /// The active buffer and cursor, to tell later whether they are still there.
+    fn origin(&self) -> Origin {
+        let buffer = self.active_buffer();
+        let version = buffer.borrow().version();
+        Origin { buffer, cursor: self.viewers[self.active].0.cursor(), version }
+    }
+
+    fn is_at(&self, origin: &Origin) -> bool {
+        Rc::ptr_eq(&self.active_buffer(), &origin.buffer)
+            && self.viewers[self.active].0.cursor() == origin.cursor
+            && origin.buffer.borrow().version() == origin.version
+    }
+
+    /// Tell why no `feature` request could be sent for `buffer`.
+    fn show_unsupported(&mut self, buffer: &Rc<RefCell<TextBuffer>>, feature: Feature) {
+        let message = match buffer.borrow().lsp_client() {
+            Some(client) => format!("{} does not support {}", client.start_arg().program, feature.name()),
+            None => "No language server for this buffer".to_owned(),
+        };
+        self.show_error(message);
+    }
+
+    async fn goto(&mut self, kind: GotoKind) -> anyhow::Result<()> {
+        let buffer = self.active_buffer();
+        let cursor = self.viewers[self.active].0.cursor();
+        let fetch = buffer.borrow_mut().goto(kind, cursor)?;
+        match fetch {
+            Some(fetch) => self.request_list(ListFetch::Goto(fetch), Some(self.origin())),
+            None => self.show_unsupported(&buffer, kind.feature()),
+        }
+        Ok(())
+    }
+
+    async fn references(&mut self) -> anyhow::Result<()> {
+        let buffer = self.active_buffer();
+        let cursor = self.viewers[self.active].0.cursor();
+        let fetch = buffer.borrow_mut().references(cursor)?;
+        match fetch {
+            Some(fetch) => self.request_list(ListFetch::References(fetch), Some(self.origin())),
+            None => self.show_unsupported(&buffer, Feature::References),
+        }
+        Ok(())
+    }
+
+    /// Ask the active buffer's server, or any running one, for symbols matching `query`.
+    async fn workspace_symbols(&mut self, query: &str) -> anyhow::Result<()> {
+        let client = self.active_buffer().borrow().lsp_client().cloned().filter(|client| client.supports(Feature::WorkspaceSymbol))
+            .or_else(|| self.lsp.clients().map(|(_, client)| client).find(|client| client.supports(Feature::WorkspaceSymbol)).cloned())
+            .ok_or_else(|| anyhow!("No language server supports workspace symbols"))?;
+        let fetch = SymbolsFetch::new(&client, SymbolsParam::new(query))?;
+        self.request_list(ListFetch::Symbols(query.to_owned(), fetch), None);
+        Ok(())
+    }
+
+    /// Search the files below the working directory for `pattern`.
+    fn grep(&mut self, pattern: &str) -> anyhow::Result<()> {
+        let items = grep::grep(&std::env::current_dir()?, pattern)?;
+        if items.is_empty() {
+            self.show_error(format!("No match for {}", pattern));
+        }
+        else {
+            let more = if items.len() >= grep::MAX_MATCHES { "+" } else { "" };
+            self.show_list(format!("{}{} matches for {}", items.len(), more, pattern), items);
+        }
+        Ok(())
+    }
+
+    fn show_list(&mut self, title: String, items: Vec<ListItem>) {
+        self.location_list = Some(LocationListViewer::new(title, items));
+        self.mode = Mode::List;
+    }
+
+    /// The line each location points at, from its buffer when it is open,
+    /// which also tells the column the server's position counts to.
+    fn with_previews(&self, locations: Vec<LspLocation>) -> Vec<ListItem> {
+        let mut files = HashMap::<PathBuf, Vec<String>>::new();
+        locations.into_iter().map(|location| {
+            let line = location.position.line as usize;
+            let text = match location.path.to_str().and_then(|path| self.find_buffer(path)) {
+                Some(buffer) => {
+                    let rope = buffer.borrow().rope_clone();
+                    match rope.get_line(line) {
+                        Some(line) => line.to_string().trim_end_matches(['\n', '\r']).to_owned(),
+                        None => String::new(),
+                    }
+                }
+                None => {
+                    let lines = files.entry(location.path.clone()).or_insert_with(|| {
+                        std::fs::read_to_string(&location.path).map(|text| text.lines().map(str::to_owned).collect()).unwrap_or_default()
+                    });
+                    lines.get(line).cloned().unwrap_or_default()
+                }
+            };
+            ListItem { location: location.resolve(&text), text }
+        }).collect()
+    }
+
+    /// Jump or show the list once the pending request is answered.
+    async fn poll_list(&mut self) -> anyhow::Result<()> {
+        let Some((mut fetch, origin)) = self.pending_list.take() else {
+            return Ok(());
+        };
+        if origin.as_ref().is_some_and(|origin| !self.is_at(origin)) {
+            fetch.abort();
+            return Ok(());
+        }
+        let result = match fetch {
+            ListFetch::Goto(ref mut goto) => {
+                let kind = goto.kind();
+                goto.try_get_result()
+                    .map(|r| r.map(|r| (format!("{}s", kind.label()), self.with_previews(r.locations.clone()))))
+                    .with_context(|| format!("{} request failed", kind.label()))
+            }
+            ListFetch::References(ref mut refs) => {
+                refs.try_get_result()
+                    .map(|r| r.map(|r| ("references".to_owned(), self.with_previews(r.locations.clone()))))
+                    .context("references request failed")
+            }
+            ListFetch::Symbols(ref query, ref mut symbols) => {
+                symbols.try_get_result()
+                    .map(|r| r.map(|r| {
+                        let items = self.with_previews(r.symbols.iter().map(|s| s.location.clone()).collect())
+                            .into_iter()
+                            .zip(r.symbols.iter())
+                            .map(|(item, s)| ListItem { text: s.describe(), ..item })
+                            .collect();
+                        (format!("symbols matching '{}'", query), items)
+                    }))
+                    .context("workspace symbol request failed")
+            }
+        };
+        let (what, items) = match result? {
+            Some(found) => found,
+            None => {
+                if fetch.is_cancelled() {
+                    return Err(anyhow!("Request cancelled"));
+                }
+                self.pending_list = Some((fetch, origin));
+                return Ok(());
+            }
+        };
+        match items.len() {
+            0 => self.show_error(format!("No {} found", what)),
+            // a single target is jumped to right away, as vim does
+            1 if matches!(fetch, ListFetch::Goto(_)) => self.open_location(&items[0].location).await?,
+            n => self.show_list(format!("{} {}", n, what), items),
+        }
+        Ok(())
+    }
+
+    /// Ask the server what can be renamed under the cursor, then prompt for
+    /// the new name. Servers without `prepareRename` get the word under the cursor.
+    async fn start_rename(&mut self) -> anyhow::Result<()> {
+        let buffer = self.active_buffer();
+        let cursor = self.viewers[self.active].0.cursor();
+        if !buffer.borrow().supports(Feature::Rename) {
+            self.show_unsupported(&buffer, Feature::Rename);
+            return Ok(());
+        }
+        let fetch = buffer.borrow_mut().prepare_rename(cursor)?;
+        match fetch {
+            Some(fetch) => {
+                if let Some(old) = self.pending_rename.replace(PendingRename::Prepare { buffer, cursor, fetch }) {
+                    old.abort();
+                }
+            }
+            None => {
+                let name = {
+                    let buffer = buffer.borrow();
+                    buffer.word_at(cursor).map(|(start, end)| buffer.text_range(start, end))
+                };
+                match name {
+                    Some(name) => self.open_rename_prompt(buffer, cursor, &name),
+                    None => self.show_error("Nothing to rename here".to_owned()),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rename the symbol at `cursor` in `buffer` without asking.
+    async fn rename(&mut self, buffer: &Rc<RefCell<TextBuffer>>, cursor: CursorPos, new_name: &str) -> anyhow::Result<()> {
+        if new_name.is_empty() {
+            return Err(anyhow!("Empty name"));
+        }
+        let fetch = buffer.borrow_mut().rename(cursor, new_name)?;
+        match fetch {
+            Some(fetch) => {
+                if let Some(old) = self.pending_rename.replace(PendingRename::Rename { new_name: new_name.to_owned(), fetch }) {
+                    old.abort();
+                }
+            }
+            None => self.show_unsupported(buffer, Feature::Rename),
+        }
+        Ok(())
+    }
+
+    fn open_rename_prompt(&mut self, buffer: Rc<RefCell<TextBuffer>>, cursor: CursorPos, name: &str) {
+        self.command_line.open("Rename: ", name);
+        self.prompt = Prompt::Rename { buffer, cursor };
+        self.mode = Mode::Command;
+    }
+
+    async fn poll_rename(&mut self) -> anyhow::Result<()> {
+        match self.pending_rename.take() {
+            None => {}
+            Some(PendingRename::Prepare { buffer, cursor, mut fetch }) => {
+                let prepared = match fetch.try_get_result() {
+                    Ok(None) => {
+                        if fetch.is_cancelled() {
+                            return Err(anyhow!("Rename cancelled"));
+                        }
+                        self.pending_rename = Some(PendingRename::Prepare { buffer, cursor, fetch });
+                        return Ok(());
+                    }
+                    Ok(Some(None)) => {
+                        self.show_error("Nothing to rename here".to_owned());
+                        return Ok(());
+                    }
+                    Ok(Some(Some(prepared))) => match (prepared.placeholder.clone(), prepared.range) {
+                        (Some(placeholder), _) => Some(placeholder),
+                        (None, Some((start, end))) => Some(buffer.borrow().text_range(start, end)),
+                        (None, None) => None,
+                    },
+                    // servers without prepareRename leave the name to us
+                    Err(e) if e.downcast_ref::<ResponseError>().is_some_and(|e| e.code == ErrorCode::MethodNotFound as i32) => None,
+                    Err(e) => return Err(e.context("rename failed")),
+                };
+                let name = prepared.or_else(|| {
+                    let buffer = buffer.borrow();
+                    buffer.word_at(cursor).map(|(start, end)| buffer.text_range(start, end))
+                });
+                match name {
+                    Some(name) => self.open_rename_prompt(buffer, cursor, &name),
+                    None => self.show_error("Nothing to rename here".to_owned()),
+                }
+            }
+            Some(PendingRename::Rename { new_name, mut fetch }) => {
+                let edit = match fetch.try_get_result() {
+                    Ok(None) => {
+                        if fetch.is_cancelled() {
+                            return Err(anyhow!("Rename cancelled"));
+                        }
+                        self.pending_rename = Some(PendingRename::Rename { new_name, fetch });
+                        return Ok(());
+                    }
+                    Ok(Some(result)) => (result.edit.clone(), result.encoding),
+                    Err(e) => return Err(e.context("rename failed")),
+                };
+                let (Some(edit), encoding) = edit else {
+                    self.show_error("Nothing to rename here".to_owned());
+                    return Ok(());
+                };
+                let files = self.apply_workspace_edit(edit, encoding).await.context("rename failed")?;
+                self.show_message(format!("Renamed to {} in {} file{}", new_name, files, if files == 1 { "" } else { "s" }));
+            }
+        }
+        Ok(())
+    }
+
+    fn active_buffer(&self) -> Rc<RefCell<TextBuffer>> {
+        self.viewers[self.active].0.buffer().clone()
+    }
+
+    fn find_buffer(&self, path: &str) -> Option<Rc<RefCell<TextBuffer>>> {
+        let path = std::path::absolute(path).ok()?;
+        self.buffers.iter()
+            .find(|buffer| buffer.borrow().filename().and_then(|name| std::path::absolute(name).ok()).as_ref() == Some(&path))
+            .cloned()
+    }
+
+    fn view_buffer(&mut self, buffer: Rc<RefCell<TextBuffer>>) -> anyhow::Result<TextViewer<TextBuffer>> {
+        let mut viewer = TextViewer::open(buffer.clone())?;
+        if let Some(idx) = self.pending_jumps.iter().position(|(b, _)| Rc::ptr_eq(b, &buffer)) {
+            viewer.jump_to(self.pending_jumps.remove(idx).1);
+        }
+        Ok(viewer)
+    }
+
+    async fn open_buffer(&mut self, path: &str) -> anyhow::Result<Rc<RefCell<TextBuffer>>> {
+        if let Some(buffer) = self.find_buffer(path) {
+            return Ok(buffer);
+        }
+        let buffer = TextBuffer::open(path).with_context(|| format!("cannot open {}", path))?;
+        let buffer = Rc::new(RefCell::new(buffer));
+        self.buffers.push(buffer.clone());
+        self.attach_lsp(&buffer).await?;
+        Ok(buffer)
+    }
+
+    /// Attach `buffer` to the server for its language and workspace, starting it on first use.
+    /// A server that fails to start is reported and the file opens without LSP.
+    async fn attach_lsp(&mut self, buffer: &Rc<RefCell<TextBuffer>>) -> anyhow::Result<()> {
+        let (Some(language), Some(path)) = (buffer.borrow().language(), buffer.borrow().filename().map(PathBuf::from)) else {
+            return Ok(());
+        };
+        match self.lsp.acquire(language, &path).await {
+            Ok(Some(client)) => buffer.borrow_mut().attach_lsp(client)?,
+            Ok(None) => {}
+            Err(e) => self.show_error(format!("{} language server disabled: {:#}", language.name, e)),
+        }
+        Ok(())
+    }
+
+    /// Close `buffer` on its server, shutting the server down if nothing else uses it.
+    async fn detach_lsp(&mut self, buffer: &Rc<RefCell<TextBuffer>>) -> anyhow::Result<()> {
+        let client = buffer.borrow_mut().detach_lsp()?;
+        if let Some(client) = client {
+            self.lsp.release(&client).await?;
+        }
+        Ok(())
+    }
+
+    async fn write_active(&mut self, force: bool, path: Option<&str>) -> anyhow::Result<()> {
+        let buffer = self.active_buffer();
+        let unnamed = buffer.borrow().filename().is_none();
+        match path {
+            Some(path) if !unnamed => {
+                buffer.borrow().write_copy(path)?;
+                self.show_message(format!("\"{}\" written", path));
+                return Ok(());
+            }
+            Some(path) => {
+                if self.find_buffer(path).is_some() {
+                    return Err(anyhow!("\"{}\" is already open in another buffer", path));
+                }
+                buffer.borrow_mut().set_filename(path);
+            }
+            None if unnamed => return Err(anyhow!("No file name")),
+            None => {}
+        }
+        if buffer.borrow().options().readonly && !force {
+            return Err(anyhow!("'readonly' option is set (add ! to override)"));
+        }
+        let unformatted = self.format_before_save(&buffer).await?;
+        buffer.borrow_mut().save()?;
+        if unnamed {
+            self.attach_lsp(&buffer).await?;
+        }
+        let written = format!("\"{}\" written", buffer.borrow().filename().unwrap_or(NO_NAME));
+        match unformatted {
+            Some(reason) => self.show_error(format!("{} ({})", written, reason)),
+            None => self.show_message(written),
+        }
+        Ok(())
+    }
+
+    async fn write_all(&mut self, force: bool) -> anyhow::Result<()> {
+        for buffer in self.buffers.clone() {
+            if !buffer.borrow().is_dirty() || (buffer.borrow().options().readonly && !force) {
+                continue;
+            }
+            if let Some(reason) = self.format_before_save(&buffer).await? {
+                self.show_error(format!("\"{}\" written ({})", buffer.borrow().filename().unwrap_or(NO_NAME), reason));
+            }
+            buffer.borrow_mut().save()?;
+        }
+        Ok(())
+    }
+
+    fn first_dirty_buffer(&self) -> Option<String> {
+        self.buffers.iter()
+            .find(|buffer| buffer.borrow().is_dirty())
+            .map(|buffer| buffer.borrow().filename().unwrap_or(NO_NAME).to_owned())
+    }
+
+    /// Close the active viewer, quitting the editor when it is the last one.
+    async fn close_active(&mut self, force: bool) -> anyhow::Result<()> {
+        if self.viewers.len() == 1 {
+            return self.quit_all(force);
+        }
+        let buffer = self.active_buffer();
+        let last_view = self.viewers.iter().filter(|(viewer, _)| Rc::ptr_eq(viewer.buffer(), &buffer)).count() == 1;
+        if last_view && buffer.borrow().is_dirty() && !force {
+            return Err(anyhow!("No write since last change (add ! to override)"));
+        }
+        self.viewers.remove(self.active);
+        self.active = self.active.min(self.viewers.len() - 1);
+        self.layout();
+        if last_view {
+            self.buffers.retain(|b| !Rc::ptr_eq(b, &buffer));
+            self.pending_jumps.retain(|(b, _)| !Rc::ptr_eq(b, &buffer));
+            // the window is gone either way; a server that fails to let go is only reported
+            if let Err(e) = self.detach_lsp(&buffer).await {
+                self.show_error(format!("{:#}", e));
+            }
+        }
+        Ok(())
+    }
+
+    fn quit_all(&mut self, force: bool) -> anyhow::Result<()> {
+        if !force {
+            if let Some(filename) = self.first_dirty_buffer() {
+                return Err(anyhow!("No write since last change for \"{}\" (add ! to override)", filename));
+            }
+        }
+        self.quit = true;
+        Ok(())
+    }
+
+    /// Without a path the active file is read again; `force` reads an open
+    /// file again even when that discards its changes.
+    async fn edit_file(&mut self, path: Option<&str>, force: bool) -> anyhow::Result<()> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => self.active_buffer().borrow().filename().ok_or_else(|| anyhow!("No file name"))?.to_owned(),
+        };
+        let open = self.find_buffer(&path);
+        let reload = match open {
+            Some(ref buffer) if buffer.borrow().is_dirty() && !force => {
+                if Rc::ptr_eq(buffer, &self.active_buffer()) {
+                    return Err(anyhow!("No write since last change (add ! to override)"));
+                }
+                false
+            }
+            Some(ref buffer) => force || Rc::ptr_eq(buffer, &self.active_buffer()),
+            None => false,
+        };
+        if let (Some(buffer), true) = (open, reload) {
+            buffer.borrow_mut().reload().with_context(|| format!("cannot open {}", path))?;
+        }
+        let buffer = self.open_buffer(&path).await?;
+        self.viewers[self.active].0 = self.view_buffer(buffer)?;
+        Ok(())
+    }
+
+    async fn split(&mut self, path: Option<&str>) -> anyhow::Result<()> {
+        if self.terminal.height().saturating_sub(1) / (self.viewers.len() + 1) < 2 {
+            return Err(anyhow!("Not enough room"));
+        }
+        let buffer = match path {
+            Some(path) => self.open_buffer(path).await?,
+            None => self.active_buffer(),
+        };
+        let rect = self.viewers[self.active].1.clone();
+        let viewer = self.view_buffer(buffer)?;
+        self.viewers.insert(self.active, (viewer, rect));
+        self.layout();
+        Ok(())
+    }
+
+    fn set_option(&mut self, arg: &str) -> anyhow::Result<()> {
+        let buffer = self.active_buffer();
+        let mut buffer = buffer.borrow_mut();
+        if let Some((name, value)) = arg.split_once('=') {
+            buffer.options_mut().set(name, Some(value))
+        }
+        else if let Some(name) = arg.strip_suffix('?') {
+            let value = buffer.options().get(name)?;
+            drop(buffer);
+            self.show_message(value);
+            Ok(())
+        }
+        else if BufferOptions::is_bool(arg.strip_prefix("no").unwrap_or(arg)) {
+            buffer.options_mut().set(arg, None)?;
+            buffer.load_undofile();
+            Ok(())
+        }
+        else {
+            let value = buffer.options().get(arg)?;
+            drop(buffer);
+            self.show_message(value);
+            Ok(())
+        }
+    }
+
+    /// Reopen the buffers of a replaced server on its successor, which sends
+    /// `didOpen` with their current contents.
+    async fn move_buffers(&mut self, restarted: Restarted) -> anyhow::Result<()> {
+        for buffer in self.buffers.iter() {
+            if !buffer.borrow().lsp_client().is_some_and(|client| Arc::ptr_eq(client, &restarted.old)) {
+                continue;
+            }
+            match restarted.new {
+                Ok(ref client) => buffer.borrow_mut().attach_lsp(client.clone())?,
+                Err(_) => buffer.borrow_mut().forget_lsp(),
+            }
+        }
+        Ok(())
+    }
+
+    /// Restart servers that exited on their own and tell the user about it.
+    async fn recover_lsp(&mut self) -> anyhow::Result<()> {
+        for restarted in self.lsp.recover_crashed().await {
+            let what = match restarted.exited {
+                Some(status) => format!("exited unexpectedly ({})", status),
+                None => "stopped responding".to_owned(),
+            };
+            match restarted.new {
+                Ok(_) => self.show_error(format!("{} language server {}, restarted", restarted.language, what)),
+                Err(ref e) => self.show_error(format!("{} language server {}: {:#}", restarted.language, what, e)),
+            }
+            self.move_buffers(restarted).await?;
+        }
+        Ok(())
+    }
+
+    /// Recover crashed servers, send the changes typing has paused on and
+    /// handle what the servers sent since the last tick.
+    async fn poll_lsp(&mut self) -> anyhow::Result<()> {
+        self.recover_lsp().await?;
+        let now = Instant::now();
+        for buffer in self.buffers.iter() {
+            if buffer.borrow().changes_due(now) {
+                buffer.borrow_mut().flush_changes()?;
+            }
+        }
+        // each request is independent, so one failing must not hold up the
+        // rest, and the message line only fits one message
+        let results = [
+            self.poll_list().await,
+            self.poll_rename().await,
+            self.poll_code_action().await,
+            self.poll_format().await,
+            self.viewers[self.active].0.poll_hover(),
+            self.viewers[self.active].0.poll_completion(),
+        ];
+        let errors: Vec<_> = results.into_iter().filter_map(Result::err).map(|e| format!("{:#}", e)).collect();
+        if !errors.is_empty() {
+            self.show_error(errors.join("; "));
+        }
+        let mut events = vec![];
+        for (language, client) in self.lsp.clients() {
+            while let Some(event) = client.try_recv_event() {
+                events.push((language, client.position_encoding(), event));
+            }
+        }
+        for (language, encoding, event) in events {
+            self.handle_lsp_event(language, encoding, event).await?;
+        }
+        let viewer = &mut self.viewers[self.active].0;
+        if self.mode == Mode::Insert && viewer.completion_shown() {
+            viewer.resolve_completion().await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_lsp_event(&mut self, language: &'static str, encoding: PositionEncoding, event: ServerEvent) -> anyhow::Result<()> {
+        match event {
+            ServerEvent::Diagnostics(params) => {
+                // servers escape spaces and non-ASCII names, so the URI is decoded
+                // rather than compared with one made from the file name
+                let buffer = uri_to_path(&params.uri).ok().and_then(|path| self.find_buffer(path.to_str()?));
+                if let Some(buffer) = buffer {
+                    buffer.borrow_mut().set_diagnostics(params.version, params.diagnostics);
+                }
+            }
+            ServerEvent::ShowMessage(params) => self.show_lsp_message(language, params.typ, &params.message),
+            ServerEvent::ShowMessageRequest(params, responder) => {
+                self.show_lsp_message(language, params.typ, &params.message);
+                responder.respond(serde_json::Value::Null)?;
+            }
+            // the log is only worth interrupting for when something went wrong
+            ServerEvent::LogMessage(params) => {
+                if params.typ == MessageType::ERROR || params.typ == MessageType::WARNING {
+                    self.show_lsp_message(language, params.typ, &params.message);
+                }
+            }
+            ServerEvent::BadNotification(error) => self.show_error(format!("{}: bad notification: {}", language, error)),
+            ServerEvent::Progress(params) => {
+                let ProgressParamsValue::WorkDone(progress) = params.value;
+                let text = match progress {
+                    WorkDoneProgress::Begin(begin) => {
+                        let text = format_progress(&begin.title, begin.message.as_deref(), begin.percentage);
+                        self.progress_titles.insert(params.token, begin.title);
+                        text
+                    }
+                    WorkDoneProgress::Report(report) => {
+                        let title = self.progress_titles.get(&params.token).map(|t| t.as_str()).unwrap_or("");
+                        format_progress(title, report.message.as_deref(), report.percentage)
+                    }
+                    WorkDoneProgress::End(end) => {
+                        let title = self.progress_titles.remove(&params.token).unwrap_or_default();
+                        format_progress(&title, Some(end.message.as_deref().unwrap_or("done")), None)
+                    }
+                };
+                if !self.message.error {
+                    self.show_message(format!("{}: {}", language, text));
+                }
+            }
+            ServerEvent::ApplyEdit(params, responder) => {
+                let response = match self.apply_workspace_edit(params.edit, encoding).await {
+                    Ok(_) => ApplyWorkspaceEditResponse { applied: true, failure_reason: None, failed_change: None },
+                    Err(e) => {
+                        let reason = format!("{:#}", e);
+                        self.show_error(format!("{}: {}", language, reason));
+                        ApplyWorkspaceEditResponse { applied: false, failure_reason: Some(reason), failed_change: None }
+                    }
+                };
+                responder.respond(response)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn show_lsp_message(&mut self, language: &str, typ: MessageType, message: &str) {
+        let text = format!("{}: {}", language, message);
+        if typ == MessageType::ERROR || typ == MessageType::WARNING {
+            self.show_error(text);
+        }
+        else {
+            self.show_message(text);
+        }
+    }
+
+    /// Start every server again and retry those that failed before.
+    async fn restart_lsp(&mut self) -> anyhow::Result<()> {
+        if self.lsp.is_disabled() {
+            return Err(anyhow!("Language servers are disabled"));
+        }
+        self.message = MessageLine::default();
+        for restarted in self.lsp.restart_all().await {
+            if let Err(ref e) = restarted.new {
+                self.show_error(format!("{} language server did not restart: {:#}", restarted.language, e));
+            }
+            self.move_buffers(restarted).await?;
+        }
+        for buffer in self.buffers.clone() {
+            if buffer.borrow().lsp_client().is_none() {
+                self.attach_lsp(&buffer).await?;
+            }
+        }
+        if !self.message.error {
+            self.show_message(format!("{} language server(s) running", self.lsp.running()));
+        }
+        Ok(())
+    }
+
+    async fn undo(&mut self) -> anyhow::Result<()> {
+        if !self.viewers[self.active].0.undo().await? {
+            self.show_message("Already at oldest change".to_owned());
+        }
+        Ok(())
+    }
+
+    async fn redo(&mut self) -> anyhow::Result<()> {
+        if !self.viewers[self.active].0.redo().await? {
+            self.show_message("Already at newest change".to_owned());
+        }
+        Ok(())
+    }
+
+    async fn undo_travel(&mut self, target: UndoTarget) -> anyhow::Result<()> {
+        if !self.viewers[self.active].0.undo_travel(target).await? {
+            let newer = match target {
+                UndoTarget::Steps(n) => n > 0,
+                UndoTarget::Seconds(n) => n > 0,
+            };
+            self.show_message(if newer { "Already at newest change" } else { "Already at oldest change" }.to_owned());
+        }
+        else {
+            self.show_undo_state();
+        }
+        Ok(())
+    }
+
+    fn show_undo_state(&mut self) {
+        let buffer = self.active_buffer();
+        let buffer = buffer.borrow();
+        let history = buffer.history();
+        let text = format!("state {} of {}, {} branch(es)", history.current_state(), history.states() - 1, history.branches());
+        drop(buffer);
+        self.show_message(text);
+    }
+
+    fn enter_insert(&mut self) {
+        let cursor = self.viewers[self.active].0.cursor();
+        self.active_buffer().borrow_mut().begin_undo_group(cursor);
+        self.mode = Mode::Insert;
+    }
+
+    fn leave_insert(&mut self) {
+        self.viewers[self.active].0.end_snippet();
+        self.viewers[self.active].0.close_completion();
+        self.viewers[self.active].0.close_signature_help();
+        let cursor = self.viewers[self.active].0.cursor();
+        self.active_buffer().borrow_mut().end_undo_group(cursor);
+        self.mode = Mode::Normal;
+    }
+
+    async fn execute_command(&mut self, line: &str) -> anyhow::Result<()> {
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        let (name, args) = parse_command_line(line)?;
+        let handler = self.commands.find(&name)
+            .ok_or_else(|| anyhow!("Not an editor command: {}", name))?
+            .handler;
+        handler(self, args).await
+    }
+
+    async fn normal_prefixed_input(&mut self, prefix: Key, key: Key) -> anyhow::Result<()> {
+        if prefix == Key::char(b'g') {
+                 if key == Key::char(b'-') { self.undo_travel(UndoTarget::Steps(-1)).await }
+            else if key == Key::char(b'+') { self.undo_travel(UndoTarget::Steps(1)).await }
+            else if key == Key::char(b'd') { self.goto(GotoKind::Definition).await }
+            else if key == Key::char(b'D') { self.goto(GotoKind::Declaration).await }
+            else if key == Key::char(b'y') { self.goto(GotoKind::TypeDefinition).await }
+            else if key == Key::char(b'i') { self.goto(GotoKind::Implementation).await }
+            else if key == Key::char(b'r') { self.references().await }
+            else if key == Key::char(b'R') { self.start_rename().await }
+            else if key == Key::char(b'a') {
+                let cursor = self.viewers[self.active].0.cursor();
+                self.code_actions((cursor, cursor)).await
+            }
+            else { Ok(()) }
+        }
+        else if prefix == Key::char(b']') && key == Key::char(b'd') { self.jump_diagnostic(true) }
+        else if prefix == Key::char(b'[') && key == Key::char(b'd') { self.jump_diagnostic(false) }
+        else { Ok(()) }
+    }
+
     async fn normal_input(&mut self, key: Key) -> anyhow::Result<()> {
+        if let Some(prefix) = self.normal_prefix.take() {
+            return self.normal_prefixed_input(prefix, key).await;
+        }
              if key == Key::char(b'j') { self.viewers[self.active].0.move_down() }
         else if key == Key::char(b'k') { self.viewers[self.active].0.move_up() }
         else if key == Key::char(b'h') { self.viewers[self.active].0.move_left() }
         else if key == Key::char(b'l') { self.viewers[self.active].0.move_right() }
-        else if key == Key::char(b'i') { self.mode = Mode::Insert; Ok(()) }
+        else if key == Key::char(b'i') { self.enter_insert(); Ok(()) }
+        else if key == Key::char(b'v') {
+            self.viewers[self.active].0.start_selection();
+            self.mode = Mode::Visual;
+            self.show_message("-- VISUAL --".to_owned());
+            Ok(())
+        }
+        else if key == Key::char(b'u') { self.undo().await }
+        else if key == Key::ctrl(b'r') { self.redo().await }
+        else if key == Key::ctrl(b'o') { self.jump_back().await }
+        else if key == Key::ctrl(b'i') { self.jump_forward().await }
+        else if key == Key::char(b'g') || key == Key::char(b']') || key == Key::char(b'[') { self.normal_prefix = Some(key); Ok(()) }
+        else if key == Key::char(b':') {
+            self.command_line.open(":", "");
+            self.mode = Mode::Command;
+            Ok(())
+        }
         else if key == Key::ctrl(b'w') { self.active = (self.active + 1) % self.viewers.len(); Ok(()) }
+        else if key == Key::ctrl(b's') {
+            if let Err(e) = self.write_active(false, None).await {
+                self.show_error(format!("{:#}", e));
+            }
+            Ok(())
+        }
         else if key == Key::char(b'K') {
+            let buffer = self.active_buffer();
+            if !buffer.borrow().supports(Feature::Hover) {
+                self.show_unsupported(&buffer, Feature::Hover);
+                return Ok(());
+            }
             self.viewers[self.active].0.hover().await?;
             Ok(())
         }
+        else if key == Key::ctrl(b'e') { self.viewers[self.active].0.scroll_hover(1); Ok(()) }
+        else if key == Key::ctrl(b'y') { self.viewers[self.active].0.scroll_hover(-1); Ok(()) }
+        else if key == Key::escape() { self.viewers[self.active].0.close_hover(); Ok(()) }
         else { Ok(()) }
     }
 
+    fn has_dirty_buffer(&self) -> bool {
+        self.buffers.iter().any(|buffer| buffer.borrow().is_dirty())
+    }
+
     async fn insert_input(&mut self, key: Key) -> anyhow::Result<()> {
-        if self.insert_char_buffer.len() > 0 {
+        if !self.insert_char_buffer.is_empty() {
             if let Key::Character(ch) = key {
                 self.insert_char_buffer.push(ch);
             }
@@ -107,16 +1209,42 @@ impl Editor {
             }
         }
         else if key == Key::escape() {
-            self.mode = Mode::Normal;
+            self.leave_insert();
         }
         else if key == Key::backspace() {
             self.viewers[self.active].0.backspace().await?;
+            self.viewers[self.active].0.signature_help(None).await?;
         }
         else if key == Key::char(b'\r') {
             self.viewers[self.active].0.newline().await?;
+            self.viewers[self.active].0.signature_help(None).await?;
+        }
+        else if key == Key::BackTab {
+            if self.viewers[self.active].0.jump_tab_stop(false) {
+                self.viewers[self.active].0.signature_help(None).await?;
+            }
+        }
+        else if key == Key::char(b'\t') {
+            // inside a snippet Tab moves to its next tab stop instead, often the next argument
+            if self.viewers[self.active].0.jump_tab_stop(true) {
+                self.viewers[self.active].0.signature_help(None).await?;
+                return Ok(());
+            }
+            let options = self.active_buffer().borrow().options().clone();
+            if options.expandtab {
+                let width = options.tabstop - self.viewers[self.active].0.cursor().1 % options.tabstop;
+                for _ in 0..width {
+                    self.viewers[self.active].0.insert_char(' ').await?;
+                }
+            }
+            else {
+                self.viewers[self.active].0.insert_char('\t').await?;
+            }
+            self.viewers[self.active].0.signature_help(None).await?;
         }
         else if key == Key::ctrl(b'd') {
             self.viewers[self.active].0.do_completion().await?;
+            self.viewers[self.active].0.signature_help(None).await?;
         }
         else if key == Key::ArrowUp {
             self.viewers[self.active].0.completion_prev().await?;
@@ -129,11 +1257,71 @@ impl Editor {
                 self.insert_char_buffer.push(ch);
             }
         }
-        if self.insert_char_buffer.len() > 0 {
+        if !self.insert_char_buffer.is_empty() {
             if let Ok(st) = String::from_utf8(self.insert_char_buffer.clone()) {
                 for c in st.chars() {
                     self.viewers[self.active].0.insert_char(c).await?;
                     self.viewers[self.active].0.completion().await?;
+                    self.viewers[self.active].0.signature_help(Some(c)).await?;
+                }
+                self.insert_char_buffer.clear();
+            }
+        }
+        Ok(())
+    }
+
+    async fn command_input(&mut self, key: Key) -> anyhow::Result<()> {
+        if !self.insert_char_buffer.is_empty() {
+            if let Key::Character(ch) = key {
+                self.insert_char_buffer.push(ch);
+            }
+            else {
+                return Err(anyhow!("bugged char?"))
+            }
+        }
+        else if key == Key::escape() || key == Key::ctrl(b'c') || (key == Key::backspace() && self.command_line.is_empty()) {
+            self.command_line.cancel();
+            self.prompt = Prompt::Command;
+            self.mode = Mode::Normal;
+        }
+        else if key == Key::char(b'\r') {
+            self.mode = Mode::Normal;
+            self.message = MessageLine::default();
+            let result = match std::mem::replace(&mut self.prompt, Prompt::Command) {
+                Prompt::Command => {
+                    let line = self.command_line.take();
+                    self.execute_command(&line).await
+                }
+                // new names are not worth keeping in the command history
+                Prompt::Rename { buffer, cursor } => {
+                    let name = self.command_line.text();
+                    self.command_line.cancel();
+                    self.rename(&buffer, cursor, name.trim()).await
+                }
+            };
+            if let Err(e) = result {
+                self.show_error(format!("{:#}", e));
+            }
+        }
+        else if key == Key::backspace() { self.command_line.backspace() }
+        else if key == Key::Delete { self.command_line.delete() }
+        else if key == Key::ctrl(b'w') { self.command_line.delete_word() }
+        else if key == Key::ctrl(b'u') { self.command_line.delete_to_start() }
+        else if key == Key::ArrowLeft { self.command_line.move_left() }
+        else if key == Key::ArrowRight { self.command_line.move_right() }
+        else if key == Key::Home || key == Key::ctrl(b'b') { self.command_line.move_home() }
+        else if key == Key::End || key == Key::ctrl(b'e') { self.command_line.move_end() }
+        else if key == Key::ArrowUp { self.command_line.history_prev() }
+        else if key == Key::ArrowDown { self.command_line.history_next() }
+        else if let Key::Character(ch) = key {
+            if ch >= 32 {
+                self.insert_char_buffer.push(ch);
+            }
+        }
+        if !self.insert_char_buffer.is_empty() {
+            if let Ok(st) = String::from_utf8(self.insert_char_buffer.clone()) {
+                for c in st.chars() {
+                    self.command_line.insert_char(c);
                 }
                 self.insert_char_buffer.clear();
             }
@@ -141,48 +1329,119 @@ impl Editor {
         Ok(())
     }
 
+    fn leave_visual(&mut self) {
+        self.viewers[self.active].0.clear_selection();
+        self.normal_prefix = None;
+        self.message = MessageLine::default();
+        self.mode = Mode::Normal;
+    }
+
+    async fn visual_input(&mut self, key: Key) -> anyhow::Result<()> {
+        if let Some(prefix) = self.normal_prefix.take() {
+            if prefix == Key::char(b'g') && key == Key::char(b'a') {
+                let selection = self.viewers[self.active].0.selection();
+                self.leave_visual();
+                if let Some(range) = selection {
+                    self.code_actions(range).await?;
+                }
+            }
+            return Ok(());
+        }
+             if key == Key::char(b'j') || key == Key::ArrowDown { self.viewers[self.active].0.move_down() }
+        else if key == Key::char(b'k') || key == Key::ArrowUp { self.viewers[self.active].0.move_up() }
+        else if key == Key::char(b'h') || key == Key::ArrowLeft { self.viewers[self.active].0.move_left() }
+        else if key == Key::char(b'l') || key == Key::ArrowRight { self.viewers[self.active].0.move_right() }
+        else if key == Key::char(b'g') { self.normal_prefix = Some(key); Ok(()) }
+        else if key == Key::char(b'=') {
+            let selection = self.viewers[self.active].0.selection();
+            self.leave_visual();
+            match selection {
+                Some(range) => self.format(Some(range)).await,
+                None => Ok(()),
+            }
+        }
+        else if key == Key::escape() || key == Key::char(b'v') { self.leave_visual(); Ok(()) }
+        else { Ok(()) }
+    }
+
+    async fn list_input(&mut self, key: Key) -> anyhow::Result<()> {
+        let Some(list) = self.location_list.as_mut() else {
+            self.mode = Mode::Normal;
+            return Ok(());
+        };
+             if key == Key::char(b'j') || key == Key::ArrowDown { list.select_next() }
+        else if key == Key::char(b'k') || key == Key::ArrowUp { list.select_prev() }
+        else if key == Key::char(b'\r') {
+            let location = list.selected().cloned();
+            self.location_list = None;
+            self.mode = Mode::Normal;
+            if let Some(location) = location {
+                self.open_location(&location).await?;
+            }
+        }
+        else if key == Key::escape() || key == Key::char(b'q') {
+            self.location_list = None;
+            self.mode = Mode::Normal;
+        }
+        Ok(())
+    }
+
     pub async fn start(&mut self) -> anyhow::Result<()> {
         self.update_all()?;
         loop {
             if let Some(key) = Key::try_read_from_stdin(&mut self.stdin)? {
-                if key == Key::ctrl(b'c') {
-                    break;
+                if key == Key::ctrl(b'c') && self.mode != Mode::Command {
+                    if self.quit_confirmed || !self.has_dirty_buffer() {
+                        break;
+                    }
+                    self.show_error("No write since last change (:w to save, Ctrl-C again or :qa! to discard)".to_owned());
+                    self.quit_confirmed = true;
+                    self.update_all()?;
+                    continue;
                 }
-                else if self.mode == Mode::Normal {
-                    self.normal_input(key).await?;
+                self.quit_confirmed = false;
+                if self.mode == Mode::Normal {
+                    if let Err(e) = self.normal_input(key).await {
+                        self.show_error(format!("{:#}", e));
+                    }
                 }
                 else if self.mode == Mode::Insert {
-                    self.insert_input(key).await?;
+                    if let Err(e) = self.insert_input(key).await {
+                        self.show_error(format!("{:#}", e));
+                    }
+                }
+                else if self.mode == Mode::Command {
+                    self.command_input(key).await?;
+                }
+                else if self.mode == Mode::List {
+                    if let Err(e) = self.list_input(key).await {
+                        self.show_error(format!("{:#}", e));
+                    }
                 }
+                else if self.mode == Mode::Visual {
+                    if let Err(e) = self.visual_input(key).await {
+                        self.show_error(format!("{:#}", e));
+                    }
+                }
+                else if self.mode == Mode::Menu {
+                    if let Err(e) = self.code_action_input(key).await {
+                        self.show_error(format!("{:#}", e));
+                    }
+                }
+            }
+            if self.quit {
+                break;
+            }
+            if let Err(e) = self.poll_lsp().await {
+                self.show_error(format!("{:#}", e));
+            }
+            if self.message.is_expired(Instant::now()) {
+                self.message = MessageLine::default();
             }
             self.update_all()?;
         }
+        // the editor is exiting anyway; a server that does not answer is not worth reporting
+        let _ = self.lsp.shutdown_all().await;
         Ok(())
     }
 }
-
-/*
-impl Editor {
-    pub fn multi_viewer_test() -> anyhow::Result<Self> {
-        let terminal = Terminal::new()?;
-        let w = terminal.width() / 2;
-        let rect1 = ViewerRect { h: terminal.height(), w, i: 0, j: 0 };
-        let rect2 = ViewerRect { h: terminal.height(), w, i: 0, j: w };
-        let buffer = Rc::new(RefCell::new(TextBuffer::open("./test.txt")?));
-        Ok(Editor {
-            _mode: RawMode::enable_raw_mode().context("enable raw mode failed")?,
-            stdin: std::io::stdin(),
-            terminal,
-            insert_char_buffer: vec![],
-            mode: Mode::Normal,
-
-            buffers: vec![buffer.clone()],
-            viewers: vec![
-                (Box::new(TextViewer::open(buffer.clone())?), rect1),
-                (Box::new(TextViewer::open(buffer.clone())?), rect2),
-            ],
-            active: 0,
-        })
-    }
-}
-*/
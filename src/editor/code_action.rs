@@ -0,0 +1,195 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use lsp_types::{CodeAction, CodeActionOrCommand, Command};
+
+use crate::{
+    buffer::{Buffer, CursorPos},
+    key::Key,
+    lsp::{capabilities::Feature, client::LspClient, method::code_action::{CodeActionFetch, CodeActionResolveFetch, ExecuteCommandFetch}},
+    viewer::{Draw, ViewerRect, code_action_viewer::CodeActionViewer},
+};
+
+use super::{Editor, Mode, Origin};
+
+/// Where a code action request is: the client is kept to resolve and run
+/// the chosen action on the server that offered it.
+pub(super) enum CodeActionState {
+    Fetching(Arc<LspClient>, CodeActionFetch, Origin),
+    Menu(Arc<LspClient>, CodeActionViewer),
+    Resolving(Arc<LspClient>, CodeActionResolveFetch),
+    Executing(ExecuteCommandFetch),
+}
+
+impl CodeActionState {
+    fn abort(self) {
+        match self {
+            CodeActionState::Fetching(_, fetch, _) => fetch.abort(),
+            CodeActionState::Menu(..) => {}
+            CodeActionState::Resolving(_, fetch) => fetch.abort(),
+            CodeActionState::Executing(fetch) => fetch.abort(),
+        }
+    }
+}
+
+impl Editor {
+    fn set_code_action(&mut self, state: CodeActionState) {
+        if let Some(old) = self.code_action.replace(state) {
+            old.abort();
+        }
+    }
+
+    /// Ask for the code actions of `range` in the active buffer.
+    pub(super) async fn code_actions(&mut self, range: (CursorPos, CursorPos)) -> anyhow::Result<()> {
+        let buffer = self.active_buffer();
+        let fetch = buffer.borrow_mut().code_actions(range)?;
+        let (Some(client), Some(fetch)) = (buffer.borrow().lsp_client().cloned(), fetch) else {
+            self.show_unsupported(&buffer, Feature::CodeAction);
+            return Ok(());
+        };
+        let origin = self.origin();
+        self.set_code_action(CodeActionState::Fetching(client, fetch, origin));
+        Ok(())
+    }
+
+    pub(super) async fn poll_code_action(&mut self) -> anyhow::Result<()> {
+        match self.code_action.take() {
+            None => {}
+            Some(CodeActionState::Fetching(client, mut fetch, origin)) => {
+                // the menu would pop up over whatever the user went on to do
+                if self.mode != Mode::Normal || !self.is_at(&origin) {
+                    fetch.abort();
+                    return Ok(());
+                }
+                let actions = match fetch.try_get_result().context("code action request failed")? {
+                    Some(result) => result.actions.clone(),
+                    None => {
+                        if fetch.is_cancelled() {
+                            return Err(anyhow!("Code action request cancelled"));
+                        }
+                        self.code_action = Some(CodeActionState::Fetching(client, fetch, origin));
+                        return Ok(());
+                    }
+                };
+                if actions.is_empty() {
+                    self.show_error("No code actions available".to_owned());
+                }
+                else {
+                    self.code_action = Some(CodeActionState::Menu(client, CodeActionViewer::new(actions)));
+                    self.mode = Mode::Menu;
+                }
+            }
+            Some(CodeActionState::Resolving(client, mut fetch)) => {
+                let action = match fetch.try_get_result().context("code action resolve failed")? {
+                    Some(action) => action.clone(),
+                    None => {
+                        if fetch.is_cancelled() {
+                            return Err(anyhow!("Code action resolve cancelled"));
+                        }
+                        self.code_action = Some(CodeActionState::Resolving(client, fetch));
+                        return Ok(());
+                    }
+                };
+                self.run_code_action(client, action).await?;
+            }
+            Some(CodeActionState::Executing(mut fetch)) => {
+                let answered = fetch.try_get_result().context("command failed")?.is_some();
+                if fetch.is_cancelled() {
+                    return Err(anyhow!("Command cancelled"));
+                }
+                if !answered {
+                    self.code_action = Some(CodeActionState::Executing(fetch));
+                }
+            }
+            Some(menu @ CodeActionState::Menu(..)) => self.code_action = Some(menu),
+        }
+        Ok(())
+    }
+
+    async fn choose_code_action(&mut self, client: Arc<LspClient>, chosen: CodeActionOrCommand) -> anyhow::Result<()> {
+        match chosen {
+            CodeActionOrCommand::Command(command) => self.execute_lsp_command(client, command).await,
+            CodeActionOrCommand::CodeAction(action) => {
+                if let Some(ref disabled) = action.disabled {
+                    return Err(anyhow!("{}", disabled.reason));
+                }
+                // servers may leave out the edit until the action is picked
+                if action.edit.is_none() && action.command.is_none() && client.supports(Feature::CodeActionResolve) {
+                    let fetch = CodeActionResolveFetch::new(&client, action)?;
+                    self.set_code_action(CodeActionState::Resolving(client, fetch));
+                    return Ok(());
+                }
+                self.run_code_action(client, action).await
+            }
+        }
+    }
+
+    /// Apply the edit of `action`, then run its command, as the specification orders.
+    async fn run_code_action(&mut self, client: Arc<LspClient>, action: CodeAction) -> anyhow::Result<()> {
+        if let Some(edit) = action.edit {
+            self.apply_workspace_edit(edit, client.position_encoding()).await.with_context(|| format!("{} failed", action.title))?;
+        }
+        match action.command {
+            Some(command) => self.execute_lsp_command(client, command).await,
+            None => {
+                self.show_message(action.title);
+                Ok(())
+            }
+        }
+    }
+
+    async fn execute_lsp_command(&mut self, client: Arc<LspClient>, command: Command) -> anyhow::Result<()> {
+        if !client.supports(Feature::ExecuteCommand) {
+            return Err(anyhow!("{} does not support {}", client.start_arg().program, Feature::ExecuteCommand.name()));
+        }
+        let title = command.title.clone();
+        let fetch = ExecuteCommandFetch::new(&client, command)?;
+        self.set_code_action(CodeActionState::Executing(fetch));
+        self.show_message(title);
+        Ok(())
+    }
+
+    pub(super) async fn code_action_input(&mut self, key: Key) -> anyhow::Result<()> {
+        let Some(CodeActionState::Menu(_, menu)) = self.code_action.as_mut() else {
+            self.mode = Mode::Normal;
+            return Ok(());
+        };
+             if key == Key::char(b'j') || key == Key::ArrowDown || key == Key::char(b'\t') { menu.select_next() }
+        else if key == Key::char(b'k') || key == Key::ArrowUp { menu.select_prev() }
+        else if key == Key::char(b'\r') {
+            let chosen = menu.selected().cloned();
+            self.mode = Mode::Normal;
+            if let (Some(CodeActionState::Menu(client, _)), Some(chosen)) = (self.code_action.take(), chosen) {
+                self.choose_code_action(client, chosen).await?;
+            }
+        }
+        else if key == Key::escape() || key == Key::char(b'q') {
+            self.code_action = None;
+            self.mode = Mode::Normal;
+        }
+        Ok(())
+    }
+
+    /// Draw the menu below the cursor, or above it when there is no room.
+    pub(super) fn draw_code_action_menu(&mut self, with_cursor: bool) -> anyhow::Result<bool> {
+        let Some(CodeActionState::Menu(_, menu)) = self.code_action.as_mut() else {
+            return Ok(false);
+        };
+        let (viewer, viewer_rect) = &mut self.viewers[self.active];
+        let (i, j) = viewer.cursor_on_screen(viewer_rect);
+        let (h, w) = menu.wanted_size();
+        let below = viewer_rect.i + viewer_rect.h - (i + 1);
+        let above = i - viewer_rect.i;
+        let (h, top) = if h <= below || below >= above { (h.min(below), i + 1) } else { (h.min(above), i - h.min(above)) };
+        if h == 0 {
+            return Ok(false);
+        }
+        let w = w.min(self.terminal.width() - j);
+        let rect = ViewerRect { h, w, i: top, j };
+        menu.draw_all(&rect, &mut self.terminal)?;
+        if with_cursor {
+            menu.draw_cursor(&rect, &mut self.terminal)?;
+        }
+        Ok(true)
+    }
+}
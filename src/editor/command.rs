@@ -0,0 +1,349 @@
+use std::{future::Future, pin::Pin};
+
+use anyhow::anyhow;
+
+use crate::buffer::history::UndoTarget;
+
+use super::Editor;
+
+pub type CommandFuture<'a> = Pin<Box<dyn Future<Output = anyhow::Result<()>> + 'a>>;
+pub type CommandHandler = for<'a> fn(&'a mut Editor, CommandArgs) -> CommandFuture<'a>;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CommandArgs {
+    pub bang: bool,
+    pub args: Vec<String>,
+}
+
+impl CommandArgs {
+    pub fn no_args(&self) -> anyhow::Result<()> {
+        match self.args.len() {
+            0 => Ok(()),
+            _ => Err(anyhow!("Trailing characters: {}", self.args.join(" "))),
+        }
+    }
+
+    pub fn optional_arg(&self) -> anyhow::Result<Option<&str>> {
+        match self.args.len() {
+            0 => Ok(None),
+            1 => Ok(Some(&self.args[0])),
+            _ => Err(anyhow!("Too many arguments")),
+        }
+    }
+}
+
+pub struct Command {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub handler: CommandHandler,
+}
+
+/// Ex commands reachable from the `:` prompt, looked up by name or alias.
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: Vec<Command>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, name: &'static str, aliases: &'static [&'static str], handler: CommandHandler) {
+        self.commands.push(Command { name, aliases, handler });
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Command> {
+        self.commands.iter().find(|c| c.name == name || c.aliases.contains(&name))
+    }
+
+    pub fn builtin() -> Self {
+        let mut registry = Self::new();
+        registry.register("write", &["w"], write);
+        registry.register("quit", &["q", "close", "clo"], quit);
+        registry.register("qall", &["qa", "quitall"], quit_all);
+        registry.register("wq", &[], write_quit);
+        registry.register("xit", &["x"], write_quit);
+        registry.register("wall", &["wa"], write_all);
+        registry.register("edit", &["e"], edit);
+        registry.register("split", &["sp"], split);
+        registry.register("references", &["refs"], references);
+        registry.register("symbols", &["sym"], symbols);
+        registry.register("rename", &[], rename);
+        registry.register("codeaction", &["ca"], code_action);
+        registry.register("format", &["fmt"], format);
+        registry.register("grep", &["gr"], grep);
+        registry.register("set", &["se"], set);
+        registry.register("undo", &["u", "un"], undo);
+        registry.register("redo", &["red"], redo);
+        registry.register("earlier", &["ea"], earlier);
+        registry.register("later", &["lat"], later);
+        registry.register("undolist", &["undol"], undolist);
+        registry.register("lsp-restart", &[], lsp_restart);
+        registry
+    }
+}
+
+/// Split `line` into a command name, a trailing `!` and whitespace separated
+/// arguments. Arguments may be quoted with `"` and use `\` escapes.
+pub fn parse_command_line(line: &str) -> anyhow::Result<(String, CommandArgs)> {
+    let line = line.trim_start();
+    let name_len = line.find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_')).unwrap_or(line.len());
+    let (name, mut rest) = line.split_at(name_len);
+    if name.is_empty() {
+        return Err(anyhow!("Not an editor command: {}", line));
+    }
+    let bang = rest.starts_with('!');
+    if bang {
+        rest = &rest[1..];
+    }
+
+    let mut args = vec![];
+    let mut chars = rest.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+        let mut arg = String::new();
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => quoted = !quoted,
+                '\\' => arg.push(chars.next().ok_or_else(|| anyhow!("Trailing \\"))?),
+                c if c.is_whitespace() && !quoted => break,
+                c => arg.push(c),
+            }
+        }
+        if quoted {
+            return Err(anyhow!("Missing closing quote"));
+        }
+        args.push(arg);
+    }
+    Ok((name.to_owned(), CommandArgs { bang, args }))
+}
+
+fn write(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        let path = args.optional_arg()?.map(|s| s.to_owned());
+        editor.write_active(args.bang, path.as_deref()).await
+    })
+}
+
+fn quit(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        args.no_args()?;
+        editor.close_active(args.bang).await
+    })
+}
+
+fn quit_all(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        args.no_args()?;
+        editor.quit_all(args.bang)
+    })
+}
+
+fn write_quit(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        let path = args.optional_arg()?.map(|s| s.to_owned());
+        editor.write_active(args.bang, path.as_deref()).await?;
+        editor.close_active(args.bang).await
+    })
+}
+
+fn write_all(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        args.no_args()?;
+        editor.write_all(args.bang).await
+    })
+}
+
+fn edit(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        let path = args.optional_arg()?.map(|s| s.to_owned());
+        editor.edit_file(path.as_deref(), args.bang).await
+    })
+}
+
+fn split(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        let path = args.optional_arg()?.map(|s| s.to_owned());
+        editor.split(path.as_deref()).await
+    })
+}
+
+fn set(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        if args.args.is_empty() {
+            return Err(anyhow!("Argument required"));
+        }
+        for arg in args.args.iter() {
+            editor.set_option(arg)?;
+        }
+        Ok(())
+    })
+}
+
+fn undo(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        args.no_args()?;
+        editor.undo().await
+    })
+}
+
+fn redo(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        args.no_args()?;
+        editor.redo().await
+    })
+}
+
+/// `N` counts states, `Ns`, `Nm`, `Nh` and `Nd` are durations. `sign` is -1 for `:earlier`.
+pub fn parse_undo_amount(arg: Option<&str>, sign: i64) -> anyhow::Result<UndoTarget> {
+    let arg = arg.unwrap_or("1");
+    let (num, unit) = match arg.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => arg.split_at(i),
+        None => (arg, ""),
+    };
+    let invalid = || anyhow!("Invalid argument: {}", arg);
+    let n = num.parse::<i64>().map_err(|_| invalid())?.checked_mul(sign).ok_or_else(invalid)?;
+    let seconds = match unit {
+        "" => return isize::try_from(n).map(UndoTarget::Steps).map_err(|_| invalid()),
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    n.checked_mul(seconds).map(UndoTarget::Seconds).ok_or_else(invalid)
+}
+
+fn earlier(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        let target = parse_undo_amount(args.optional_arg()?, -1)?;
+        editor.undo_travel(target).await
+    })
+}
+
+fn later(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        let target = parse_undo_amount(args.optional_arg()?, 1)?;
+        editor.undo_travel(target).await
+    })
+}
+
+fn undolist(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        args.no_args()?;
+        editor.show_undo_state();
+        Ok(())
+    })
+}
+
+fn lsp_restart(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        args.no_args()?;
+        editor.restart_lsp().await
+    })
+}
+
+fn references(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        args.no_args()?;
+        editor.references().await
+    })
+}
+
+/// `:rename` asks for the new name, `:rename name` uses it right away.
+fn rename(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        match args.optional_arg()? {
+            Some(name) => {
+                let buffer = editor.active_buffer();
+                let cursor = editor.viewers[editor.active].0.cursor();
+                editor.rename(&buffer, cursor, name).await
+            }
+            None => editor.start_rename().await,
+        }
+    })
+}
+
+fn code_action(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        args.no_args()?;
+        let cursor = editor.viewers[editor.active].0.cursor();
+        editor.code_actions((cursor, cursor)).await
+    })
+}
+
+fn format(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        args.no_args()?;
+        editor.format(None).await
+    })
+}
+
+/// The arguments form one query, so `:symbols foo bar` looks for "foo bar".
+fn symbols(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        editor.workspace_symbols(&args.args.join(" ")).await
+    })
+}
+
+fn grep(editor: &mut Editor, args: CommandArgs) -> CommandFuture<'_> {
+    Box::pin(async move {
+        if args.args.is_empty() {
+            return Err(anyhow!("Argument required"));
+        }
+        editor.grep(&args.args.join(" "))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::history::UndoTarget;
+
+    use super::{parse_command_line, parse_undo_amount, CommandArgs};
+
+    fn args(bang: bool, args: &[&str]) -> CommandArgs {
+        CommandArgs { bang, args: args.iter().map(|s| s.to_string()).collect() }
+    }
+
+    #[test]
+    fn name_only() {
+        assert_eq!(parse_command_line("w").unwrap(), ("w".to_owned(), args(false, &[])));
+        assert_eq!(parse_command_line("  lsp-restart ").unwrap(), ("lsp-restart".to_owned(), args(false, &[])));
+    }
+
+    #[test]
+    fn bang_and_args() {
+        assert_eq!(parse_command_line("q!").unwrap(), ("q".to_owned(), args(true, &[])));
+        assert_eq!(parse_command_line("e! foo.cpp").unwrap(), ("e".to_owned(), args(true, &["foo.cpp"])));
+        assert_eq!(parse_command_line("set ts=2  noet").unwrap(), ("set".to_owned(), args(false, &["ts=2", "noet"])));
+    }
+
+    #[test]
+    fn quotes_and_escapes() {
+        assert_eq!(parse_command_line(r#"e "a b.txt""#).unwrap(), ("e".to_owned(), args(false, &["a b.txt"])));
+        assert_eq!(parse_command_line(r"e a\ b.txt").unwrap(), ("e".to_owned(), args(false, &["a b.txt"])));
+        assert!(parse_command_line(r#"e "a b.txt"#).is_err());
+    }
+
+    #[test]
+    fn undo_amount() {
+        assert_eq!(parse_undo_amount(None, -1).unwrap(), UndoTarget::Steps(-1));
+        assert_eq!(parse_undo_amount(Some("3"), 1).unwrap(), UndoTarget::Steps(3));
+        assert_eq!(parse_undo_amount(Some("10m"), -1).unwrap(), UndoTarget::Seconds(-600));
+        assert!(parse_undo_amount(Some("2f"), -1).is_err());
+        assert!(parse_undo_amount(Some("m"), -1).is_err());
+        assert!(parse_undo_amount(Some("999999999999999999d"), -1).is_err());
+        assert!(parse_undo_amount(Some("99999999999999999999"), 1).is_err());
+    }
+
+    #[test]
+    fn not_a_command() {
+        assert!(parse_command_line("").is_err());
+        assert!(parse_command_line("!ls").is_err());
+    }
+}
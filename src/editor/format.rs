@@ -0,0 +1,99 @@
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use anyhow::{anyhow, Context};
+
+use crate::{
+    buffer::{Buffer, CursorPos, edit::{transform_all_within, Edit}, text_buffer::TextBuffer},
+    lsp::{capabilities::Feature, method::formatting::FormattingFetch},
+};
+
+use super::Editor;
+
+/// How long writing a file waits for the server to format it.
+const FORMAT_ON_SAVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A `:format` waiting for the server. The edits are only applied to the
+/// version of the text they were computed for.
+pub(super) struct PendingFormat {
+    buffer: Rc<RefCell<TextBuffer>>,
+    version: i32,
+    fetch: FormattingFetch,
+}
+
+impl Editor {
+    /// Format `range` of the active buffer, or all of it.
+    pub(super) async fn format(&mut self, range: Option<(CursorPos, CursorPos)>) -> anyhow::Result<()> {
+        let buffer = self.active_buffer();
+        let fetch = buffer.borrow_mut().formatting(range)?;
+        let Some(fetch) = fetch else {
+            self.show_unsupported(&buffer, if range.is_some() { Feature::RangeFormatting } else { Feature::Formatting });
+            return Ok(());
+        };
+        let version = buffer.borrow().version();
+        if let Some(old) = self.pending_format.replace(PendingFormat { buffer, version, fetch }) {
+            old.fetch.abort();
+        }
+        Ok(())
+    }
+
+    pub(super) async fn poll_format(&mut self) -> anyhow::Result<()> {
+        let Some(mut pending) = self.pending_format.take() else {
+            return Ok(());
+        };
+        let edits = match pending.fetch.try_get_result().context("formatting failed")? {
+            Some(edits) => edits.to_vec(),
+            None => {
+                if pending.fetch.is_cancelled() {
+                    return Err(anyhow!("Formatting cancelled"));
+                }
+                self.pending_format = Some(pending);
+                return Ok(());
+            }
+        };
+        if pending.buffer.borrow().version() != pending.version {
+            return Err(anyhow!("Buffer changed while formatting"));
+        }
+        self.apply_formatting(&pending.buffer, &edits).await
+    }
+
+    /// Apply `edits` as one undo step, keeping the cursor of every viewer
+    /// of `buffer` on the line it was on.
+    async fn apply_formatting(&mut self, buffer: &Rc<RefCell<TextBuffer>>, edits: &[Edit]) -> anyhow::Result<()> {
+        if edits.is_empty() {
+            return Ok(());
+        }
+        let cursors = self.viewers.iter().enumerate()
+            .filter(|(_, (viewer, _))| Rc::ptr_eq(viewer.buffer(), buffer))
+            .map(|(idx, (viewer, _))| (idx, transform_all_within(viewer.cursor(), edits)))
+            .collect::<Vec<_>>();
+        let cursor = cursors.first().map_or((0, 0), |&(idx, _)| self.viewers[idx].0.cursor());
+        buffer.borrow_mut().apply_edits(edits, cursor)?;
+        for (idx, cursor) in cursors {
+            self.viewers[idx].0.jump_to(cursor);
+        }
+        Ok(())
+    }
+
+    /// Format `buffer` before writing it when its language asks for it.
+    /// Returns why the file is written unformatted, if formatting failed.
+    pub(super) async fn format_before_save(&mut self, buffer: &Rc<RefCell<TextBuffer>>) -> anyhow::Result<Option<String>> {
+        let Some(language) = buffer.borrow().language() else {
+            return Ok(None);
+        };
+        if !self.format_on_save.contains(&language.name) {
+            return Ok(None);
+        }
+        let fetch = buffer.borrow_mut().formatting(None)?;
+        let Some(fetch) = fetch else {
+            return Ok(None);
+        };
+        match fetch.await_within(FORMAT_ON_SAVE_TIMEOUT).await {
+            Ok(Some(edits)) => {
+                self.apply_formatting(buffer, &edits).await?;
+                Ok(None)
+            }
+            Ok(None) => Ok(Some("formatting timed out".to_owned())),
+            Err(e) => Ok(Some(format!("formatting failed: {:#}", e))),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::Path;
+
+use crate::{lsp::method::goto::Location, viewer::location_list_viewer::ListItem};
+
+/// Stop searching after this many matches.
+pub const MAX_MATCHES: usize = 1000;
+
+/// Directories not worth searching: hidden ones such as `.git`, and build output.
+fn skip_dir(name: &str) -> bool {
+    name.starts_with('.') || name == "target" || name == "node_modules"
+}
+
+/// Every line under `root` containing `pattern`, in path order, skipping
+/// files that are not UTF-8 text.
+pub fn grep(root: &Path, pattern: &str) -> std::io::Result<Vec<ListItem>> {
+    let mut items = vec![];
+    grep_dir(root, pattern, &mut items)?;
+    Ok(items)
+}
+
+fn grep_dir(dir: &Path, pattern: &str, items: &mut Vec<ListItem>) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        if items.len() >= MAX_MATCHES {
+            break;
+        }
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if !entry.file_name().to_str().is_some_and(skip_dir) {
+                grep_dir(&path, pattern, items)?;
+            }
+        }
+        else if file_type.is_file() {
+            let Ok(text) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            for (i, line) in text.lines().enumerate() {
+                if let Some(byte) = line.find(pattern) {
+                    let pos = (i, line[..byte].chars().count());
+                    items.push(ListItem { location: Location { path: path.clone(), pos }, text: line.to_owned() });
+                    if items.len() >= MAX_MATCHES {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::grep;
+
+    #[test]
+    fn finds_lines() {
+        let base = std::env::temp_dir().join(format!("editor-grep-{}", std::process::id()));
+        std::fs::create_dir_all(base.join("src")).unwrap();
+        std::fs::create_dir_all(base.join(".git")).unwrap();
+        std::fs::write(base.join("src/a.rs"), "fn main() {\n    let ñ = foo();\n}\n").unwrap();
+        std::fs::write(base.join("b.txt"), "foo\nbar\n").unwrap();
+        std::fs::write(base.join(".git/config"), "foo\n").unwrap();
+        std::fs::write(base.join("bin"), [0xff, 0xfe, b'f', b'o', b'o']).unwrap();
+
+        let found = grep(&base, "foo").unwrap().into_iter()
+            .map(|item| (item.location.path.strip_prefix(&base).unwrap().to_owned(), item.location.pos, item.text))
+            .collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            ("b.txt".into(), (0, 0), "foo".to_owned()),
+            ("src/a.rs".into(), (1, 12), "    let ñ = foo();".to_owned()),
+        ]);
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
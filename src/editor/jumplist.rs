@@ -0,0 +1,78 @@
+/// Positions jumped away from, walked with Ctrl-O and Ctrl-I.
+pub struct JumpList<T> {
+    entries: Vec<T>,
+    /// `entries.len()` while not walking the list.
+    idx: usize,
+}
+
+impl<T> Default for JumpList<T> {
+    fn default() -> Self {
+        Self { entries: vec![], idx: 0 }
+    }
+}
+
+impl<T: Clone + PartialEq> JumpList<T> {
+    /// Remember `from` before a jump. Entries after the current one are dropped.
+    pub fn push(&mut self, from: T) {
+        self.entries.truncate(self.idx);
+        if self.entries.last() != Some(&from) {
+            self.entries.push(from);
+        }
+        self.idx = self.entries.len();
+    }
+
+    /// The entry before the current one. `current` is remembered so that
+    /// `forward` can return to it.
+    pub fn back(&mut self, current: T) -> Option<T> {
+        if self.idx == self.entries.len() && !self.entries.is_empty() {
+            if self.entries.last() != Some(&current) {
+                self.entries.push(current);
+            }
+            self.idx = self.entries.len() - 1;
+        }
+        if self.idx == 0 {
+            return None;
+        }
+        self.idx -= 1;
+        Some(self.entries[self.idx].clone())
+    }
+
+    pub fn forward(&mut self) -> Option<T> {
+        if self.idx + 1 >= self.entries.len() {
+            return None;
+        }
+        self.idx += 1;
+        Some(self.entries[self.idx].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JumpList;
+
+    #[test]
+    fn back_and_forward() {
+        let mut jumps = JumpList::default();
+        assert_eq!(jumps.back(0), None);
+        jumps.push(1);
+        jumps.push(2);
+        assert_eq!(jumps.back(3), Some(2));
+        assert_eq!(jumps.back(2), Some(1));
+        assert_eq!(jumps.back(1), None);
+        assert_eq!(jumps.forward(), Some(2));
+        assert_eq!(jumps.forward(), Some(3));
+        assert_eq!(jumps.forward(), None);
+    }
+
+    #[test]
+    fn push_drops_newer_entries() {
+        let mut jumps = JumpList::default();
+        jumps.push(1);
+        jumps.push(2);
+        assert_eq!(jumps.back(3), Some(2));
+        assert_eq!(jumps.back(2), Some(1));
+        jumps.push(1);
+        assert_eq!(jumps.forward(), None);
+        assert_eq!(jumps.back(5), Some(1));
+    }
+}
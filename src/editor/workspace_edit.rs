@@ -0,0 +1,137 @@
+use std::{cell::RefCell, collections::HashSet, path::Path, rc::Rc};
+
+use anyhow::{anyhow, bail, Context};
+use lsp_types::{Position, WorkspaceEdit};
+
+use crate::{buffer::{Buffer, CursorPos, text_buffer::TextBuffer}, lsp::{position::{PositionEncoding, Positions}, workspace_edit::{operations, EditOp}}};
+
+use super::Editor;
+
+fn path_str(path: &Path) -> anyhow::Result<&str> {
+    path.to_str().ok_or_else(|| anyhow!("not a UTF-8 path: {}", path.display()))
+}
+
+fn create_parent(path: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+impl Editor {
+    /// Apply a `WorkspaceEdit` from a server, opening the files it edits
+    /// that have no buffer yet. Each buffer gets one undo step. Returns the
+    /// number of files touched. Columns count units of `encoding`.
+    pub(super) async fn apply_workspace_edit(&mut self, edit: WorkspaceEdit, encoding: PositionEncoding) -> anyhow::Result<usize> {
+        let ops = operations(edit)?;
+        // refuse the whole edit rather than leave it half done
+        for op in ops.iter() {
+            if let EditOp::Edit { path, version: Some(version), .. } = op {
+                if let Some(buffer) = self.find_buffer(path_str(path)?) {
+                    if buffer.borrow().version() != *version {
+                        bail!("{} changed since the edit was computed", path.display());
+                    }
+                }
+            }
+        }
+        let files = ops.iter().map(|op| match op {
+            EditOp::Edit { path, .. } | EditOp::Create { path, .. } | EditOp::Delete { path, .. } => path,
+            EditOp::Rename { to, .. } => to,
+        }).collect::<HashSet<_>>().len();
+        for op in ops {
+            self.apply_edit_op(op, encoding).await?;
+        }
+        for (viewer, _) in self.viewers.iter_mut() {
+            let cursor = viewer.cursor();
+            viewer.jump_to(cursor);
+        }
+        Ok(files)
+    }
+
+    async fn apply_edit_op(&mut self, op: EditOp, encoding: PositionEncoding) -> anyhow::Result<()> {
+        match op {
+            EditOp::Edit { path, edits, .. } => {
+                let buffer = self.open_buffer(path_str(&path)?).await?;
+                let positions = Positions::new(buffer.borrow().rope_clone(), encoding);
+                let unit = |pos: CursorPos| positions.from_lsp(Position::new(pos.0 as u32, pos.1 as u32));
+                let edits = edits.into_iter().map(|(start, end, text)| (unit(start), unit(end), text)).collect::<Vec<_>>();
+                let viewer = self.viewers.iter().position(|(viewer, _)| Rc::ptr_eq(viewer.buffer(), &buffer));
+                let cursor = match viewer {
+                    Some(idx) => self.viewers[idx].0.cursor(),
+                    None => edits.first().map_or((0, 0), |e| e.0),
+                };
+                let moved = buffer.borrow_mut().apply_edits(&edits, cursor)
+                    .with_context(|| format!("cannot edit {}", path.display()))?;
+                // a command run next, or the server that asked for the edit, expects to see it
+                buffer.borrow_mut().flush_changes()?;
+                // keep the cursor on the same text
+                if let Some(idx) = viewer {
+                    self.viewers[idx].0.jump_to(moved);
+                }
+            }
+            EditOp::Create { path, overwrite, ignore_if_exists } => {
+                if path.exists() && !overwrite {
+                    if ignore_if_exists {
+                        return Ok(());
+                    }
+                    bail!("{} already exists", path.display());
+                }
+                create_parent(&path)?;
+                std::fs::write(&path, "").with_context(|| format!("cannot create {}", path.display()))?;
+                if let Some(buffer) = self.find_buffer(path_str(&path)?) {
+                    let end = buffer.borrow().end_pos();
+                    buffer.borrow_mut().apply_edits(&[((0, 0), end, String::new())], (0, 0))?;
+                }
+            }
+            EditOp::Rename { from, to, overwrite, ignore_if_exists } => {
+                if to.exists() && !overwrite {
+                    if ignore_if_exists {
+                        return Ok(());
+                    }
+                    bail!("{} already exists", to.display());
+                }
+                create_parent(&to)?;
+                std::fs::rename(&from, &to).with_context(|| format!("cannot rename {} to {}", from.display(), to.display()))?;
+                if let Some(buffer) = self.find_buffer(path_str(&from)?) {
+                    self.rename_buffer(&buffer, path_str(&to)?).await?;
+                }
+            }
+            EditOp::Delete { path, recursive, ignore_if_not_exists } => {
+                if !path.exists() {
+                    if ignore_if_not_exists {
+                        return Ok(());
+                    }
+                    bail!("{} does not exist", path.display());
+                }
+                let removed = match (path.is_dir(), recursive) {
+                    (true, true) => std::fs::remove_dir_all(&path),
+                    (true, false) => std::fs::remove_dir(&path),
+                    _ => std::fs::remove_file(&path),
+                };
+                removed.with_context(|| format!("cannot delete {}", path.display()))?;
+                // the buffers stay open with their text, as after deleting a file from a shell
+                let deleted = self.buffers.iter()
+                    .filter(|buffer| buffer.borrow().filename().and_then(|name| std::path::absolute(name).ok()).is_some_and(|name| name.starts_with(&path)))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for buffer in deleted {
+                    self.detach_lsp(&buffer).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Follow a file moved on disk: the server sees it closed under the old
+    /// name and opened under the new one.
+    async fn rename_buffer(&mut self, buffer: &Rc<RefCell<TextBuffer>>, to: &str) -> anyhow::Result<()> {
+        let old = buffer.borrow_mut().detach_lsp()?;
+        buffer.borrow_mut().set_filename(to);
+        // acquire before release so that a shared server is not restarted
+        self.attach_lsp(buffer).await?;
+        if let Some(old) = old {
+            self.lsp.release(&old).await?;
+        }
+        Ok(())
+    }
+}
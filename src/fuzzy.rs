@@ -0,0 +1,115 @@
+//! Fuzzy matching of a typed pattern against candidate names.
+
+/// How well a pattern matches a text and which characters of the text it matched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub score: i64,
+    /// Char indices into the text, ascending.
+    pub positions: Vec<usize>,
+}
+
+const MATCH: i64 = 16;
+/// A match at the start of a word, after `_`, `.` or `::`, or at a camelCase hump.
+const BOUNDARY: i64 = 8;
+const CONSECUTIVE: i64 = 6;
+const SAME_CASE: i64 = 1;
+const GAP: i64 = 1;
+
+fn is_boundary(text: &[char], j: usize) -> bool {
+    j == 0 || !text[j - 1].is_alphanumeric() || (text[j - 1].is_lowercase() && text[j].is_uppercase())
+}
+
+/// Match `pattern` against `text`: every character of the pattern has to
+/// appear in order, ignoring case. Among the ways it can, the best scored
+/// one is chosen, preferring matches at word starts and in runs.
+pub fn fuzzy_match(pattern: &str, text: &str) -> Option<Match> {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    if pattern.is_empty() {
+        return Some(Match { score: 0, positions: vec![] });
+    }
+    let (m, n) = (pattern.len(), text.len());
+    if m > n {
+        return None;
+    }
+    let same = |a: char, b: char| a == b || a.to_lowercase().eq(b.to_lowercase());
+    // best[i][j]: score of matching pattern[..=i] with pattern[i] at text[j]
+    let mut best = vec![vec![None::<i64>; n]; m];
+    // from[i][j]: where pattern[i - 1] is matched in that case
+    let mut from = vec![vec![0; n]; m];
+    for i in 0..m {
+        // best score of pattern[..i] ending before j, less the gap up to j
+        let mut carry = None::<(i64, usize)>;
+        for j in i..n {
+            if i > 0 && j >= 2 {
+                let prev = best[i - 1][j - 2].map(|score| (score, j - 2));
+                carry = match (carry, prev) {
+                    (Some((c, k)), Some((p, _))) if c - GAP >= p => Some((c - GAP, k)),
+                    (Some((c, k)), None) => Some((c - GAP, k)),
+                    (_, prev) => prev,
+                };
+            }
+            if !same(pattern[i], text[j]) {
+                continue;
+            }
+            let bonus = MATCH
+                + if is_boundary(&text, j) { BOUNDARY } else { 0 }
+                + if pattern[i] == text[j] { SAME_CASE } else { 0 };
+            if i == 0 {
+                // leading characters cost a little, so that prefixes win
+                best[0][j] = Some(bonus - (j as i64).min(3) * GAP);
+                continue;
+            }
+            let consecutive = best[i - 1][j - 1].map(|score| (score + CONSECUTIVE, j - 1));
+            let gap = carry.map(|(score, k)| (score - GAP, k));
+            let choice = match (consecutive, gap) {
+                (Some(c), Some(g)) => Some(if c.0 >= g.0 { c } else { g }),
+                (c, g) => c.or(g),
+            };
+            if let Some((score, k)) = choice {
+                best[i][j] = Some(score + bonus);
+                from[i][j] = k;
+            }
+        }
+    }
+    let (mut j, score) = (0..n).filter_map(|j| Some((j, best[m - 1][j]?))).max_by_key(|&(j, score)| (score, std::cmp::Reverse(j)))?;
+    let mut positions = vec![0; m];
+    for i in (0..m).rev() {
+        positions[i] = j;
+        j = from[i][j];
+    }
+    Some(Match { score, positions })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fuzzy_match;
+
+    fn positions(pattern: &str, text: &str) -> Option<Vec<usize>> {
+        fuzzy_match(pattern, text).map(|m| m.positions)
+    }
+
+    fn score(pattern: &str, text: &str) -> i64 {
+        fuzzy_match(pattern, text).unwrap().score
+    }
+
+    #[test]
+    fn matches() {
+        assert_eq!(positions("", "abc"), Some(vec![]));
+        assert_eq!(positions("abc", "ab"), None);
+        assert_eq!(positions("ba", "abc"), None);
+        assert_eq!(positions("fb", "foo_bar"), Some(vec![0, 4]));
+        assert_eq!(positions("gt", "getText"), Some(vec![0, 3]));
+        assert_eq!(positions("PB", "push_back"), Some(vec![0, 5]));
+        // the run is preferred over the scattered letters that come first
+        assert_eq!(positions("bar", "bxaxrbar"), Some(vec![5, 6, 7]));
+    }
+
+    #[test]
+    fn ranking() {
+        assert!(score("get", "getText") > score("get", "forget"));
+        assert!(score("size", "size") > score("size", "resize"));
+        assert!(score("vs", "vector_size") > score("vs", "visit"));
+        assert!(score("push", "push_back") > score("push", "PushBack"));
+    }
+}
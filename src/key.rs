@@ -13,6 +13,8 @@ pub enum Key {
     End,
     PageUp,
     PageDown,
+    /// Shift-Tab.
+    BackTab,
 }
 
 impl Key {
@@ -78,6 +80,7 @@ fn read_escape_sequence(stdin: &mut Stdin) -> anyhow::Result<Key> {
                     b'D' => Key::ArrowLeft,
                     b'H' => Key::Home,
                     b'F' => Key::End,
+                    b'Z' => Key::BackTab,
                     _ => Key::Character(b'\x1b'),
                 }
             )
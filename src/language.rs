@@ -0,0 +1,216 @@
+use std::path::{Path, PathBuf};
+
+/// How files of one language are recognised and which server handles them.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Language {
+    pub name: &'static str,
+    /// `languageId` sent in `textDocument/didOpen`.
+    pub language_id: &'static str,
+    pub extensions: &'static [&'static str],
+    pub filenames: &'static [&'static str],
+    /// Interpreters recognised on a `#!` line.
+    pub interpreters: &'static [&'static str],
+    /// Language server command line. Files without one open without LSP.
+    pub server: Option<&'static str>,
+    /// Files or directories marking the workspace root handed to the server.
+    pub root_markers: &'static [&'static str],
+}
+
+pub static LANGUAGES: &[Language] = &[
+    Language {
+        name: "c",
+        language_id: "c",
+        extensions: &["c", "h"],
+        filenames: &[],
+        interpreters: &[],
+        server: Some("clangd"),
+        root_markers: &["compile_commands.json", "compile_flags.txt", ".clangd", ".git"],
+    },
+    Language {
+        name: "cpp",
+        language_id: "cpp",
+        extensions: &["cpp", "cc", "cxx", "c++", "hpp", "hh", "hxx", "inl"],
+        filenames: &[],
+        interpreters: &[],
+        server: Some("clangd"),
+        root_markers: &["compile_commands.json", "compile_flags.txt", ".clangd", ".git"],
+    },
+    Language {
+        name: "rust",
+        language_id: "rust",
+        extensions: &["rs"],
+        filenames: &[],
+        interpreters: &[],
+        server: Some("rust-analyzer"),
+        root_markers: &["Cargo.toml", ".git"],
+    },
+    Language {
+        name: "python",
+        language_id: "python",
+        extensions: &["py", "pyi"],
+        filenames: &[],
+        interpreters: &["python", "python3"],
+        server: Some("pyright-langserver --stdio"),
+        root_markers: &["pyproject.toml", "setup.py", "setup.cfg", "pyrightconfig.json", ".git"],
+    },
+    Language {
+        name: "go",
+        language_id: "go",
+        extensions: &["go"],
+        filenames: &[],
+        interpreters: &[],
+        server: Some("gopls"),
+        root_markers: &["go.mod", ".git"],
+    },
+    Language {
+        name: "javascript",
+        language_id: "javascript",
+        extensions: &["js", "mjs", "cjs"],
+        filenames: &[],
+        interpreters: &["node"],
+        server: Some("typescript-language-server --stdio"),
+        root_markers: &["package.json", "jsconfig.json", ".git"],
+    },
+    Language {
+        name: "typescript",
+        language_id: "typescript",
+        extensions: &["ts", "mts", "cts"],
+        filenames: &[],
+        interpreters: &[],
+        server: Some("typescript-language-server --stdio"),
+        root_markers: &["package.json", "tsconfig.json", ".git"],
+    },
+    Language {
+        name: "sh",
+        language_id: "shellscript",
+        extensions: &["sh", "bash"],
+        filenames: &[".bashrc", ".bash_profile", ".profile"],
+        interpreters: &["sh", "bash"],
+        server: Some("bash-language-server start"),
+        root_markers: &[".git"],
+    },
+    Language {
+        name: "toml",
+        language_id: "toml",
+        extensions: &["toml"],
+        filenames: &["Cargo.lock"],
+        interpreters: &[],
+        server: Some("taplo lsp stdio"),
+        root_markers: &[".taplo.toml", ".git"],
+    },
+    Language {
+        name: "markdown",
+        language_id: "markdown",
+        extensions: &["md", "markdown"],
+        filenames: &[],
+        interpreters: &[],
+        server: None,
+        root_markers: &[],
+    },
+    Language {
+        name: "make",
+        language_id: "makefile",
+        extensions: &["mk"],
+        filenames: &["Makefile", "makefile", "GNUmakefile"],
+        interpreters: &["make"],
+        server: None,
+        root_markers: &[],
+    },
+    Language {
+        name: "cmake",
+        language_id: "cmake",
+        extensions: &["cmake"],
+        filenames: &["CMakeLists.txt"],
+        interpreters: &[],
+        server: None,
+        root_markers: &[],
+    },
+];
+
+/// The nearest directory above `path` containing one of `markers`, or the
+/// nearest existing directory of `path` when there is none.
+pub fn find_root(path: &Path, markers: &[&str]) -> std::io::Result<PathBuf> {
+    let path = std::path::absolute(path)?;
+    let dir = path.parent().unwrap_or(&path);
+    Ok(dir.ancestors()
+        .find(|dir| markers.iter().any(|marker| dir.join(marker).exists()))
+        .or_else(|| dir.ancestors().find(|dir| dir.is_dir()))
+        .unwrap_or(dir)
+        .to_path_buf())
+}
+
+/// The interpreter named on a `#!` line, looking through `env`.
+fn shebang_interpreter(first_line: &str) -> Option<&str> {
+    let mut words = first_line.strip_prefix("#!")?.split_whitespace();
+    let program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        words.find(|w| !w.starts_with('-') && !w.contains('='))
+    }
+    else {
+        Some(program)
+    }
+}
+
+/// Detect the language by file name, then extension, then the `#!` line.
+pub fn detect(path: Option<&Path>, first_line: &str) -> Option<&'static Language> {
+    if let Some(path) = path {
+        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+            if let Some(lang) = LANGUAGES.iter().find(|lang| lang.filenames.contains(&name)) {
+                return Some(lang);
+            }
+        }
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            if let Some(lang) = LANGUAGES.iter().find(|lang| lang.extensions.contains(&ext)) {
+                return Some(lang);
+            }
+        }
+    }
+    let interpreter = shebang_interpreter(first_line)?;
+    // `python3.12` is still python3
+    let unversioned = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    LANGUAGES.iter().find(|lang| lang.interpreters.contains(&interpreter) || lang.interpreters.contains(&unversioned))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{detect, find_root};
+
+    fn name(path: Option<&str>, first_line: &str) -> Option<&'static str> {
+        detect(path.map(Path::new), first_line).map(|lang| lang.name)
+    }
+
+    #[test]
+    fn by_path() {
+        assert_eq!(name(Some("src/main.rs"), ""), Some("rust"));
+        assert_eq!(name(Some("a/b.hpp"), ""), Some("cpp"));
+        assert_eq!(name(Some("x.h"), ""), Some("c"));
+        assert_eq!(name(Some("dir/Makefile"), ""), Some("make"));
+        assert_eq!(name(Some("notes.txt"), ""), None);
+    }
+
+    #[test]
+    fn by_shebang() {
+        assert_eq!(name(Some("script"), "#!/usr/bin/env python3"), Some("python"));
+        assert_eq!(name(None, "#!/usr/bin/python3.12 -u"), Some("python"));
+        assert_eq!(name(None, "#!/usr/bin/env -S bash -e"), Some("sh"));
+        assert_eq!(name(None, "#!/bin/sh"), Some("sh"));
+        assert_eq!(name(None, "# not a shebang"), None);
+        // the extension wins over the shebang
+        assert_eq!(name(Some("run.rs"), "#!/bin/sh"), Some("rust"));
+    }
+
+    #[test]
+    fn root_by_marker() {
+        let base = std::env::temp_dir().join(format!("editor-root-{}", std::process::id()));
+        std::fs::create_dir_all(base.join("proj/src/deep")).unwrap();
+        std::fs::write(base.join("proj/Cargo.toml"), "").unwrap();
+        let file = base.join("proj/src/deep/main.rs");
+        assert_eq!(find_root(&file, &["Cargo.toml"]).unwrap(), base.join("proj"));
+        assert_eq!(find_root(&file, &["no-such-marker"]).unwrap(), base.join("proj/src/deep"));
+        // a new file in a directory that does not exist yet
+        assert_eq!(find_root(&base.join("proj/new/dir/a.rs"), &["no-such-marker"]).unwrap(), base.join("proj"));
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
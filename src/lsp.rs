@@ -1,3 +1,8 @@
 pub mod client;
 pub mod msg;
+pub mod dispatch;
 pub mod method;
+pub mod manager;
+pub mod workspace_edit;
+pub mod capabilities;
+pub mod position;
@@ -0,0 +1,151 @@
+//! What the editor tells the server it can do, and which of the server's
+//! features the editor may use.
+
+use lsp_types::{ClientCapabilities, CodeActionProviderCapability, DeclarationCapability, GeneralClientCapabilities, HoverProviderCapability, ImplementationProviderCapability, OneOf, ServerCapabilities, TextDocumentClientCapabilities, TypeDefinitionProviderCapability, WindowClientCapabilities, WorkspaceClientCapabilities};
+
+use super::{dispatch, method, position, workspace_edit};
+
+/// A request the editor sends only when the server announced it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Hover,
+    Completion,
+    CompletionResolve,
+    SignatureHelp,
+    Definition,
+    Declaration,
+    TypeDefinition,
+    Implementation,
+    References,
+    Rename,
+    PrepareRename,
+    CodeAction,
+    CodeActionResolve,
+    ExecuteCommand,
+    Formatting,
+    RangeFormatting,
+    WorkspaceSymbol,
+}
+
+fn enabled<T>(provider: &Option<OneOf<bool, T>>) -> bool {
+    matches!(provider, Some(OneOf::Left(true) | OneOf::Right(_)))
+}
+
+impl Feature {
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Hover => "hover",
+            Feature::Completion => "completion",
+            Feature::CompletionResolve => "resolving completions",
+            Feature::SignatureHelp => "signature help",
+            Feature::Definition => "go to definition",
+            Feature::Declaration => "go to declaration",
+            Feature::TypeDefinition => "go to type definition",
+            Feature::Implementation => "go to implementation",
+            Feature::References => "references",
+            Feature::Rename => "rename",
+            Feature::PrepareRename => "prepare rename",
+            Feature::CodeAction => "code actions",
+            Feature::CodeActionResolve => "resolving code actions",
+            Feature::ExecuteCommand => "commands",
+            Feature::Formatting => "formatting",
+            Feature::RangeFormatting => "range formatting",
+            Feature::WorkspaceSymbol => "workspace symbols",
+        }
+    }
+
+    pub fn is_supported(self, caps: &ServerCapabilities) -> bool {
+        match self {
+            Feature::Hover => matches!(caps.hover_provider, Some(HoverProviderCapability::Simple(true) | HoverProviderCapability::Options(_))),
+            Feature::Completion => caps.completion_provider.is_some(),
+            Feature::CompletionResolve => caps.completion_provider.as_ref().and_then(|provider| provider.resolve_provider) == Some(true),
+            Feature::SignatureHelp => caps.signature_help_provider.is_some(),
+            Feature::Definition => enabled(&caps.definition_provider),
+            Feature::Declaration => !matches!(caps.declaration_provider, None | Some(DeclarationCapability::Simple(false))),
+            Feature::TypeDefinition => !matches!(caps.type_definition_provider, None | Some(TypeDefinitionProviderCapability::Simple(false))),
+            Feature::Implementation => !matches!(caps.implementation_provider, None | Some(ImplementationProviderCapability::Simple(false))),
+            Feature::References => enabled(&caps.references_provider),
+            Feature::Rename => enabled(&caps.rename_provider),
+            Feature::PrepareRename => matches!(caps.rename_provider, Some(OneOf::Right(ref options)) if options.prepare_provider == Some(true)),
+            Feature::CodeAction => matches!(caps.code_action_provider, Some(CodeActionProviderCapability::Simple(true) | CodeActionProviderCapability::Options(_))),
+            Feature::CodeActionResolve => matches!(caps.code_action_provider, Some(CodeActionProviderCapability::Options(ref options)) if options.resolve_provider == Some(true)),
+            Feature::ExecuteCommand => caps.execute_command_provider.is_some(),
+            Feature::Formatting => enabled(&caps.document_formatting_provider),
+            Feature::RangeFormatting => enabled(&caps.document_range_formatting_provider),
+            Feature::WorkspaceSymbol => enabled(&caps.workspace_symbol_provider),
+        }
+    }
+}
+
+pub fn text_document(caps: &mut ClientCapabilities) -> &mut TextDocumentClientCapabilities {
+    caps.text_document.get_or_insert_with(Default::default)
+}
+
+pub fn workspace(caps: &mut ClientCapabilities) -> &mut WorkspaceClientCapabilities {
+    caps.workspace.get_or_insert_with(Default::default)
+}
+
+pub fn window(caps: &mut ClientCapabilities) -> &mut WindowClientCapabilities {
+    caps.window.get_or_insert_with(Default::default)
+}
+
+pub fn general(caps: &mut ClientCapabilities) -> &mut GeneralClientCapabilities {
+    caps.general.get_or_insert_with(Default::default)
+}
+
+/// The capabilities sent in `initialize`, each part declared by the module
+/// that implements it.
+pub fn client_capabilities() -> ClientCapabilities {
+    let mut caps = ClientCapabilities::default();
+    position::declare(&mut caps);
+    method::save::declare(&mut caps);
+    method::hover::declare(&mut caps);
+    method::completion::declare(&mut caps);
+    method::signature_help::declare(&mut caps);
+    method::goto::declare(&mut caps);
+    method::references::declare(&mut caps);
+    method::rename::declare(&mut caps);
+    method::code_action::declare(&mut caps);
+    method::formatting::declare(&mut caps);
+    method::symbols::declare(&mut caps);
+    workspace_edit::declare(&mut caps);
+    dispatch::declare(&mut caps);
+    caps
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{CompletionOptions, OneOf, RenameOptions, ServerCapabilities};
+
+    use super::{client_capabilities, Feature};
+
+    #[test]
+    fn server_features() {
+        let caps = ServerCapabilities {
+            completion_provider: Some(CompletionOptions::default()),
+            definition_provider: Some(OneOf::Left(true)),
+            references_provider: Some(OneOf::Left(false)),
+            rename_provider: Some(OneOf::Right(RenameOptions { prepare_provider: Some(true), work_done_progress_options: Default::default() })),
+            ..Default::default()
+        };
+        assert!(Feature::Completion.is_supported(&caps));
+        assert!(!Feature::CompletionResolve.is_supported(&caps));
+        assert!(Feature::Definition.is_supported(&caps));
+        assert!(!Feature::References.is_supported(&caps));
+        assert!(!Feature::Hover.is_supported(&caps));
+        assert!(Feature::PrepareRename.is_supported(&caps));
+        let caps = ServerCapabilities { rename_provider: Some(OneOf::Left(true)), ..Default::default() };
+        assert!(Feature::Rename.is_supported(&caps));
+        assert!(!Feature::PrepareRename.is_supported(&caps));
+    }
+
+    #[test]
+    fn declared() {
+        let caps = client_capabilities();
+        let text_document = caps.text_document.unwrap();
+        let item = text_document.completion.unwrap().completion_item.unwrap();
+        assert_eq!(item.snippet_support, Some(true));
+        assert_eq!(text_document.rename.unwrap().prepare_support, Some(true));
+        assert_eq!(caps.workspace.unwrap().workspace_edit.unwrap().document_changes, Some(true));
+    }
+}
@@ -1,73 +1,152 @@
+use super::capabilities::{self, Feature};
+use super::position::PositionEncoding;
+use super::dispatch::{handle_request, parse_notification, Reply, ServerEvent};
 use super::msg::{Message, Notification, Request, RequestId, Response, ResponseError};
 use anyhow::{anyhow, Context};
 
-use lsp_types::ServerCapabilities;
-use tokio::{sync::{Mutex, Notify, mpsc::{ self, Receiver, Sender }}, task::JoinHandle};
+use lsp_types::{ServerCapabilities, notification::{Cancel, Notification as _}};
+use tokio::{sync::{Mutex, Notify, mpsc::{ self, Receiver, UnboundedReceiver, UnboundedSender }}, task::JoinHandle};
 
-use std::{collections::HashMap, str::FromStr, sync::Arc};
+use std::{collections::HashMap, path::PathBuf, str::FromStr, sync::{Arc, MutexGuard, PoisonError, atomic::{AtomicBool, AtomicI32, Ordering}}, time::Duration};
+
+/// Where the responses go, by the id of the request they answer.
+type ResponseSenders = Arc<std::sync::Mutex<HashMap<RequestId, tokio::sync::oneshot::Sender<Response>>>>;
+
+/// Nothing is left half done while the senders are locked, so a panic
+/// elsewhere does not make them unusable.
+fn lock_senders(senders: &ResponseSenders) -> MutexGuard<'_, HashMap<RequestId, tokio::sync::oneshot::Sender<Response>>> {
+    senders.lock().unwrap_or_else(PoisonError::into_inner)
+}
 
 pub struct LspClient {
-    lsp_process_child: tokio::process::Child,
+    lsp_process_child: Mutex<tokio::process::Child>,
     from_server_thread: tokio::task::JoinHandle<anyhow::Result<()>>,
-    from_server_receiver: Receiver<Message>,
+    from_server_receiver: std::sync::Mutex<UnboundedReceiver<ServerEvent>>,
     to_server_thread: tokio::task::JoinHandle<anyhow::Result<()>>,
-    to_server_sender: Sender<Message>,
+    to_server_sender: UnboundedSender<Message>,
 
-    response_senders: Arc<Mutex<HashMap<RequestId, tokio::sync::oneshot::Sender<Response>>>>,
+    response_senders: ResponseSenders,
 
     server_capabilities: ServerCapabilities,
+    position_encoding: PositionEncoding,
 
-    id_cnt: Mutex<i32>,
+    id_cnt: AtomicI32,
+
+    start_arg: LspClientStartArg,
+    /// Set once `shutdown` starts so that the exit is not taken for a crash.
+    shutting_down: AtomicBool,
 }
 
+#[derive(Debug, Clone)]
 pub struct LspClientStartArg {
     pub program: String,
+    pub args: Vec<String>,
+    /// Workspace root sent in `initialize`.
+    pub root: PathBuf,
+}
+
+impl LspClientStartArg {
+    /// Split a command line such as `"clangd --log=verbose"` into words.
+    pub fn from_command_line(command: &str, root: PathBuf) -> anyhow::Result<Self> {
+        let mut words = split_command(command)?.into_iter();
+        let program = words.next().ok_or_else(|| anyhow!("empty language server command"))?;
+        Ok(Self { program, args: words.collect(), root })
+    }
+}
+
+/// Split `command` into words as a shell does: `'...'` is taken literally,
+/// and elsewhere, also inside `"..."`, `\` escapes the next character.
+pub fn split_command(command: &str) -> anyhow::Result<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => word.get_or_insert_default().push(c),
+            (_, '\\') => word.get_or_insert_default().push(chars.next().ok_or_else(|| anyhow!("trailing \\ in {}", command))?),
+            (Some(_), c) => word.get_or_insert_default().push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_default().push(c),
+        }
+    }
+    if let Some(q) = quote {
+        return Err(anyhow!("missing closing {} in {}", q, command));
+    }
+    words.extend(word);
+    Ok(words)
 }
 
 impl LspClient {
     pub async fn start(start_arg: LspClientStartArg) -> anyhow::Result<Self> {
-        let mut child = tokio::process::Command::new(start_arg.program)
-            //.arg("--log=verbose")
+        let mut child = tokio::process::Command::new(&start_arg.program)
+            .args(&start_arg.args)
+            .current_dir(&start_arg.root)
             .stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
             .stderr(std::io::stderr())
+            .kill_on_drop(true)
             .spawn()
-            .context("failed to launch")?;
+            .with_context(|| format!("failed to launch {}", start_arg.program))?;
 
         let mut to_server = child.stdin.take().unwrap();
         let from_server = child.stdout.take().unwrap();
         let mut server_reader = tokio::io::BufReader::new(from_server);
 
-        let response_senders = Arc::new(Mutex::new(HashMap::<RequestId, tokio::sync::oneshot::Sender<Response>>::new()));
+        let response_senders = ResponseSenders::default();
 
         let response_senders_for_thread = response_senders.clone();
 
-        let (from_server_sender, from_server_receiver) = mpsc::channel::<Message>(1000);
+        // unbounded as well, so that sending never waits and the buffers can
+        // talk to the server without holding on to anything across an await
+        let (to_server_sender, mut to_server_receiver) = mpsc::unbounded_channel::<Message>();
+        let to_server_for_thread = to_server_sender.clone();
+        let root = start_arg.root.clone();
+        // unbounded, so that a flood of notifications the editor has not read
+        // yet never holds up the responses behind them
+        let (from_server_sender, from_server_receiver) = mpsc::unbounded_channel::<ServerEvent>();
         let from_server_thread =
             tokio::spawn(async move {
-                while let Some(msg) = Message::read(&mut server_reader).await.context("message read failed")? {
-                    match msg {
-                        Message::Response(res) => {
-                            eprintln!("got {:?}", res);
-                            let opt_sender = {
-                                response_senders_for_thread.as_ref().lock().await.remove(&res.id)
-                            };
-                            eprintln!("opt_sender: {:?}", opt_sender);
-                            if let Some(sender) = opt_sender {
-                                sender.send(res).map_err(|_e| anyhow!("receiver dropped"))?;
+                let result = async {
+                    while let Some(msg) = Message::read(&mut server_reader).await.context("message read failed")? {
+                        match msg {
+                            Message::Response(res) => {
+                                eprintln!("got {:?}", res);
+                                let opt_sender = lock_senders(&response_senders_for_thread).remove(&res.id);
+                                eprintln!("opt_sender: {:?}", opt_sender);
+                                if let Some(sender) = opt_sender {
+                                    // the requester may have given up on the response
+                                    let _ = sender.send(res);
+                                }
+                            }
+                            Message::Request(req) => {
+                                match handle_request(req, &root, &to_server_for_thread) {
+                                    Reply::Respond(res) => to_server_for_thread.send(res.into())?,
+                                    Reply::Forward(event) => from_server_sender.send(event)?,
+                                }
+                            }
+                            Message::Notification(ntf) => {
+                                match parse_notification(ntf) {
+                                    Ok(Some(event)) => from_server_sender.send(event)?,
+                                    Ok(None) => {}
+                                    Err(e) => from_server_sender.send(ServerEvent::BadNotification(format!("{:#}", e)))?,
+                                }
                             }
                         }
-                        Message::Request(req) => {
-                        }
-                        Message::Notification(ntf) => {
-                        }
+                        eprintln!("read time");
                     }
-                    eprintln!("read time");
-                }
-                Ok(())
+                    Ok(())
+                }.await;
+                // the server is gone; fail every request still waiting for a response
+                lock_senders(&response_senders_for_thread).clear();
+                result
             });
 
-        let (to_server_sender, mut to_server_receiver) = mpsc::channel::<Message>(1000);
         let to_server_thread = 
             tokio::spawn(async move {
                 while let Some(it) = to_server_receiver.recv().await {
@@ -77,62 +156,129 @@ impl LspClient {
             });
 
         let mut client = Self {
-            lsp_process_child: child,
+            lsp_process_child: Mutex::new(child),
             from_server_thread,
-            from_server_receiver,
+            from_server_receiver: std::sync::Mutex::new(from_server_receiver),
             to_server_thread,
             to_server_sender,
             response_senders,
             server_capabilities: ServerCapabilities::default(),
-            id_cnt: Mutex::new(0),
+            position_encoding: PositionEncoding::default(),
+            id_cnt: AtomicI32::new(0),
+            start_arg,
+            shutting_down: AtomicBool::new(false),
         };
         client.initialize().await?;
         Ok(client)
     }
 
+    pub fn start_arg(&self) -> &LspClientStartArg {
+        &self.start_arg
+    }
+
+    pub fn server_capabilities(&self) -> &ServerCapabilities {
+        &self.server_capabilities
+    }
+
+    /// How the server counts columns.
+    pub fn position_encoding(&self) -> PositionEncoding {
+        self.position_encoding
+    }
+
+    /// Whether the server announced `feature` in `initialize`.
+    pub fn supports(&self, feature: Feature) -> bool {
+        feature.is_supported(&self.server_capabilities)
+    }
+
     pub async fn initialize(&mut self) -> anyhow::Result<()> {
         use lsp_types::*;
-        let client_capabilities = ClientCapabilities::default();
-
+        let root = &self.start_arg.root;
         let work = WorkspaceFolder {
-            uri: path_to_uri("./")?,
-            name: "test".to_owned(),
+            uri: path_to_uri(root)?,
+            name: root.file_name().and_then(|n| n.to_str()).unwrap_or("/").to_owned(),
         };
 
+        #[allow(deprecated)]
         let init_params = InitializeParams {
             process_id: Some(std::process::id()),
-            capabilities: client_capabilities,
+            capabilities: capabilities::client_capabilities(),
+            // older servers only look at rootUri
+            root_uri: Some(work.uri.clone()),
             workspace_folders: Some(vec![work]),
             ..Default::default()
         };
-        let recv = self.request::<lsp_types::request::Initialize>(init_params).await?;
+        let recv = self.request::<lsp_types::request::Initialize>(init_params)?;
         let inited = recv.await_result().await?.0?;
 
+        self.position_encoding = PositionEncoding::negotiated(inited.capabilities.position_encoding.as_ref());
         self.server_capabilities = inited.capabilities;
 
-        self.notify::<notification::Initialized>(InitializedParams {}).await?;
+        self.notify::<notification::Initialized>(InitializedParams {})?;
         Ok(())
     }
 
-    async fn get_new_id(&self) -> RequestId {
-        let mut num = self.id_cnt.lock().await;
-        let ans = *num;
-        *num += 1;
-        RequestId::from(ans)
+    /// Ask the server to shut down and exit, killing it when it has not
+    /// exited within `timeout`.
+    pub async fn shutdown(&self, timeout: Duration) -> anyhow::Result<()> {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        let graceful = async {
+            let recv = self.request::<lsp_types::request::Shutdown>(())?;
+            recv.await_result().await?.0?;
+            self.notify::<lsp_types::notification::Exit>(())?;
+            self.lsp_process_child.lock().await.wait().await?;
+            Ok(())
+        };
+        let result = match tokio::time::timeout(timeout, graceful).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("{} did not exit in time", self.start_arg.program)),
+        };
+        if result.is_err() {
+            self.lsp_process_child.lock().await.kill().await?;
+        }
+        self.from_server_thread.abort();
+        self.to_server_thread.abort();
+        result
+    }
+
+    /// The next notification or request from the server that needs the editor.
+    pub fn try_recv_event(&self) -> Option<ServerEvent> {
+        self.from_server_receiver.lock().ok()?.try_recv().ok()
     }
 
-    pub async fn request<R: lsp_types::request::Request>(&self, param: R::Params) -> anyhow::Result<ResponseReceiver<R>>
+    /// Whether the server stopped talking to us without being shut down.
+    pub fn has_crashed(&self) -> bool {
+        self.from_server_thread.is_finished() && !self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Make sure the process of a server that stopped talking is gone. A
+    /// server that cannot be read any more may still be running, so it is
+    /// killed; the exit status is returned when it had exited on its own.
+    pub async fn stop(&self) -> Option<std::process::ExitStatus> {
+        self.to_server_thread.abort();
+        let mut child = self.lsp_process_child.lock().await;
+        match child.try_wait() {
+            Ok(Some(status)) => Some(status),
+            _ => {
+                let _ = child.kill().await;
+                None
+            }
+        }
+    }
+
+    fn get_new_id(&self) -> RequestId {
+        RequestId::from(self.id_cnt.fetch_add(1, Ordering::SeqCst))
+    }
+
+    pub fn request<R: lsp_types::request::Request>(&self, param: R::Params) -> anyhow::Result<ResponseReceiver<R>>
     where R::Params: Clone
     {
         let (sender, receiver) = tokio::sync::oneshot::channel::<Response>();
-        let id = self.get_new_id().await;
-        {
-            self.response_senders.as_ref().lock().await.insert(id.clone(), sender);
-        }
+        let id = self.get_new_id();
+        lock_senders(&self.response_senders).insert(id.clone(), sender);
 
-        let req = Request::new(id, R::METHOD.to_owned(), param.clone());
+        let req = Request::new(id.clone(), R::METHOD.to_owned(), param.clone());
         let msg = Message::Request(req);
-        self.to_server_sender.send(msg).await?;
+        self.to_server_sender.send(msg)?;
 
         let (sender2, receiver2) = tokio::sync::oneshot::channel::<ResponseResult<R>>();
 
@@ -146,26 +292,56 @@ impl LspClient {
                 }
             }
         });
-        Ok(ResponseReceiver { receiver: receiver2, handle, param })
+        let canceller = Canceller { id, response_senders: self.response_senders.clone(), to_server: self.to_server_sender.clone(), handle };
+        Ok(ResponseReceiver { receiver: receiver2, canceller, param })
     }
 
-    pub async fn notify<N: lsp_types::notification::Notification>(&self, param: N::Params) -> anyhow::Result<()> {
+    pub fn notify<N: lsp_types::notification::Notification>(&self, param: N::Params) -> anyhow::Result<()> {
         let nt = Notification::new(N::METHOD.to_owned(), param);
         let msg = Message::Notification(nt);
-        self.to_server_sender.send(msg).await?;
+        self.to_server_sender.send(msg)?;
         Ok(())
     }
 }
 
+/// What it takes to call off a request after it was sent. Dropping it
+/// before the response came calls the request off.
+struct Canceller {
+    id: RequestId,
+    response_senders: ResponseSenders,
+    to_server: UnboundedSender<Message>,
+    /// Forwards the response, finished once it came.
+    handle: JoinHandle<anyhow::Result<()>>,
+}
+
+impl Drop for Canceller {
+    fn drop(&mut self) {
+        if self.handle.is_finished() {
+            return;
+        }
+        self.handle.abort();
+        // forget the request, so that its response is dropped when it comes,
+        // and ask the server to stop working on it unless it already answered
+        let pending = lock_senders(&self.response_senders).remove(&self.id).is_some();
+        if pending {
+            let params = lsp_types::CancelParams { id: self.id.clone().into() };
+            // a server that is gone has nothing left to cancel
+            let _ = self.to_server.send(Notification::new(Cancel::METHOD.to_owned(), params).into());
+        }
+    }
+}
+
+/// Dropping it before the response came sends `$/cancelRequest`.
 pub struct ResponseReceiver<R: lsp_types::request::Request> {
     pub receiver: tokio::sync::oneshot::Receiver<ResponseResult<R>>,
-    handle: JoinHandle<anyhow::Result<()>>,
+    canceller: Canceller,
     pub param: R::Params,
 }
 
 impl<R: lsp_types::request::Request> ResponseReceiver<R> {
-    pub fn abort_request(self) {
-        self.handle.abort();
+    /// Give up on the response and send `$/cancelRequest`.
+    pub fn cancel(self) {
+        drop(self.canceller);
     }
 
     pub async fn await_result(self) -> anyhow::Result<(ResponseResult<R>, R::Params)> {
@@ -175,7 +351,7 @@ impl<R: lsp_types::request::Request> ResponseReceiver<R> {
         match self.receiver.try_recv() {
             Err(tokio::sync::oneshot::error::TryRecvError::Empty) => TryGetResponse::Yet(self),
             Ok(resp) => TryGetResponse::Receive((resp, self.param)),
-            Err(tokio::sync::oneshot::error::TryRecvError::Closed) => unreachable!("closed???")
+            Err(tokio::sync::oneshot::error::TryRecvError::Closed) => TryGetResponse::Receive((Err(anyhow!("language server exited")), self.param)),
 
         }
     }
@@ -204,3 +380,43 @@ fn response_to_result<R: lsp_types::request::Request>(resp: Response) -> Respons
 pub fn path_to_uri<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<lsp_types::Uri> {
     Ok(lsp_types::Uri::from_str(&format!("file://{}", std::path::absolute(path)?.to_str().context("cant to_str")?))?)
 }
+
+/// The file a `file://` URI points at, decoding `%XX` escapes.
+pub fn uri_to_path(uri: &lsp_types::Uri) -> anyhow::Result<PathBuf> {
+    let path = uri.as_str().strip_prefix("file://").ok_or_else(|| anyhow!("not a file URI: {}", uri.as_str()))?;
+    let mut bytes = vec![];
+    let mut rest = path.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let escaped = if b == b'%' && tail.len() >= 2 {
+            std::str::from_utf8(&tail[..2]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    Ok(PathBuf::from(String::from_utf8(bytes)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::uri_to_path;
+
+    #[test]
+    fn escaped_uri_to_path() {
+        let uri = lsp_types::Uri::from_str("file:///tmp/my%20project/caf%C3%A9.cpp").unwrap();
+        assert_eq!(uri_to_path(&uri).unwrap(), std::path::absolute("/tmp/my project/café.cpp").unwrap());
+        let uri = lsp_types::Uri::from_str("untitled:Untitled-1").unwrap();
+        assert!(uri_to_path(&uri).is_err());
+    }
+}
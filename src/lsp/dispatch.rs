@@ -0,0 +1,158 @@
+use std::path::Path;
+
+use lsp_types::{ApplyWorkspaceEditParams, ClientCapabilities, ConfigurationParams, LogMessageParams, ProgressParams, PublishDiagnosticsClientCapabilities, PublishDiagnosticsParams, ShowMessageParams, ShowMessageRequestClientCapabilities, ShowMessageRequestParams, WorkspaceFolder, notification::{self, Notification as _}, request::{self, Request as _}};
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{capabilities, client::path_to_uri, msg::{ErrorCode, Message, Notification, Request, RequestId, Response}};
+
+/// Something the server told the editor, drained by the editor every tick.
+#[derive(Debug)]
+pub enum ServerEvent {
+    Diagnostics(PublishDiagnosticsParams),
+    ShowMessage(ShowMessageParams),
+    /// Answered with the chosen action, or null.
+    ShowMessageRequest(ShowMessageRequestParams, Responder),
+    LogMessage(LogMessageParams),
+    Progress(ProgressParams),
+    /// The server waits for `responder` to tell it whether the edit was applied.
+    ApplyEdit(ApplyWorkspaceEditParams, Responder),
+    /// A notification whose parameters could not be parsed.
+    BadNotification(String),
+}
+
+/// Answers one server request once the editor has dealt with it.
+#[derive(Debug)]
+pub struct Responder {
+    id: RequestId,
+    to_server: UnboundedSender<Message>,
+}
+
+impl Responder {
+    pub fn respond<R: Serialize>(self, result: R) -> anyhow::Result<()> {
+        self.to_server.send(Response::new_ok(self.id, result).into())?;
+        Ok(())
+    }
+}
+
+/// What to do with a request from the server.
+#[derive(Debug)]
+pub enum Reply {
+    Respond(Response),
+    Forward(ServerEvent),
+}
+
+pub fn parse_notification(ntf: Notification) -> anyhow::Result<Option<ServerEvent>> {
+    let event = match ntf.method.as_str() {
+        notification::PublishDiagnostics::METHOD => ServerEvent::Diagnostics(ntf.extract(notification::PublishDiagnostics::METHOD)?),
+        notification::ShowMessage::METHOD => ServerEvent::ShowMessage(ntf.extract(notification::ShowMessage::METHOD)?),
+        notification::LogMessage::METHOD => ServerEvent::LogMessage(ntf.extract(notification::LogMessage::METHOD)?),
+        notification::Progress::METHOD => ServerEvent::Progress(ntf.extract(notification::Progress::METHOD)?),
+        _ => return Ok(None),
+    };
+    Ok(Some(event))
+}
+
+/// Answer the requests the client can handle on its own and forward the
+/// ones that need the editor. Anything else gets `MethodNotFound` so the
+/// server does not wait forever.
+pub fn handle_request(req: Request, root: &Path, to_server: &UnboundedSender<Message>) -> Reply {
+    let id = req.id.clone();
+    let result = match req.method.as_str() {
+        // no settings; the server falls back to its defaults
+        request::WorkspaceConfiguration::METHOD => {
+            req.extract::<ConfigurationParams>(request::WorkspaceConfiguration::METHOD)
+                .map(|(_, params)| serde_json::to_value(vec![serde_json::Value::Null; params.items.len()]).unwrap())
+        }
+        request::WorkDoneProgressCreate::METHOD
+        | request::RegisterCapability::METHOD
+        | request::UnregisterCapability::METHOD => Ok(serde_json::Value::Null),
+        request::WorkspaceFoldersRequest::METHOD => {
+            path_to_uri(root).map(|uri| {
+                let name = root.file_name().and_then(|n| n.to_str()).unwrap_or("/").to_owned();
+                serde_json::to_value(vec![WorkspaceFolder { uri, name }]).unwrap()
+            })
+        }
+        request::ShowMessageRequest::METHOD => {
+            match req.extract::<ShowMessageRequestParams>(request::ShowMessageRequest::METHOD) {
+                Ok((id, params)) => return Reply::Forward(ServerEvent::ShowMessageRequest(params, Responder { id, to_server: to_server.clone() })),
+                Err(e) => Err(e),
+            }
+        }
+        request::ApplyWorkspaceEdit::METHOD => {
+            match req.extract::<ApplyWorkspaceEditParams>(request::ApplyWorkspaceEdit::METHOD) {
+                Ok((id, params)) => return Reply::Forward(ServerEvent::ApplyEdit(params, Responder { id, to_server: to_server.clone() })),
+                Err(e) => Err(e),
+            }
+        }
+        _ => return Reply::Respond(Response::new_err(id, ErrorCode::MethodNotFound as i32, format!("unhandled method {}", req.method))),
+    };
+    match result {
+        Ok(result) => Reply::Respond(Response::new_ok(id, result)),
+        Err(e) => Reply::Respond(Response::new_err(id, ErrorCode::InvalidParams as i32, format!("{:#}", e))),
+    }
+}
+
+/// What the requests and notifications handled here need from the client.
+pub fn declare(caps: &mut ClientCapabilities) {
+    let workspace = capabilities::workspace(caps);
+    workspace.apply_edit = Some(true);
+    workspace.configuration = Some(true);
+    workspace.workspace_folders = Some(true);
+    let window = capabilities::window(caps);
+    window.work_done_progress = Some(true);
+    window.show_message = Some(ShowMessageRequestClientCapabilities::default());
+    capabilities::text_document(caps).publish_diagnostics = Some(PublishDiagnosticsClientCapabilities {
+        version_support: Some(true),
+        ..Default::default()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::json;
+
+    use crate::lsp::msg::{Message, Notification, Request, RequestId};
+
+    use super::{handle_request, parse_notification, Reply, ServerEvent};
+
+    fn request(method: &str, params: serde_json::Value) -> Reply {
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel::<Message>();
+        handle_request(Request::new(RequestId::from(7), method.to_owned(), params), Path::new("/tmp/proj"), &sender)
+    }
+
+    fn response(reply: Reply) -> (Option<serde_json::Value>, Option<i32>) {
+        match reply {
+            Reply::Respond(res) => (res.result, res.error.map(|e| e.code)),
+            Reply::Forward(event) => panic!("forwarded {:?}", event),
+        }
+    }
+
+    #[test]
+    fn answered_requests() {
+        let params = json!({ "items": [{ "section": "clangd" }, { "section": "python" }] });
+        assert_eq!(response(request("workspace/configuration", params)), (Some(json!([null, null])), None));
+        assert_eq!(response(request("window/workDoneProgress/create", json!({ "token": 1 }))), (Some(json!(null)), None));
+        assert_eq!(response(request("client/registerCapability", json!({ "registrations": [] }))), (Some(json!(null)), None));
+        let (folders, _) = response(request("workspace/workspaceFolders", json!(null)));
+        assert_eq!(folders.unwrap()[0]["name"], "proj");
+    }
+
+    #[test]
+    fn unknown_request() {
+        assert_eq!(response(request("foo/bar", json!({}))), (None, Some(-32601)));
+        assert_eq!(response(request("workspace/configuration", json!({ "items": 3 }))).1, Some(-32602));
+    }
+
+    #[test]
+    fn forwarded() {
+        let params = json!({ "edit": { "changes": {} } });
+        assert!(matches!(request("workspace/applyEdit", params), Reply::Forward(ServerEvent::ApplyEdit(..))));
+        let ntf = Notification::new("textDocument/publishDiagnostics".to_owned(), json!({ "uri": "file:///a.cpp", "diagnostics": [] }));
+        assert!(matches!(parse_notification(ntf).unwrap(), Some(ServerEvent::Diagnostics(_))));
+        let ntf = Notification::new("$/unknown".to_owned(), json!(null));
+        assert!(parse_notification(ntf).unwrap().is_none());
+    }
+}
@@ -0,0 +1,186 @@
+use std::{collections::HashSet, path::{Path, PathBuf}, process::ExitStatus, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+
+use crate::language::{find_root, Language};
+
+use super::client::{LspClient, LspClientStartArg};
+
+struct ManagedClient {
+    /// The language the server was started for; files of other languages
+    /// with the same command and root share it, e.g. C headers and C++.
+    language: &'static str,
+    command: String,
+    root: PathBuf,
+    client: Arc<LspClient>,
+    /// Buffers attached to `client`.
+    users: usize,
+    /// Unexpected exits since the last manual restart.
+    crashes: usize,
+}
+
+/// How long a server gets to exit after `shutdown` before it is killed.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+/// A server that keeps crashing is given up on after this many restarts.
+const MAX_CRASH_RESTARTS: usize = 3;
+
+/// A server command given on the command line, for one language or, without
+/// a language, for every language that has no command of its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerOverride {
+    pub language: Option<&'static str>,
+    pub command: String,
+}
+
+/// A server process replaced by a new one, or given up on when `new` is an error.
+pub struct Restarted {
+    pub language: &'static str,
+    pub old: Arc<LspClient>,
+    pub new: anyhow::Result<Arc<LspClient>>,
+    /// How a crashed server ended; `None` when it was still running and had
+    /// to be killed, or was restarted on request.
+    pub exited: Option<ExitStatus>,
+}
+
+/// Starts one client per (server command, workspace root) on first use and shuts
+/// it down when the last buffer using it is released.
+pub struct LspManager {
+    clients: Vec<ManagedClient>,
+    /// Servers that failed to start; not retried until `restart_all`.
+    failed: HashSet<(String, PathBuf)>,
+    /// Server commands used instead of `Language::server`.
+    server_overrides: Vec<ServerOverride>,
+    disabled: bool,
+}
+
+impl LspManager {
+    pub fn new(server_overrides: Vec<ServerOverride>, disabled: bool) -> Self {
+        Self { clients: vec![], failed: HashSet::new(), server_overrides, disabled }
+    }
+
+    /// The command for `language`: its own override, else one for every
+    /// language, else the configured server.
+    fn server_command(&self, language: &'static Language) -> Option<String> {
+        let find = |wanted: Option<&str>| self.server_overrides.iter().rev().find(|o| o.language == wanted);
+        find(Some(language.name))
+            .or_else(|| find(None))
+            .map(|o| o.command.clone())
+            .or_else(|| language.server.map(str::to_owned))
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
+    pub fn running(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn clients(&self) -> impl Iterator<Item = (&'static str, &Arc<LspClient>)> {
+        self.clients.iter().map(|c| (c.language, &c.client))
+    }
+
+    /// The client for `path`, counted as one more user. `Ok(None)` when the
+    /// language has no server or its server failed to start before.
+    pub async fn acquire(&mut self, language: &'static Language, path: &Path) -> anyhow::Result<Option<Arc<LspClient>>> {
+        if self.disabled {
+            return Ok(None);
+        }
+        let Some(command) = self.server_command(language) else {
+            return Ok(None);
+        };
+        let root = find_root(path, language.root_markers)?;
+        if let Some(managed) = self.clients.iter_mut().find(|c| c.command == command && c.root == root) {
+            managed.users += 1;
+            return Ok(Some(managed.client.clone()));
+        }
+        let key = (command.clone(), root.clone());
+        if self.failed.contains(&key) {
+            return Ok(None);
+        }
+        let start_arg = LspClientStartArg::from_command_line(&command, root.clone())?;
+        match LspClient::start(start_arg).await {
+            Ok(client) => {
+                let client = Arc::new(client);
+                self.clients.push(ManagedClient { language: language.name, command, root, client: client.clone(), users: 1, crashes: 0 });
+                Ok(Some(client))
+            }
+            Err(e) => {
+                self.failed.insert(key);
+                Err(e)
+            }
+        }
+    }
+
+    /// Drop one user of `client`, shutting the server down after the last one.
+    pub async fn release(&mut self, client: &Arc<LspClient>) -> anyhow::Result<()> {
+        let Some(idx) = self.clients.iter().position(|c| Arc::ptr_eq(&c.client, client)) else {
+            return Ok(());
+        };
+        self.clients[idx].users -= 1;
+        if self.clients[idx].users == 0 {
+            let managed = self.clients.remove(idx);
+            managed.client.shutdown(SHUTDOWN_TIMEOUT).await?;
+        }
+        Ok(())
+    }
+
+    /// Start `managed` again; a server that does not come back is not retried.
+    async fn restart(&mut self, managed: ManagedClient, exited: Option<ExitStatus>) -> Restarted {
+        let language = managed.language;
+        match LspClient::start(managed.client.start_arg().clone()).await {
+            Ok(client) => {
+                let client = Arc::new(client);
+                self.clients.push(ManagedClient { client: client.clone(), ..managed });
+                Restarted { language, old: managed.client, new: Ok(client), exited }
+            }
+            Err(e) => {
+                self.failed.insert((managed.command, managed.root));
+                Restarted { language, old: managed.client, new: Err(e), exited }
+            }
+        }
+    }
+
+    /// Start every server again, including those that failed before.
+    /// Buffers should be moved from `old` to `new` clients.
+    pub async fn restart_all(&mut self) -> Vec<Restarted> {
+        self.failed.clear();
+        let mut restarted = vec![];
+        for mut managed in std::mem::take(&mut self.clients) {
+            let _ = managed.client.shutdown(SHUTDOWN_TIMEOUT).await;
+            managed.crashes = 0;
+            restarted.push(self.restart(managed, None).await);
+        }
+        restarted
+    }
+
+    /// Restart the servers that exited on their own.
+    pub async fn recover_crashed(&mut self) -> Vec<Restarted> {
+        let (crashed, running) = std::mem::take(&mut self.clients).into_iter().partition::<Vec<_>, _>(|c| c.client.has_crashed());
+        self.clients = running;
+        let mut restarted = vec![];
+        for mut managed in crashed {
+            let exited = managed.client.stop().await;
+            managed.crashes += 1;
+            if managed.crashes > MAX_CRASH_RESTARTS {
+                let new = Err(anyhow!("exited {} times, giving up", managed.crashes));
+                self.failed.insert((managed.command, managed.root));
+                restarted.push(Restarted { language: managed.language, old: managed.client, new, exited });
+                continue;
+            }
+            restarted.push(self.restart(managed, exited).await);
+        }
+        restarted
+    }
+
+    /// Shut down every server, e.g. when the editor quits.
+    pub async fn shutdown_all(&mut self) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        for managed in std::mem::take(&mut self.clients) {
+            if let Err(e) = managed.client.shutdown(SHUTDOWN_TIMEOUT).await {
+                result = Err(e);
+            }
+        }
+        result
+    }
+}
@@ -1,93 +1,162 @@
-use super::client::{LspClient, ResponseReceiver, TryGetResponse};
+use std::time::Duration;
+
+use anyhow::anyhow;
+
+use ropey::Rope;
+
+use super::{client::{LspClient, ResponseReceiver, ResponseResult, TryGetResponse}, msg::ResponseError, position::Positions};
 
 pub mod hover;
 pub mod didchange;
 pub mod completion;
+pub mod save;
+pub mod goto;
+pub mod references;
+pub mod symbols;
+pub mod rename;
+pub mod code_action;
+pub mod formatting;
+pub mod signature_help;
 
+/// Both traits get the text the request is about, to convert positions
+/// between the editor and the server.
 pub trait LspParam {
     type ActualParam;
-    fn into_param(self) -> Self::ActualParam;
+    fn into_param(self, positions: &Positions) -> Self::ActualParam;
 }
 
 pub trait LspResult {
     type Response;
     type Param;
-    fn from_response(resp: Self::Response, param: Self::Param) -> Self;
+    fn from_response(resp: Self::Response, param: Self::Param, positions: &Positions) -> Self;
 }
 
 pub enum LspFetch<Request: lsp_types::request::Request, Result> {
-    Yet(ResponseReceiver<Request>),
+    /// Waiting for the answer, about the given version of a document if any.
+    Yet(ResponseReceiver<Request>, Positions, Option<i32>),
     Got(Result),
+    /// Failed, or called off with no answer to show.
     Tmp,
 }
 
+/// `Ok(None)` for a request the server called off or gave up on because
+/// the document changed; its answer is not coming and not missed.
+fn answered<R: lsp_types::request::Request>(resp: ResponseResult<R>) -> anyhow::Result<Option<R::Result>> {
+    match resp {
+        Err(e) if e.downcast_ref::<ResponseError>().is_some_and(ResponseError::is_cancelled) => Ok(None),
+        resp => resp.map(Some),
+    }
+}
+
 impl<Request, Res> LspFetch<Request, Res>
 where
     Request: lsp_types::request::Request,
     <Request as lsp_types::request::Request>::Params: Clone,
     Res: LspResult<Response=<Request as lsp_types::request::Request>::Result, Param=<Request as lsp_types::request::Request>::Params>,
 {
-    pub async fn new<P: LspParam<ActualParam=<Request as lsp_types::request::Request>::Params>>(client: &LspClient, param: P) -> anyhow::Result<Self> {
-        let params = param.into_param();
-        let receiver = client.request::<Request>(params).await?;
-        Ok(Self::Yet(receiver))
+    /// A request that is not about the text of a document.
+    pub fn new<P: LspParam<ActualParam=<Request as lsp_types::request::Request>::Params>>(client: &LspClient, param: P) -> anyhow::Result<Self> {
+        Self::send(client, Positions::new(Rope::new(), client.position_encoding()), None, param)
+    }
+
+    /// A request about `text`, `version` of the document as the server has it now.
+    pub fn in_text<P: LspParam<ActualParam=<Request as lsp_types::request::Request>::Params>>(client: &LspClient, text: Rope, version: i32, param: P) -> anyhow::Result<Self> {
+        Self::send(client, Positions::new(text, client.position_encoding()), Some(version), param)
+    }
+
+    fn send<P: LspParam<ActualParam=<Request as lsp_types::request::Request>::Params>>(client: &LspClient, positions: Positions, version: Option<i32>, param: P) -> anyhow::Result<Self> {
+        let params = param.into_param(&positions);
+        let receiver = client.request::<Request>(params)?;
+        Ok(Self::Yet(receiver, positions, version))
     }
 
+    /// Cancel the request on the server too, if it is still waiting.
     pub fn abort(self) {
-        if let Self::Yet(receiver) = self {
-            receiver.abort_request();
+        if let Self::Yet(receiver, ..) = self {
+            receiver.cancel();
         }
     }
 
+    /// Whether the request is still waiting but was about another version
+    /// of the document than `version`, so that its answer would be stale.
+    pub fn is_stale(&self, version: i32) -> bool {
+        matches!(self, Self::Yet(_, _, Some(sent)) if *sent != version)
+    }
+
+    /// Whether no answer is coming any more, because the request was called
+    /// off or failed before.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, Self::Tmp)
+    }
 
     pub async fn await_result(self) -> anyhow::Result<Res> {
         match self {
-            Self::Yet(receiver) => {
+            Self::Yet(receiver, positions, _) => {
                 let (resp, param) = receiver.await_result().await?;
-                resp.map(|resp| Res::from_response(resp, param))
+                resp.map(|resp| Res::from_response(resp, param, &positions))
             }
             Self::Got(r) => Ok(r),
-            _ => unreachable!(),
+            Self::Tmp => Err(anyhow!("request cancelled")),
+        }
+    }
+
+    /// Like `await_result`, but gives up after `timeout` with `Ok(None)`
+    /// and then calls the request off on the server.
+    pub async fn await_within(self, timeout: Duration) -> anyhow::Result<Option<Res>> {
+        let Self::Yet(mut receiver, positions, _) = self else {
+            return self.await_result().await.map(Some);
+        };
+        match tokio::time::timeout(timeout, &mut receiver.receiver).await {
+            Ok(resp) => Ok(Some(Res::from_response(resp??, receiver.param, &positions))),
+            Err(_) => {
+                receiver.cancel();
+                Ok(None)
+            }
         }
     }
 
     pub fn try_get_result(&mut self) -> anyhow::Result<Option<&Res>> {
         let mut v = std::mem::replace(self, Self::Tmp);
         v = match v {
-            Self::Yet(receiver) => {
+            Self::Yet(receiver, positions, version) => {
                 match receiver.try_get_response() {
-                    TryGetResponse::Yet(receiver) => Self::Yet(receiver),
-                    TryGetResponse::Receive(resp) => Self::Got(resp.0.map(|r| Res::from_response(r, resp.1))?),
+                    TryGetResponse::Yet(receiver) => Self::Yet(receiver, positions, version),
+                    TryGetResponse::Receive((resp, param)) => match answered::<Request>(resp)? {
+                        Some(r) => Self::Got(Res::from_response(r, param, &positions)),
+                        None => Self::Tmp,
+                    },
                 }
             }
             Self::Got(r) => Self::Got(r),
-            _ => unreachable!(),
+            // a request that failed before, the error reported then, or was called off
+            Self::Tmp => Self::Tmp,
         };
         *self = v;
         Ok(match self {
-            Self::Yet(_) => None,
+            Self::Yet(..) | Self::Tmp => None,
             Self::Got(ref r) => Some(r),
-            _ => unreachable!(),
         })
     }
 
     pub fn try_get_result_mut(&mut self) -> anyhow::Result<Option<&mut Res>> {
         let mut v = std::mem::replace(self, Self::Tmp);
         v = match v {
-            Self::Yet(receiver) => {
+            Self::Yet(receiver, positions, version) => {
                 match receiver.try_get_response() {
-                    TryGetResponse::Yet(receiver) => Self::Yet(receiver),
-                    TryGetResponse::Receive(resp) => Self::Got(resp.0.map(|r| Res::from_response(r, resp.1))?),
+                    TryGetResponse::Yet(receiver) => Self::Yet(receiver, positions, version),
+                    TryGetResponse::Receive((resp, param)) => match answered::<Request>(resp)? {
+                        Some(r) => Self::Got(Res::from_response(r, param, &positions)),
+                        None => Self::Tmp,
+                    },
                 }
             }
             Self::Got(r) => Self::Got(r),
-            _ => unreachable!(),
+            Self::Tmp => Self::Tmp,
         };
         *self = v;
         Ok(match self {
-            Self::Yet(_) => None,
+            Self::Yet(..) | Self::Tmp => None,
             Self::Got(ref mut r) => Some(r),
-            _ => unreachable!(),
         })
     }
 }
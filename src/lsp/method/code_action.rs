@@ -0,0 +1,133 @@
+use lsp_types::{ClientCapabilities, CodeAction, CodeActionCapabilityResolveSupport, CodeActionClientCapabilities, CodeActionContext, CodeActionKind, CodeActionKindLiteralSupport, CodeActionLiteralSupport, CodeActionOrCommand, CodeActionParams, CodeActionResponse, CodeActionTriggerKind, Command, Diagnostic, ExecuteCommandClientCapabilities, ExecuteCommandParams, Uri, request::{CodeActionRequest, CodeActionResolveRequest, ExecuteCommand}};
+
+use crate::{buffer::{CursorPos, diagnostics}, lsp::{capabilities, client::path_to_uri, position::Positions}};
+
+use super::{LspFetch, LspParam, LspResult};
+
+pub struct CodeActionResult {
+    pub actions: Vec<CodeActionOrCommand>,
+}
+
+impl LspResult for CodeActionResult {
+    type Response = Option<CodeActionResponse>;
+    type Param = CodeActionParams;
+    fn from_response(resp: Option<CodeActionResponse>, _param: CodeActionParams, _positions: &Positions) -> Self {
+        CodeActionResult { actions: resp.unwrap_or_default() }
+    }
+}
+
+pub struct CodeActionParam {
+    uri: Uri,
+    range: (CursorPos, CursorPos),
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl CodeActionParam {
+    /// `diagnostics` are the ones overlapping `range`, so that the server
+    /// can offer their fixes. Their ranges count chars, as the buffer keeps them.
+    pub fn new<S: AsRef<std::path::Path>>(filename: S, range: (CursorPos, CursorPos), diagnostics: Vec<Diagnostic>) -> anyhow::Result<Self> {
+        Ok(Self {
+            uri: path_to_uri(filename)?,
+            range,
+            diagnostics,
+        })
+    }
+}
+
+impl LspParam for CodeActionParam {
+    type ActualParam = CodeActionParams;
+    fn into_param(self, positions: &Positions) -> Self::ActualParam {
+        let (start, end) = self.range;
+        let diagnostics = self.diagnostics.into_iter().map(|mut d| {
+            d.range = positions.range_to_lsp(diagnostics::start(&d), diagnostics::end(&d));
+            d
+        }).collect();
+        CodeActionParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri: self.uri },
+            range: positions.range_to_lsp(start, end),
+            context: CodeActionContext { diagnostics, only: None, trigger_kind: Some(CodeActionTriggerKind::INVOKED) },
+            work_done_progress_params: lsp_types::WorkDoneProgressParams { work_done_token: None },
+            partial_result_params: lsp_types::PartialResultParams { partial_result_token: None },
+        }
+    }
+}
+
+pub type CodeActionFetch = LspFetch<CodeActionRequest, CodeActionResult>;
+
+/// An action sent without its edit, filled in by `codeAction/resolve`.
+impl LspParam for CodeAction {
+    type ActualParam = CodeAction;
+    fn into_param(self, _positions: &Positions) -> Self::ActualParam {
+        self
+    }
+}
+
+impl LspResult for CodeAction {
+    type Response = CodeAction;
+    type Param = CodeAction;
+    fn from_response(resp: CodeAction, _param: CodeAction, _positions: &Positions) -> Self {
+        resp
+    }
+}
+
+pub type CodeActionResolveFetch = LspFetch<CodeActionResolveRequest, CodeAction>;
+
+impl LspParam for Command {
+    type ActualParam = ExecuteCommandParams;
+    fn into_param(self, _positions: &Positions) -> Self::ActualParam {
+        ExecuteCommandParams {
+            command: self.command,
+            arguments: self.arguments.unwrap_or_default(),
+            work_done_progress_params: lsp_types::WorkDoneProgressParams { work_done_token: None },
+        }
+    }
+}
+
+/// The server usually answers with `workspace/applyEdit` before it replies; the reply itself is not used.
+pub struct ExecuteCommandResult;
+
+impl LspResult for ExecuteCommandResult {
+    type Response = Option<serde_json::Value>;
+    type Param = ExecuteCommandParams;
+    fn from_response(_resp: Option<serde_json::Value>, _param: ExecuteCommandParams, _positions: &Positions) -> Self {
+        ExecuteCommandResult
+    }
+}
+
+pub type ExecuteCommandFetch = LspFetch<ExecuteCommand, ExecuteCommandResult>;
+
+/// The title shown in the menu, marked when the server says the action cannot be applied.
+pub fn title(action: &CodeActionOrCommand) -> String {
+    match action {
+        CodeActionOrCommand::Command(command) => command.title.clone(),
+        CodeActionOrCommand::CodeAction(action) => match action.disabled {
+            Some(ref disabled) => format!("{} (disabled: {})", action.title, disabled.reason),
+            None if action.is_preferred == Some(true) => format!("{} *", action.title),
+            None => action.title.clone(),
+        },
+    }
+}
+
+pub fn declare(caps: &mut ClientCapabilities) {
+    let kinds = [
+        CodeActionKind::EMPTY,
+        CodeActionKind::QUICKFIX,
+        CodeActionKind::REFACTOR,
+        CodeActionKind::REFACTOR_EXTRACT,
+        CodeActionKind::REFACTOR_INLINE,
+        CodeActionKind::REFACTOR_REWRITE,
+        CodeActionKind::SOURCE,
+        CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+    ];
+    capabilities::text_document(caps).code_action = Some(CodeActionClientCapabilities {
+        code_action_literal_support: Some(CodeActionLiteralSupport {
+            code_action_kind: CodeActionKindLiteralSupport { value_set: kinds.map(|kind| kind.as_str().to_owned()).to_vec() },
+        }),
+        disabled_support: Some(true),
+        data_support: Some(true),
+        // an action without an edit is resolved when it is picked
+        resolve_support: Some(CodeActionCapabilityResolveSupport { properties: vec!["edit".to_owned()] }),
+        ..Default::default()
+    });
+    capabilities::workspace(caps).execute_command = Some(ExecuteCommandClientCapabilities::default());
+}
@@ -1,6 +1,6 @@
-use lsp_types::{CompletionParams, PartialResultParams, Position, Uri, WorkDoneProgressParams, request::{Request, Completion}};
+use lsp_types::{ClientCapabilities, CompletionClientCapabilities, CompletionItem, CompletionItemCapability, CompletionItemCapabilityResolveSupport, CompletionItemKind, CompletionParams, MarkupKind, PartialResultParams, Position, Uri, WorkDoneProgressParams, request::{Request, Completion, ResolveCompletionItem}};
 
-use crate::{buffer::CursorPos, lsp::client::path_to_uri, viewer::completion_viewer::CompletionViewer};
+use crate::{buffer::CursorPos, lsp::{capabilities, client::path_to_uri, position::Positions}, viewer::completion_viewer::CompletionViewer};
 
 use super::{LspFetch, LspParam, LspResult};
 
@@ -20,11 +20,11 @@ impl CompletionParam {
 
 impl LspParam for CompletionParam {
     type ActualParam = CompletionParams;
-    fn into_param(self) -> Self::ActualParam {
+    fn into_param(self, positions: &Positions) -> Self::ActualParam {
         CompletionParams {
             text_document_position: lsp_types::TextDocumentPositionParams {
                 text_document: lsp_types::TextDocumentIdentifier { uri: self.uri }, 
-                position: lsp_types::Position { line: self.cursor.0 as u32, character: self.cursor.1 as u32 },
+                position: positions.to_lsp(self.cursor),
             },
             work_done_progress_params: WorkDoneProgressParams { work_done_token: None },
             partial_result_params: PartialResultParams { partial_result_token: None },
@@ -40,9 +40,75 @@ impl LspResult for Option<CompletionViewer> {
     type Response = Option<CompletionResponse>;
     type Param = CompletionParams;
 
-    fn from_response(resp: Self::Response, param: Self::Param) -> Self {
-        resp.map(|resp| CompletionViewer::new(resp, (param.text_document_position.position.line as usize, param.text_document_position.position.character as usize)))
+    fn from_response(resp: Self::Response, param: Self::Param, positions: &Positions) -> Self {
+        resp.map(|resp| CompletionViewer::new(resp, positions.from_lsp(param.text_document_position.position), positions.clone()))
     }
 }
 
 pub type CompletionFetch = LspFetch<Completion, Option<CompletionViewer>>;
+
+/// A short name for `kind`, shown in front of the label.
+pub fn kind_label(kind: CompletionItemKind) -> &'static str {
+    match kind {
+        CompletionItemKind::TEXT => "text",
+        CompletionItemKind::METHOD => "method",
+        CompletionItemKind::FUNCTION => "fn",
+        CompletionItemKind::CONSTRUCTOR => "ctor",
+        CompletionItemKind::FIELD => "field",
+        CompletionItemKind::VARIABLE => "var",
+        CompletionItemKind::CLASS => "class",
+        CompletionItemKind::INTERFACE => "iface",
+        CompletionItemKind::MODULE => "mod",
+        CompletionItemKind::PROPERTY => "prop",
+        CompletionItemKind::UNIT => "unit",
+        CompletionItemKind::VALUE => "value",
+        CompletionItemKind::ENUM => "enum",
+        CompletionItemKind::KEYWORD => "kw",
+        CompletionItemKind::SNIPPET => "snip",
+        CompletionItemKind::COLOR => "color",
+        CompletionItemKind::FILE => "file",
+        CompletionItemKind::REFERENCE => "ref",
+        CompletionItemKind::FOLDER => "dir",
+        CompletionItemKind::ENUM_MEMBER => "member",
+        CompletionItemKind::CONSTANT => "const",
+        CompletionItemKind::STRUCT => "struct",
+        CompletionItemKind::EVENT => "event",
+        CompletionItemKind::OPERATOR => "op",
+        CompletionItemKind::TYPE_PARAMETER => "tparam",
+        _ => "",
+    }
+}
+
+pub fn declare(caps: &mut ClientCapabilities) {
+    capabilities::text_document(caps).completion = Some(CompletionClientCapabilities {
+        completion_item: Some(CompletionItemCapability {
+            snippet_support: Some(true),
+            documentation_format: Some(vec![MarkupKind::Markdown, MarkupKind::PlainText]),
+            insert_replace_support: Some(true),
+            // what `set_resolved` takes from a resolved item
+            resolve_support: Some(CompletionItemCapabilityResolveSupport {
+                properties: ["detail", "documentation", "additionalTextEdits"].map(String::from).to_vec(),
+            }),
+            label_details_support: Some(true),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+}
+
+impl LspParam for CompletionItem {
+    type ActualParam = CompletionItem;
+    fn into_param(self, _positions: &Positions) -> Self::ActualParam {
+        self
+    }
+}
+
+impl LspResult for CompletionItem {
+    type Response = CompletionItem;
+    type Param = CompletionItem;
+    fn from_response(resp: CompletionItem, _param: CompletionItem, _positions: &Positions) -> Self {
+        resp
+    }
+}
+
+pub type CompletionResolveFetch = LspFetch<ResolveCompletionItem, CompletionItem>;
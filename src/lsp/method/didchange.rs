@@ -1,67 +1,136 @@
-use lsp_types::{DidChangeTextDocumentParams, Position, TextDocumentContentChangeEvent, VersionedTextDocumentIdentifier, notification::DidChangeTextDocument};
+use std::time::{Duration, Instant};
 
-use crate::{buffer::CursorPos, lsp::client::{LspClient, path_to_uri}};
+use lsp_types::{DidChangeTextDocumentParams, Range, ServerCapabilities, TextDocumentContentChangeEvent, TextDocumentSyncCapability, TextDocumentSyncKind, Uri, VersionedTextDocumentIdentifier, notification::DidChangeTextDocument};
 
-pub struct DidChangeNotifyBuilder {
-    text_document: VersionedTextDocumentIdentifier,
+use crate::lsp::client::{LspClient, path_to_uri};
+
+/// How long changes wait for more before they are sent on their own.
+const DELAY: Duration = Duration::from_millis(100);
+
+/// How the server wants to hear about changes; nothing at all when it does not say.
+pub fn sync_kind(caps: &ServerCapabilities) -> TextDocumentSyncKind {
+    match caps.text_document_sync {
+        Some(TextDocumentSyncCapability::Kind(kind)) => kind,
+        Some(TextDocumentSyncCapability::Options(ref options)) => options.change.unwrap_or(TextDocumentSyncKind::NONE),
+        None => TextDocumentSyncKind::NONE,
+    }
+}
+
+/// Changes of one document not sent yet. They go out together as one
+/// `didChange`, so that typing does not cost a message per key, and must
+/// be flushed before anything the server answers from the text.
+#[derive(Debug)]
+pub struct PendingChanges {
+    kind: TextDocumentSyncKind,
     changes: Vec<TextDocumentContentChangeEvent>,
+    /// When the oldest change not sent yet was made.
+    since: Option<Instant>,
 }
 
-impl DidChangeNotifyBuilder {
-    pub fn new<S: AsRef<std::path::Path>>(filename: S, version: i32) -> anyhow::Result<Self> {
-        Ok(Self {
-            text_document: VersionedTextDocumentIdentifier { uri: path_to_uri(filename)?, version, },
-            changes: vec![],
-        })
+impl Default for PendingChanges {
+    fn default() -> Self {
+        Self::new(TextDocumentSyncKind::NONE)
+    }
+}
+
+impl PendingChanges {
+    pub fn new(kind: TextDocumentSyncKind) -> Self {
+        Self { kind, changes: vec![], since: None }
+    }
+
+    /// Replace `range`, counted as the server counts in the text before
+    /// this change, with `text`.
+    pub fn edit(&mut self, range: Range, text: String) {
+        if self.kind == TextDocumentSyncKind::NONE {
+            return;
+        }
+        if self.kind == TextDocumentSyncKind::INCREMENTAL {
+            self.changes.push(TextDocumentContentChangeEvent { range: Some(range), range_length: None, text });
+        }
+        self.since.get_or_insert_with(Instant::now);
     }
 
-    pub fn full(mut self, full_text: String) -> Self {
-        self.changes.push(TextDocumentContentChangeEvent { range: None, range_length: None, text: full_text });
-        self
+    /// Whether the changes waited long enough for more.
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.since.is_some_and(|since| now.duration_since(since) >= DELAY)
     }
 
-    pub fn insert(mut self, insert_pos: CursorPos, text: String) -> Self {
-        self.changes.push(TextDocumentContentChangeEvent {
-            range: Some(lsp_types::Range {
-                start: Position::new(insert_pos.0 as u32, insert_pos.1 as u32),
-                end: Position::new(insert_pos.0 as u32, insert_pos.1 as u32),
-            }),
-            range_length: None,
-            text,
-        });
-        self
+    /// Forget the changes, e.g. when the document is closed.
+    pub fn clear(&mut self) {
+        self.changes.clear();
+        self.since = None;
     }
 
-    pub fn delete(mut self, delete_start: CursorPos, delete_end: CursorPos) -> Self {
-        self.changes.push(TextDocumentContentChangeEvent {
-            range: Some(lsp_types::Range {
-                start: Position::new(delete_start.0 as u32, delete_start.1 as u32),
-                end: Position::new(delete_end.0 as u32, delete_end.1 as u32),
-            }),
-            range_length: None,
-            text: String::new(),
-        });
-        self
+    /// The notification for everything since the last one, `None` when
+    /// there is nothing to send. `text` is asked for when the server wants
+    /// the whole document.
+    fn take(&mut self, uri: Uri, version: i32, text: impl FnOnce() -> String) -> Option<DidChangeTextDocumentParams> {
+        self.since.take()?;
+        let content_changes = if self.kind == TextDocumentSyncKind::FULL {
+            vec![TextDocumentContentChangeEvent { range: None, range_length: None, text: text() }]
+        } else {
+            std::mem::take(&mut self.changes)
+        };
+        Some(DidChangeTextDocumentParams { text_document: VersionedTextDocumentIdentifier { uri, version }, content_changes })
     }
 
-    pub fn edit(mut self, start: CursorPos, end: CursorPos, text: String) -> Self {
-        self.changes.push(TextDocumentContentChangeEvent {
-            range: Some(lsp_types::Range {
-                start: Position::new(start.0 as u32, start.1 as u32),
-                end: Position::new(end.0 as u32, end.1 as u32),
-            }),
-            range_length: None,
-            text,
-        });
-        self
+    /// Send what is pending as `version` of `filename`.
+    pub fn flush<S: AsRef<std::path::Path>>(&mut self, client: &LspClient, filename: S, version: i32, text: impl FnOnce() -> String) -> anyhow::Result<()> {
+        if self.since.is_none() {
+            return Ok(());
+        }
+        if let Some(params) = self.take(path_to_uri(filename)?, version, text) {
+            client.notify::<DidChangeTextDocument>(params)?;
+        }
+        Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, time::{Duration, Instant}};
+
+    use lsp_types::{Position, Range, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions, Uri};
+
+    use super::{sync_kind, PendingChanges};
+
+    fn range(character: u32) -> Range {
+        Range::new(Position::new(0, character), Position::new(0, character))
+    }
+
+    #[test]
+    fn kind_from_capabilities() {
+        assert_eq!(sync_kind(&ServerCapabilities::default()), TextDocumentSyncKind::NONE);
+        let caps = ServerCapabilities { text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)), ..Default::default() };
+        assert_eq!(sync_kind(&caps), TextDocumentSyncKind::FULL);
+        let options = TextDocumentSyncOptions { change: Some(TextDocumentSyncKind::INCREMENTAL), ..Default::default() };
+        let caps = ServerCapabilities { text_document_sync: Some(TextDocumentSyncCapability::Options(options)), ..Default::default() };
+        assert_eq!(sync_kind(&caps), TextDocumentSyncKind::INCREMENTAL);
+    }
+
+    #[test]
+    fn batched() {
+        let uri = Uri::from_str("file:///a.rs").unwrap();
+        let mut pending = PendingChanges::new(TextDocumentSyncKind::INCREMENTAL);
+        pending.edit(range(0), "a".to_owned());
+        pending.edit(range(1), "b".to_owned());
+        assert!(!pending.is_due(Instant::now()));
+        assert!(pending.is_due(Instant::now() + Duration::from_secs(1)));
+        let params = pending.take(uri.clone(), 3, || unreachable!()).unwrap();
+        assert_eq!(params.text_document.version, 3);
+        assert_eq!(params.content_changes.iter().map(|c| (c.range, c.text.as_str())).collect::<Vec<_>>(), [(Some(range(0)), "a"), (Some(range(1)), "b")]);
+        assert!(pending.take(uri.clone(), 3, || unreachable!()).is_none());
+
+        let mut pending = PendingChanges::new(TextDocumentSyncKind::FULL);
+        pending.edit(range(0), "a".to_owned());
+        pending.edit(range(1), "b".to_owned());
+        let params = pending.take(uri.clone(), 4, || "ab".to_owned()).unwrap();
+        assert_eq!(params.content_changes.len(), 1);
+        assert_eq!((params.content_changes[0].range, params.content_changes[0].text.as_str()), (None, "ab"));
 
-    pub async fn notify(self, client: &LspClient) -> anyhow::Result<Self> {
-        client.notify::<DidChangeTextDocument>(
-            DidChangeTextDocumentParams {
-                text_document: self.text_document.clone(),
-                content_changes: self.changes.clone(),
-            }).await?;
-        Ok(self)
+        let mut pending = PendingChanges::new(TextDocumentSyncKind::NONE);
+        pending.edit(range(0), "a".to_owned());
+        assert!(!pending.is_due(Instant::now() + Duration::from_secs(1)));
+        assert!(pending.take(uri, 5, || unreachable!()).is_none());
     }
 }
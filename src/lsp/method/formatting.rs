@@ -0,0 +1,125 @@
+use std::{collections::HashMap, marker::PhantomData, time::Duration};
+
+use lsp_types::{ClientCapabilities, DocumentFormattingClientCapabilities, DocumentFormattingParams, DocumentRangeFormattingClientCapabilities, DocumentRangeFormattingParams, FormattingOptions, TextEdit, Uri, request::{Formatting, RangeFormatting}};
+
+use ropey::Rope;
+
+use crate::{buffer::{BufferOptions, CursorPos, edit::Edit}, lsp::{capabilities, client::{LspClient, path_to_uri}, position::Positions}};
+
+use super::{LspFetch, LspParam, LspResult};
+
+/// The answer to whole document (`P` is `DocumentFormattingParams`) or
+/// range formatting (`DocumentRangeFormattingParams`), which is the same.
+pub struct FormattingResult<P> {
+    pub edits: Vec<Edit>,
+    param: PhantomData<fn(P)>,
+}
+
+impl<P> LspResult for FormattingResult<P> {
+    type Response = Option<Vec<TextEdit>>;
+    type Param = P;
+    fn from_response(resp: Option<Vec<TextEdit>>, _param: P, positions: &Positions) -> Self {
+        FormattingResult { edits: resp.iter().flatten().map(|edit| positions.edit_from_lsp(edit)).collect(), param: PhantomData }
+    }
+}
+
+impl LspParam for DocumentFormattingParams {
+    type ActualParam = DocumentFormattingParams;
+    fn into_param(self, _positions: &Positions) -> Self::ActualParam {
+        self
+    }
+}
+
+impl LspParam for DocumentRangeFormattingParams {
+    type ActualParam = DocumentRangeFormattingParams;
+    fn into_param(self, _positions: &Positions) -> Self::ActualParam {
+        self
+    }
+}
+
+pub struct FormattingParam {
+    uri: Uri,
+    options: FormattingOptions,
+    /// `None` formats the whole document.
+    range: Option<(CursorPos, CursorPos)>,
+}
+
+impl FormattingParam {
+    /// The indentation asked for is the buffer's own.
+    pub fn new<S: AsRef<std::path::Path>>(filename: S, options: &BufferOptions, range: Option<(CursorPos, CursorPos)>) -> anyhow::Result<Self> {
+        Ok(Self {
+            uri: path_to_uri(filename)?,
+            options: FormattingOptions {
+                tab_size: options.tabstop as u32,
+                insert_spaces: options.expandtab,
+                properties: HashMap::new(),
+                trim_trailing_whitespace: None,
+                insert_final_newline: None,
+                trim_final_newlines: None,
+            },
+            range,
+        })
+    }
+}
+
+/// Whole document and range formatting answer alike but are different methods.
+pub enum FormattingFetch {
+    Document(LspFetch<Formatting, FormattingResult<DocumentFormattingParams>>),
+    Range(LspFetch<RangeFormatting, FormattingResult<DocumentRangeFormattingParams>>),
+}
+
+impl FormattingFetch {
+    /// `text` is `version` of the document as the server has it now.
+    pub fn new(client: &LspClient, text: Rope, version: i32, param: FormattingParam) -> anyhow::Result<Self> {
+        let text_document = lsp_types::TextDocumentIdentifier { uri: param.uri };
+        let work_done_progress_params = lsp_types::WorkDoneProgressParams { work_done_token: None };
+        Ok(match param.range {
+            None => FormattingFetch::Document(LspFetch::in_text(client, text, version, DocumentFormattingParams {
+                text_document,
+                options: param.options,
+                work_done_progress_params,
+            })?),
+            Some((start, end)) => FormattingFetch::Range(LspFetch::in_text(client, text.clone(), version, DocumentRangeFormattingParams {
+                text_document,
+                range: Positions::new(text, client.position_encoding()).range_to_lsp(start, end),
+                options: param.options,
+                work_done_progress_params,
+            })?),
+        })
+    }
+
+    pub fn try_get_result(&mut self) -> anyhow::Result<Option<&[Edit]>> {
+        Ok(match self {
+            FormattingFetch::Document(fetch) => fetch.try_get_result()?.map(|r| r.edits.as_slice()),
+            FormattingFetch::Range(fetch) => fetch.try_get_result()?.map(|r| r.edits.as_slice()),
+        })
+    }
+
+    /// The edits, or `None` when they did not come within `timeout`.
+    pub async fn await_within(self, timeout: Duration) -> anyhow::Result<Option<Vec<Edit>>> {
+        Ok(match self {
+            FormattingFetch::Document(fetch) => fetch.await_within(timeout).await?.map(|r| r.edits),
+            FormattingFetch::Range(fetch) => fetch.await_within(timeout).await?.map(|r| r.edits),
+        })
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        match self {
+            FormattingFetch::Document(fetch) => fetch.is_cancelled(),
+            FormattingFetch::Range(fetch) => fetch.is_cancelled(),
+        }
+    }
+
+    pub fn abort(self) {
+        match self {
+            FormattingFetch::Document(fetch) => fetch.abort(),
+            FormattingFetch::Range(fetch) => fetch.abort(),
+        }
+    }
+}
+
+pub fn declare(caps: &mut ClientCapabilities) {
+    let text_document = capabilities::text_document(caps);
+    text_document.formatting = Some(DocumentFormattingClientCapabilities::default());
+    text_document.range_formatting = Some(DocumentRangeFormattingClientCapabilities::default());
+}
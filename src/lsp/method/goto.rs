@@ -0,0 +1,177 @@
+use std::path::PathBuf;
+
+use lsp_types::{ClientCapabilities, GotoCapability, GotoDefinitionParams, GotoDefinitionResponse, Uri, request::{GotoDeclaration, GotoDefinition, GotoImplementation, GotoTypeDefinition}};
+
+use ropey::Rope;
+
+use crate::{buffer::CursorPos, lsp::{capabilities::{self, Feature}, client::{LspClient, path_to_uri, uri_to_path}, position::{from_units, PositionEncoding, Positions}}};
+
+use super::{LspFetch, LspParam, LspResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GotoKind {
+    Definition,
+    Declaration,
+    TypeDefinition,
+    Implementation,
+}
+
+impl GotoKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            GotoKind::Definition => "definition",
+            GotoKind::Declaration => "declaration",
+            GotoKind::TypeDefinition => "type definition",
+            GotoKind::Implementation => "implementation",
+        }
+    }
+
+    pub fn feature(self) -> Feature {
+        match self {
+            GotoKind::Definition => Feature::Definition,
+            GotoKind::Declaration => Feature::Declaration,
+            GotoKind::TypeDefinition => Feature::TypeDefinition,
+            GotoKind::Implementation => Feature::Implementation,
+        }
+    }
+}
+
+pub fn declare(caps: &mut ClientCapabilities) {
+    // links are answered with the range of the target's name
+    let goto = GotoCapability { dynamic_registration: None, link_support: Some(true) };
+    let text_document = capabilities::text_document(caps);
+    text_document.definition = Some(goto);
+    text_document.declaration = Some(goto);
+    text_document.type_definition = Some(goto);
+    text_document.implementation = Some(goto);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub pos: CursorPos,
+}
+
+/// A location as the server sent it. The column counts in the server's
+/// encoding until it is converted with the text of the line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LspLocation {
+    pub path: PathBuf,
+    pub position: lsp_types::Position,
+    pub encoding: PositionEncoding,
+}
+
+impl LspLocation {
+    /// Where this is in `line`, the text of the line it points at.
+    pub fn resolve(&self, line: &str) -> Location {
+        let column = from_units(line.chars(), self.position.character, self.encoding);
+        Location { path: self.path.clone(), pos: (self.position.line as usize, column) }
+    }
+}
+
+pub struct GotoResult {
+    pub locations: Vec<LspLocation>,
+}
+
+pub(super) fn location(uri: &Uri, position: lsp_types::Position, encoding: PositionEncoding) -> Option<LspLocation> {
+    Some(LspLocation { path: uri_to_path(uri).ok()?, position, encoding })
+}
+
+impl LspResult for GotoResult {
+    type Response = Option<GotoDefinitionResponse>;
+    type Param = GotoDefinitionParams;
+    fn from_response(resp: Option<GotoDefinitionResponse>, _param: GotoDefinitionParams, positions: &Positions) -> Self {
+        let encoding = positions.encoding();
+        let locations = match resp {
+            None => vec![],
+            Some(GotoDefinitionResponse::Scalar(loc)) => location(&loc.uri, loc.range.start, encoding).into_iter().collect(),
+            Some(GotoDefinitionResponse::Array(locs)) => locs.iter().filter_map(|loc| location(&loc.uri, loc.range.start, encoding)).collect(),
+            // the selection range is the name itself rather than the whole item
+            Some(GotoDefinitionResponse::Link(links)) => links.iter().filter_map(|link| location(&link.target_uri, link.target_selection_range.start, encoding)).collect(),
+        };
+        GotoResult { locations }
+    }
+}
+
+pub struct GotoParam {
+    uri: Uri,
+    cursor: CursorPos,
+}
+
+impl GotoParam {
+    pub fn new<S: AsRef<std::path::Path>>(filename: S, cursor: CursorPos) -> anyhow::Result<Self> {
+        Ok(Self {
+            uri: path_to_uri(filename)?,
+            cursor,
+        })
+    }
+}
+
+impl LspParam for GotoParam {
+    type ActualParam = GotoDefinitionParams;
+    fn into_param(self, positions: &Positions) -> Self::ActualParam {
+        GotoDefinitionParams {
+            text_document_position_params: lsp_types::TextDocumentPositionParams {
+                text_document: lsp_types::TextDocumentIdentifier { uri: self.uri },
+                position: positions.to_lsp(self.cursor),
+            },
+            work_done_progress_params: lsp_types::WorkDoneProgressParams { work_done_token: None },
+            partial_result_params: lsp_types::PartialResultParams { partial_result_token: None },
+        }
+    }
+}
+
+/// The four requests share their parameters and results but not their methods.
+pub enum GotoFetch {
+    Definition(LspFetch<GotoDefinition, GotoResult>),
+    Declaration(LspFetch<GotoDeclaration, GotoResult>),
+    TypeDefinition(LspFetch<GotoTypeDefinition, GotoResult>),
+    Implementation(LspFetch<GotoImplementation, GotoResult>),
+}
+
+impl GotoFetch {
+    pub fn new(client: &LspClient, kind: GotoKind, text: Rope, version: i32, param: GotoParam) -> anyhow::Result<Self> {
+        Ok(match kind {
+            GotoKind::Definition => GotoFetch::Definition(LspFetch::in_text(client, text, version, param)?),
+            GotoKind::Declaration => GotoFetch::Declaration(LspFetch::in_text(client, text, version, param)?),
+            GotoKind::TypeDefinition => GotoFetch::TypeDefinition(LspFetch::in_text(client, text, version, param)?),
+            GotoKind::Implementation => GotoFetch::Implementation(LspFetch::in_text(client, text, version, param)?),
+        })
+    }
+
+    pub fn kind(&self) -> GotoKind {
+        match self {
+            GotoFetch::Definition(_) => GotoKind::Definition,
+            GotoFetch::Declaration(_) => GotoKind::Declaration,
+            GotoFetch::TypeDefinition(_) => GotoKind::TypeDefinition,
+            GotoFetch::Implementation(_) => GotoKind::Implementation,
+        }
+    }
+
+    pub fn try_get_result(&mut self) -> anyhow::Result<Option<&GotoResult>> {
+        match self {
+            GotoFetch::Definition(fetch) => fetch.try_get_result(),
+            GotoFetch::Declaration(fetch) => fetch.try_get_result(),
+            GotoFetch::TypeDefinition(fetch) => fetch.try_get_result(),
+            GotoFetch::Implementation(fetch) => fetch.try_get_result(),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        match self {
+            GotoFetch::Definition(fetch) => fetch.is_cancelled(),
+            GotoFetch::Declaration(fetch) => fetch.is_cancelled(),
+            GotoFetch::TypeDefinition(fetch) => fetch.is_cancelled(),
+            GotoFetch::Implementation(fetch) => fetch.is_cancelled(),
+        }
+    }
+
+    pub fn abort(self) {
+        match self {
+            GotoFetch::Definition(fetch) => fetch.abort(),
+            GotoFetch::Declaration(fetch) => fetch.abort(),
+            GotoFetch::TypeDefinition(fetch) => fetch.abort(),
+            GotoFetch::Implementation(fetch) => fetch.abort(),
+        }
+    }
+}
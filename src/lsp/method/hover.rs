@@ -1,48 +1,37 @@
 use lsp_types::{MarkedString, request::HoverRequest};
-use lsp_types::{Hover, HoverContents, HoverParams, Uri};
+use lsp_types::{Hover, HoverClientCapabilities, HoverContents, HoverParams, MarkupKind, Uri};
 
-use crate::{buffer::CursorPos, lsp::client::{LspClient, TryGetResponse, ResponseReceiver, path_to_uri}};
+use crate::{buffer::CursorPos, lsp::{capabilities, client::{LspClient, TryGetResponse, ResponseReceiver, path_to_uri}, position::Positions}};
 
 use super::{LspFetch, LspParam, LspResult};
 
 pub struct HoverResult {
     pub text: String,
+    /// Whether `text` is markdown rather than plain text.
+    pub markdown: bool,
     pub pos: CursorPos,
 }
 
+/// Marked strings are markdown; code in a language becomes a fenced block.
+fn marked_string(s: MarkedString) -> String {
+    match s {
+        MarkedString::String(s) => s,
+        MarkedString::LanguageString(ls) => format!("```{}\n{}\n```", ls.language, ls.value),
+    }
+}
+
 impl LspResult for Option<HoverResult> {
     type Response = Option<Hover>;
     type Param = HoverParams;
-    fn from_response(resp: Option<Hover>, param: HoverParams) -> Self {
+    fn from_response(resp: Option<Hover>, param: HoverParams, positions: &Positions) -> Self {
         resp.map(|resp| {
-            let pos = (param.text_document_position_params.position.line as usize, param.text_document_position_params.position.character as usize);
-
-            match resp.contents {
-                HoverContents::Markup(content) => {
-                    HoverResult { text: content.value, pos }
-                }
-                HoverContents::Array(vec) => {
-                    HoverResult {
-                        text: vec.into_iter().map(|hover| {
-                            match hover {
-                                MarkedString::String(s) => s,
-                                MarkedString::LanguageString(ls) => ls.value,
-                            }
-                        }).collect::<Vec<_>>().join("\n"),
-                        pos,
-                    }
-                }
-                HoverContents::Scalar(s) => {
-                    HoverResult {
-                        text: 
-                            match s {
-                                MarkedString::String(s) => s,
-                                MarkedString::LanguageString(ls) => ls.value,
-                            },
-                            pos,
-                    }
-                }
-            }
+            let pos = positions.from_lsp(param.text_document_position_params.position);
+            let (text, markdown) = match resp.contents {
+                HoverContents::Markup(content) => (content.value, content.kind == MarkupKind::Markdown),
+                HoverContents::Array(vec) => (vec.into_iter().map(marked_string).collect::<Vec<_>>().join("\n\n"), true),
+                HoverContents::Scalar(s) => (marked_string(s), true),
+            };
+            HoverResult { text, markdown, pos }
         })
     }
 }
@@ -63,11 +52,11 @@ impl HoverParam {
 
 impl LspParam for HoverParam {
     type ActualParam = HoverParams;
-    fn into_param(self) -> Self::ActualParam {
+    fn into_param(self, positions: &Positions) -> Self::ActualParam {
         lsp_types::HoverParams {
             text_document_position_params: lsp_types::TextDocumentPositionParams {
                 text_document: lsp_types::TextDocumentIdentifier { uri: self.uri }, 
-                position: lsp_types::Position { line: self.cursor.0 as u32, character: self.cursor.1 as u32 },
+                position: positions.to_lsp(self.cursor),
             },
             work_done_progress_params: lsp_types::WorkDoneProgressParams { work_done_token: None }
         }
@@ -75,3 +64,10 @@ impl LspParam for HoverParam {
 }
 
 pub type HoverFetch = LspFetch<HoverRequest, Option<HoverResult>>;
+
+pub fn declare(caps: &mut lsp_types::ClientCapabilities) {
+    capabilities::text_document(caps).hover = Some(HoverClientCapabilities {
+        dynamic_registration: None,
+        content_format: Some(vec![MarkupKind::Markdown, MarkupKind::PlainText]),
+    });
+}
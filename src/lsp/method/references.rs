@@ -0,0 +1,58 @@
+use lsp_types::{ClientCapabilities, ReferenceClientCapabilities, ReferenceContext, ReferenceParams, Uri, request::References};
+
+use crate::{buffer::CursorPos, lsp::{capabilities, client::path_to_uri, position::Positions}};
+
+use super::{LspFetch, LspParam, LspResult, goto::{LspLocation, location}};
+
+pub struct ReferencesResult {
+    pub locations: Vec<LspLocation>,
+}
+
+impl LspResult for ReferencesResult {
+    type Response = Option<Vec<lsp_types::Location>>;
+    type Param = ReferenceParams;
+    fn from_response(resp: Option<Vec<lsp_types::Location>>, _param: ReferenceParams, positions: &Positions) -> Self {
+        let mut locations = resp.unwrap_or_default().iter()
+            .filter_map(|loc| location(&loc.uri, loc.range.start, positions.encoding()))
+            .collect::<Vec<_>>();
+        locations.sort_by(|a, b| (&a.path, a.position).cmp(&(&b.path, b.position)));
+        locations.dedup();
+        ReferencesResult { locations }
+    }
+}
+
+pub struct ReferencesParam {
+    uri: Uri,
+    cursor: CursorPos,
+}
+
+impl ReferencesParam {
+    pub fn new<S: AsRef<std::path::Path>>(filename: S, cursor: CursorPos) -> anyhow::Result<Self> {
+        Ok(Self {
+            uri: path_to_uri(filename)?,
+            cursor,
+        })
+    }
+}
+
+impl LspParam for ReferencesParam {
+    type ActualParam = ReferenceParams;
+    fn into_param(self, positions: &Positions) -> Self::ActualParam {
+        ReferenceParams {
+            text_document_position: lsp_types::TextDocumentPositionParams {
+                text_document: lsp_types::TextDocumentIdentifier { uri: self.uri },
+                position: positions.to_lsp(self.cursor),
+            },
+            work_done_progress_params: lsp_types::WorkDoneProgressParams { work_done_token: None },
+            partial_result_params: lsp_types::PartialResultParams { partial_result_token: None },
+            // the symbol under the cursor is listed too
+            context: ReferenceContext { include_declaration: true },
+        }
+    }
+}
+
+pub type ReferencesFetch = LspFetch<References, ReferencesResult>;
+
+pub fn declare(caps: &mut ClientCapabilities) {
+    capabilities::text_document(caps).references = Some(ReferenceClientCapabilities::default());
+}
@@ -0,0 +1,105 @@
+use lsp_types::{ClientCapabilities, PrepareRenameResponse, RenameClientCapabilities, RenameParams, TextDocumentPositionParams, Uri, WorkspaceEdit, request::{PrepareRenameRequest, Rename}};
+
+use crate::{buffer::CursorPos, lsp::{capabilities, client::path_to_uri, position::{PositionEncoding, Positions}}};
+
+use super::{LspFetch, LspParam, LspResult};
+
+/// What `prepareRename` allows to rename. Both are `None` when the server
+/// leaves it to the editor to pick the word under the cursor.
+pub struct PrepareRenameResult {
+    pub range: Option<(CursorPos, CursorPos)>,
+    pub placeholder: Option<String>,
+}
+
+/// `None` when there is nothing to rename at the position.
+impl LspResult for Option<PrepareRenameResult> {
+    type Response = Option<PrepareRenameResponse>;
+    type Param = TextDocumentPositionParams;
+    fn from_response(resp: Option<PrepareRenameResponse>, _param: TextDocumentPositionParams, positions: &Positions) -> Self {
+        resp.map(|resp| match resp {
+            PrepareRenameResponse::Range(range) => PrepareRenameResult { range: Some((positions.from_lsp(range.start), positions.from_lsp(range.end))), placeholder: None },
+            PrepareRenameResponse::RangeWithPlaceholder { range, placeholder } => PrepareRenameResult { range: Some((positions.from_lsp(range.start), positions.from_lsp(range.end))), placeholder: Some(placeholder) },
+            PrepareRenameResponse::DefaultBehavior { .. } => PrepareRenameResult { range: None, placeholder: None },
+        })
+    }
+}
+
+pub struct PrepareRenameParam {
+    uri: Uri,
+    cursor: CursorPos,
+}
+
+impl PrepareRenameParam {
+    pub fn new<S: AsRef<std::path::Path>>(filename: S, cursor: CursorPos) -> anyhow::Result<Self> {
+        Ok(Self {
+            uri: path_to_uri(filename)?,
+            cursor,
+        })
+    }
+}
+
+impl LspParam for PrepareRenameParam {
+    type ActualParam = TextDocumentPositionParams;
+    fn into_param(self, positions: &Positions) -> Self::ActualParam {
+        TextDocumentPositionParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri: self.uri },
+            position: positions.to_lsp(self.cursor),
+        }
+    }
+}
+
+pub type PrepareRenameFetch = LspFetch<PrepareRenameRequest, Option<PrepareRenameResult>>;
+
+pub struct RenameResult {
+    /// `None` when the server found nothing to change.
+    pub edit: Option<WorkspaceEdit>,
+    /// How the columns of `edit` are counted.
+    pub encoding: PositionEncoding,
+}
+
+impl LspResult for RenameResult {
+    type Response = Option<WorkspaceEdit>;
+    type Param = RenameParams;
+    fn from_response(resp: Option<WorkspaceEdit>, _param: RenameParams, positions: &Positions) -> Self {
+        RenameResult { edit: resp, encoding: positions.encoding() }
+    }
+}
+
+pub struct RenameParam {
+    uri: Uri,
+    cursor: CursorPos,
+    new_name: String,
+}
+
+impl RenameParam {
+    pub fn new<S: AsRef<std::path::Path>>(filename: S, cursor: CursorPos, new_name: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            uri: path_to_uri(filename)?,
+            cursor,
+            new_name: new_name.to_owned(),
+        })
+    }
+}
+
+impl LspParam for RenameParam {
+    type ActualParam = RenameParams;
+    fn into_param(self, positions: &Positions) -> Self::ActualParam {
+        RenameParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: lsp_types::TextDocumentIdentifier { uri: self.uri },
+                position: positions.to_lsp(self.cursor),
+            },
+            new_name: self.new_name,
+            work_done_progress_params: lsp_types::WorkDoneProgressParams { work_done_token: None },
+        }
+    }
+}
+
+pub type RenameFetch = LspFetch<Rename, RenameResult>;
+
+pub fn declare(caps: &mut ClientCapabilities) {
+    capabilities::text_document(caps).rename = Some(RenameClientCapabilities {
+        prepare_support: Some(true),
+        ..Default::default()
+    });
+}
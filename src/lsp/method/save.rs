@@ -0,0 +1,54 @@
+use lsp_types::{DidSaveTextDocumentParams, TextDocumentIdentifier, TextDocumentSaveReason, TextDocumentSyncClientCapabilities, TextDocumentSyncCapability, TextDocumentSyncSaveOptions, WillSaveTextDocumentParams, notification::{DidSaveTextDocument, WillSaveTextDocument}};
+
+use crate::lsp::{capabilities, client::{LspClient, path_to_uri}};
+
+fn will_save_supported(client: &LspClient) -> bool {
+    match client.server_capabilities().text_document_sync {
+        Some(TextDocumentSyncCapability::Options(ref options)) => options.will_save.unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// `Some(include_text)` when the server wants `textDocument/didSave`.
+fn did_save_supported(client: &LspClient) -> Option<bool> {
+    match client.server_capabilities().text_document_sync {
+        Some(TextDocumentSyncCapability::Options(ref options)) => {
+            match options.save {
+                Some(TextDocumentSyncSaveOptions::Supported(true)) => Some(false),
+                Some(TextDocumentSyncSaveOptions::SaveOptions(ref save)) => Some(save.include_text.unwrap_or(false)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+pub fn notify_will_save<S: AsRef<std::path::Path>>(client: &LspClient, filename: S) -> anyhow::Result<()> {
+    if will_save_supported(client) {
+        client.notify::<WillSaveTextDocument>(
+            WillSaveTextDocumentParams {
+                text_document: TextDocumentIdentifier { uri: path_to_uri(filename)? },
+                reason: TextDocumentSaveReason::MANUAL,
+            })?;
+    }
+    Ok(())
+}
+
+pub fn notify_did_save<S: AsRef<std::path::Path>>(client: &LspClient, filename: S, text: impl FnOnce() -> String) -> anyhow::Result<()> {
+    if let Some(include_text) = did_save_supported(client) {
+        client.notify::<DidSaveTextDocument>(
+            DidSaveTextDocumentParams {
+                text_document: TextDocumentIdentifier { uri: path_to_uri(filename)? },
+                text: if include_text { Some(text()) } else { None },
+            })?;
+    }
+    Ok(())
+}
+
+pub fn declare(caps: &mut lsp_types::ClientCapabilities) {
+    capabilities::text_document(caps).synchronization = Some(TextDocumentSyncClientCapabilities {
+        will_save: Some(true),
+        did_save: Some(true),
+        ..Default::default()
+    });
+}
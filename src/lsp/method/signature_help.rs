@@ -0,0 +1,131 @@
+use lsp_types::{ClientCapabilities, MarkupKind, ParameterInformationSettings, ParameterLabel, SignatureHelp, SignatureHelpClientCapabilities, SignatureHelpContext, SignatureHelpParams, SignatureHelpTriggerKind, SignatureInformation, SignatureInformationSettings, Uri, request::SignatureHelpRequest};
+
+use crate::{buffer::CursorPos, lsp::{capabilities, client::{LspClient, path_to_uri}, position::Positions}, viewer::signature_help_viewer::SignatureHelpViewer};
+
+use super::{LspFetch, LspParam, LspResult};
+
+/// `None` when the server knows no signature here, which closes the popup.
+impl LspResult for Option<SignatureHelpViewer> {
+    type Response = Option<SignatureHelp>;
+    type Param = SignatureHelpParams;
+    fn from_response(resp: Option<SignatureHelp>, _param: SignatureHelpParams, _positions: &Positions) -> Self {
+        resp.filter(|help| !help.signatures.is_empty()).map(SignatureHelpViewer::new)
+    }
+}
+
+pub fn declare(caps: &mut ClientCapabilities) {
+    capabilities::text_document(caps).signature_help = Some(SignatureHelpClientCapabilities {
+        dynamic_registration: None,
+        signature_information: Some(SignatureInformationSettings {
+            // the popup shows one line of it as it is
+            documentation_format: Some(vec![MarkupKind::PlainText]),
+            parameter_information: Some(ParameterInformationSettings { label_offset_support: Some(true) }),
+            active_parameter_support: Some(true),
+        }),
+        context_support: Some(true),
+    });
+}
+
+/// Why signature help is asked for, or `None` when typing `typed` does not
+/// ask for it. `active` is the help shown now: while it is, retrigger
+/// characters and any other edit update it.
+pub fn context(client: &LspClient, typed: Option<char>, active: Option<&SignatureHelp>) -> Option<SignatureHelpContext> {
+    let provider = client.server_capabilities().signature_help_provider.as_ref()?;
+    let listed = |chars: &Option<Vec<String>>, c: char| chars.iter().flatten().any(|s| s.chars().eq(std::iter::once(c)));
+    let is_retrigger = active.is_some();
+    let trigger_kind = match typed {
+        Some(c) if listed(&provider.trigger_characters, c) => SignatureHelpTriggerKind::TRIGGER_CHARACTER,
+        Some(c) if is_retrigger && listed(&provider.retrigger_characters, c) => SignatureHelpTriggerKind::TRIGGER_CHARACTER,
+        _ if is_retrigger => SignatureHelpTriggerKind::CONTENT_CHANGE,
+        _ => return None,
+    };
+    Some(SignatureHelpContext {
+        trigger_character: typed.filter(|_| trigger_kind == SignatureHelpTriggerKind::TRIGGER_CHARACTER).map(String::from),
+        trigger_kind,
+        is_retrigger,
+        active_signature_help: active.cloned(),
+    })
+}
+
+/// The characters of `signature.label` naming its active parameter.
+/// Offsets sent by the server count UTF-16 code units.
+pub fn active_parameter_range(help: &SignatureHelp, signature: &SignatureInformation) -> Option<(usize, usize)> {
+    let active = signature.active_parameter.or(help.active_parameter)? as usize;
+    match signature.parameters.as_ref()?.get(active)?.label {
+        ParameterLabel::Simple(ref name) => {
+            let start = signature.label.find(name.as_str())?;
+            let start = signature.label[..start].chars().count();
+            Some((start, start + name.chars().count()))
+        }
+        ParameterLabel::LabelOffsets([start, end]) => {
+            let mut units = 0;
+            let mut range = (None, None);
+            for (idx, c) in signature.label.chars().chain(std::iter::once('\0')).enumerate() {
+                if units == start as usize {
+                    range.0 = Some(idx);
+                }
+                if units == end as usize {
+                    range.1 = Some(idx);
+                }
+                units += c.len_utf16();
+            }
+            Some((range.0?, range.1?))
+        }
+    }
+}
+
+pub struct SignatureHelpParam {
+    uri: Uri,
+    cursor: CursorPos,
+    context: SignatureHelpContext,
+}
+
+impl SignatureHelpParam {
+    pub fn new<S: AsRef<std::path::Path>>(filename: S, cursor: CursorPos, context: SignatureHelpContext) -> anyhow::Result<Self> {
+        Ok(Self {
+            uri: path_to_uri(filename)?,
+            cursor,
+            context,
+        })
+    }
+}
+
+impl LspParam for SignatureHelpParam {
+    type ActualParam = SignatureHelpParams;
+    fn into_param(self, positions: &Positions) -> Self::ActualParam {
+        SignatureHelpParams {
+            context: Some(self.context),
+            text_document_position_params: lsp_types::TextDocumentPositionParams {
+                text_document: lsp_types::TextDocumentIdentifier { uri: self.uri },
+                position: positions.to_lsp(self.cursor),
+            },
+            work_done_progress_params: lsp_types::WorkDoneProgressParams { work_done_token: None },
+        }
+    }
+}
+
+pub type SignatureHelpFetch = LspFetch<SignatureHelpRequest, Option<SignatureHelpViewer>>;
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::SignatureHelp;
+    use serde_json::json;
+
+    use super::active_parameter_range;
+
+    fn range(help: serde_json::Value) -> Option<(usize, usize)> {
+        let help = serde_json::from_value::<SignatureHelp>(help).unwrap();
+        active_parameter_range(&help, &help.signatures[0])
+    }
+
+    #[test]
+    fn parameter_ranges() {
+        let by_name = json!({ "signatures": [{ "label": "int f(int a, int b)", "parameters": [{ "label": "int a" }, { "label": "int b" }] }], "activeParameter": 1 });
+        assert_eq!(range(by_name), Some((13, 18)));
+        // the signature's own active parameter wins; offsets are UTF-16 units
+        let by_offsets = json!({ "signatures": [{ "label": "f(𝑥, y)", "parameters": [{ "label": [2, 4] }, { "label": [6, 7] }], "activeParameter": 1 }], "activeParameter": 0 });
+        assert_eq!(range(by_offsets), Some((5, 6)));
+        let past_the_end = json!({ "signatures": [{ "label": "f()", "parameters": [] }], "activeParameter": 0 });
+        assert_eq!(range(past_the_end), None);
+    }
+}
@@ -0,0 +1,98 @@
+use lsp_types::{ClientCapabilities, OneOf, SymbolKind, WorkspaceSymbolClientCapabilities, WorkspaceSymbolParams, WorkspaceSymbolResponse, request::WorkspaceSymbolRequest};
+
+use crate::lsp::{capabilities, position::Positions};
+
+use super::{LspFetch, LspParam, LspResult, goto::{LspLocation, location}};
+
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub container: Option<String>,
+    pub location: LspLocation,
+}
+
+impl Symbol {
+    /// `container::name (kind)`, as shown in the location list.
+    pub fn describe(&self) -> String {
+        match self.container {
+            Some(ref container) if !container.is_empty() => format!("{}::{} ({})", container, self.name, kind_label(self.kind)),
+            _ => format!("{} ({})", self.name, kind_label(self.kind)),
+        }
+    }
+}
+
+pub fn kind_label(kind: SymbolKind) -> &'static str {
+    match kind {
+        SymbolKind::FILE => "file",
+        SymbolKind::MODULE => "module",
+        SymbolKind::NAMESPACE => "namespace",
+        SymbolKind::PACKAGE => "package",
+        SymbolKind::CLASS => "class",
+        SymbolKind::METHOD => "method",
+        SymbolKind::PROPERTY => "property",
+        SymbolKind::FIELD => "field",
+        SymbolKind::CONSTRUCTOR => "constructor",
+        SymbolKind::ENUM => "enum",
+        SymbolKind::INTERFACE => "interface",
+        SymbolKind::FUNCTION => "function",
+        SymbolKind::VARIABLE => "variable",
+        SymbolKind::CONSTANT => "constant",
+        SymbolKind::STRUCT => "struct",
+        SymbolKind::ENUM_MEMBER => "enum member",
+        SymbolKind::TYPE_PARAMETER => "type parameter",
+        _ => "symbol",
+    }
+}
+
+pub struct SymbolsResult {
+    pub symbols: Vec<Symbol>,
+}
+
+impl LspResult for SymbolsResult {
+    type Response = Option<WorkspaceSymbolResponse>;
+    type Param = WorkspaceSymbolParams;
+    fn from_response(resp: Option<WorkspaceSymbolResponse>, _param: WorkspaceSymbolParams, positions: &Positions) -> Self {
+        let symbols = match resp {
+            None => vec![],
+            Some(WorkspaceSymbolResponse::Flat(symbols)) => symbols.into_iter().filter_map(|s| {
+                Some(Symbol { location: location(&s.location.uri, s.location.range.start, positions.encoding())?, name: s.name, kind: s.kind, container: s.container_name })
+            }).collect(),
+            Some(WorkspaceSymbolResponse::Nested(symbols)) => symbols.into_iter().filter_map(|s| {
+                // a location without a range points at the file only
+                let location = match s.location {
+                    OneOf::Left(loc) => location(&loc.uri, loc.range.start, positions.encoding())?,
+                    OneOf::Right(loc) => location(&loc.uri, lsp_types::Position::new(0, 0), positions.encoding())?,
+                };
+                Some(Symbol { location, name: s.name, kind: s.kind, container: s.container_name })
+            }).collect(),
+        };
+        SymbolsResult { symbols }
+    }
+}
+
+pub struct SymbolsParam {
+    query: String,
+}
+
+impl SymbolsParam {
+    pub fn new(query: &str) -> Self {
+        Self { query: query.to_owned() }
+    }
+}
+
+impl LspParam for SymbolsParam {
+    type ActualParam = WorkspaceSymbolParams;
+    fn into_param(self, _positions: &Positions) -> Self::ActualParam {
+        WorkspaceSymbolParams {
+            query: self.query,
+            work_done_progress_params: lsp_types::WorkDoneProgressParams { work_done_token: None },
+            partial_result_params: lsp_types::PartialResultParams { partial_result_token: None },
+        }
+    }
+}
+
+pub type SymbolsFetch = LspFetch<WorkspaceSymbolRequest, SymbolsResult>;
+
+pub fn declare(caps: &mut ClientCapabilities) {
+    capabilities::workspace(caps).symbol = Some(WorkspaceSymbolClientCapabilities::default());
+}
@@ -48,6 +48,15 @@ impl From<i32> for RequestId {
     }
 }
 
+impl From<RequestId> for lsp_types::NumberOrString {
+    fn from(id: RequestId) -> lsp_types::NumberOrString {
+        match id.0 {
+            IdRepr::I32(it) => lsp_types::NumberOrString::Number(it),
+            IdRepr::String(it) => lsp_types::NumberOrString::String(it),
+        }
+    }
+}
+
 impl From<String> for RequestId {
     fn from(id: String) -> RequestId {
         RequestId(IdRepr::String(id))
@@ -81,7 +90,8 @@ pub struct Response {
     // to decode the request's id. Ignore this special case
     // and just die horribly.
     pub id: RequestId,
-    #[serde(skip_serializing_if = "Option::is_none", deserialize_with="deserialize_result")]
+    // absent from error responses
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with="deserialize_result")]
     pub result: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<ResponseError>,
@@ -112,6 +122,14 @@ impl Display for ResponseError {
 
 impl Error for ResponseError {}
 
+impl ResponseError {
+    /// Whether the request was called off or overtaken by an edit, rather
+    /// than failed: there is no answer and nothing to report.
+    pub fn is_cancelled(&self) -> bool {
+        [ErrorCode::RequestCanceled, ErrorCode::ContentModified, ErrorCode::ServerCancelled].iter().any(|&code| self.code == code as i32)
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 #[non_exhaustive]
 pub enum ErrorCode {
@@ -178,7 +196,7 @@ macro_rules! invalid_data {
 
 impl Message {
     pub async fn read<R: AsyncBufReadExt + AsyncReadExt + Unpin>(r: &mut R) -> anyhow::Result<Option<Message>> {
-        Ok(Message::_read(r).await?)
+        Message::_read(r).await
     }
     async fn _read<R: AsyncBufReadExt + AsyncReadExt + Unpin>(r: &mut R) -> anyhow::Result<Option<Message>> {
         let text = match read_msg_text(r).await? {
@@ -223,7 +241,7 @@ impl Response {
     ) -> anyhow::Result<P> {
         match serde_json::from_value(self.result.unwrap()) {
             Ok(params) => Ok(params),
-            Err(error) => Err(anyhow!("extract error")),
+            Err(error) => Err(anyhow!("extract error: {}", error)),
         }
     }
 }
@@ -321,7 +339,7 @@ async fn write_msg_text<W: AsyncWriteExt + Unpin>(out: &mut W, msg: &str) -> any
 
 #[cfg(test)]
 mod tests {
-    use super::{Message, Notification, Request, RequestId};
+    use super::{ErrorCode, Message, Notification, Request, RequestId, ResponseError};
 
     #[test]
     fn shutdown_with_explicit_null() {
@@ -381,4 +399,21 @@ mod tests {
 
         assert_eq!("{\"method\":\"exit\"}", serialized);
     }
+
+    #[test]
+    fn error_response_without_result() {
+        let text = "{\"jsonrpc\": \"2.0\",\"id\": 2,\"error\": {\"code\": -32801, \"message\": \"modified\"}}";
+        let msg: Message = serde_json::from_str(text).unwrap();
+
+        assert!(matches!(msg, Message::Response(res) if res.result.is_none() && res.error.as_ref().is_some_and(ResponseError::is_cancelled)));
+    }
+
+    #[test]
+    fn cancelled_errors() {
+        let error = |code: ErrorCode| ResponseError { code: code as i32, message: String::new(), data: None };
+        assert!(error(ErrorCode::RequestCanceled).is_cancelled());
+        assert!(error(ErrorCode::ContentModified).is_cancelled());
+        assert!(!error(ErrorCode::InternalError).is_cancelled());
+        assert_eq!(lsp_types::NumberOrString::from(RequestId::from(7)), lsp_types::NumberOrString::Number(7));
+    }
 }
@@ -0,0 +1,153 @@
+//! Columns as the server counts them. A `CursorPos` column counts chars,
+//! while servers count UTF-16 code units unless another encoding was
+//! agreed on in `initialize`.
+
+use lsp_types::{ClientCapabilities, Position, PositionEncodingKind, Range, TextEdit};
+use ropey::Rope;
+
+use crate::buffer::{CursorPos, edit::Edit};
+
+use super::capabilities;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PositionEncoding {
+    Utf8,
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Offered in `initialize`, most preferred first: UTF-32 counts chars
+    /// as the editor does, and UTF-8 is cheaper to convert than UTF-16.
+    const OFFERED: [PositionEncoding; 3] = [PositionEncoding::Utf32, PositionEncoding::Utf8, PositionEncoding::Utf16];
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    /// The encoding the server chose; UTF-16 when it does not say.
+    pub fn negotiated(kind: Option<&PositionEncodingKind>) -> Self {
+        Self::OFFERED.into_iter().find(|encoding| Some(&encoding.kind()) == kind).unwrap_or_default()
+    }
+
+    fn units(self, c: char) -> usize {
+        match self {
+            PositionEncoding::Utf8 => c.len_utf8(),
+            PositionEncoding::Utf16 => c.len_utf16(),
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+}
+
+pub fn declare(caps: &mut ClientCapabilities) {
+    capabilities::general(caps).position_encodings = Some(PositionEncoding::OFFERED.map(PositionEncoding::kind).to_vec());
+}
+
+/// The units of `encoding` taken by the first `column` chars of `line`.
+pub fn to_units(line: impl IntoIterator<Item = char>, column: usize, encoding: PositionEncoding) -> u32 {
+    line.into_iter().take(column).map(|c| encoding.units(c)).sum::<usize>() as u32
+}
+
+/// The char column `units` of `encoding` point at in `line`. A count that
+/// ends inside a char points at its start, and one past the end of the
+/// line at the end, before the line break.
+pub fn from_units(line: impl IntoIterator<Item = char>, units: u32, encoding: PositionEncoding) -> usize {
+    let (mut column, mut count) = (0, 0);
+    for c in line {
+        if c == '\n' || c == '\r' {
+            break;
+        }
+        count += encoding.units(c);
+        if count > units as usize {
+            break;
+        }
+        column += 1;
+    }
+    column
+}
+
+/// Converts positions in one version of a document to and from those of a
+/// server. Positions past the end of the text are passed through as they are.
+#[derive(Debug, Clone)]
+pub struct Positions {
+    rope: Rope,
+    encoding: PositionEncoding,
+}
+
+impl Positions {
+    pub fn new(rope: Rope, encoding: PositionEncoding) -> Self {
+        Self { rope, encoding }
+    }
+
+    pub fn encoding(&self) -> PositionEncoding {
+        self.encoding
+    }
+
+    pub fn to_lsp(&self, pos: CursorPos) -> Position {
+        let character = match self.rope.get_line(pos.0) {
+            Some(line) => to_units(line.chars(), pos.1, self.encoding),
+            None => pos.1 as u32,
+        };
+        Position::new(pos.0 as u32, character)
+    }
+
+    pub fn range_to_lsp(&self, start: CursorPos, end: CursorPos) -> Range {
+        Range::new(self.to_lsp(start), self.to_lsp(end))
+    }
+
+    pub fn from_lsp(&self, pos: Position) -> CursorPos {
+        let line = pos.line as usize;
+        match self.rope.get_line(line) {
+            Some(text) => (line, from_units(text.chars(), pos.character, self.encoding)),
+            None => (line, pos.character as usize),
+        }
+    }
+
+    pub fn edit_from_lsp(&self, edit: &TextEdit) -> Edit {
+        (self.from_lsp(edit.range.start), self.from_lsp(edit.range.end), edit.new_text.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Position, PositionEncodingKind};
+    use ropey::Rope;
+
+    use super::{from_units, to_units, PositionEncoding, Positions};
+
+    #[test]
+    fn units() {
+        // 'あ' is 3 bytes and one UTF-16 unit, '😀' 4 bytes and two units
+        let line = "aあ😀b\n";
+        assert_eq!(to_units(line.chars(), 3, PositionEncoding::Utf16), 4);
+        assert_eq!(to_units(line.chars(), 3, PositionEncoding::Utf8), 8);
+        assert_eq!(to_units(line.chars(), 3, PositionEncoding::Utf32), 3);
+        assert_eq!(from_units(line.chars(), 4, PositionEncoding::Utf16), 3);
+        assert_eq!(from_units(line.chars(), 8, PositionEncoding::Utf8), 3);
+        // inside the surrogate pair, and past the end of the line
+        assert_eq!(from_units(line.chars(), 3, PositionEncoding::Utf16), 2);
+        assert_eq!(from_units(line.chars(), 99, PositionEncoding::Utf16), 4);
+    }
+
+    #[test]
+    fn round_trip() {
+        let rope = Rope::from_str("// 日本語のコメント\nint x;\n");
+        let positions = Positions::new(rope, PositionEncoding::Utf8);
+        assert_eq!(positions.to_lsp((0, 5)), Position::new(0, 9));
+        assert_eq!(positions.from_lsp(Position::new(0, 9)), (0, 5));
+        assert_eq!(positions.to_lsp((1, 4)), Position::new(1, 4));
+        assert_eq!(positions.from_lsp(Position::new(5, 2)), (5, 2));
+    }
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(PositionEncoding::negotiated(None), PositionEncoding::Utf16);
+        assert_eq!(PositionEncoding::negotiated(Some(&PositionEncodingKind::UTF8)), PositionEncoding::Utf8);
+        assert_eq!(PositionEncoding::negotiated(Some(&PositionEncodingKind::new("utf-7"))), PositionEncoding::Utf16);
+    }
+}
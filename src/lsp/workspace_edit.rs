@@ -0,0 +1,133 @@
+use std::path::PathBuf;
+
+use lsp_types::{ClientCapabilities, DocumentChangeOperation, DocumentChanges, OneOf, ResourceOp, ResourceOperationKind, TextDocumentEdit, WorkspaceEdit, WorkspaceEditClientCapabilities};
+
+use crate::buffer::edit::{from_lsp, Edit};
+
+use super::{capabilities, client::uri_to_path};
+
+pub fn declare(caps: &mut ClientCapabilities) {
+    capabilities::workspace(caps).workspace_edit = Some(WorkspaceEditClientCapabilities {
+        document_changes: Some(true),
+        resource_operations: Some(vec![ResourceOperationKind::Create, ResourceOperationKind::Rename, ResourceOperationKind::Delete]),
+        ..Default::default()
+    });
+}
+
+/// One step of a `WorkspaceEdit`, in the order it has to be applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditOp {
+    /// `version` is the document version the edits were computed for, when
+    /// the server sent one. Columns are still in the server's units.
+    Edit { path: PathBuf, version: Option<i32>, edits: Vec<Edit> },
+    Create { path: PathBuf, overwrite: bool, ignore_if_exists: bool },
+    Rename { from: PathBuf, to: PathBuf, overwrite: bool, ignore_if_exists: bool },
+    Delete { path: PathBuf, recursive: bool, ignore_if_not_exists: bool },
+}
+
+fn document_edit(doc: TextDocumentEdit) -> anyhow::Result<EditOp> {
+    let edits = doc.edits.iter().map(|e| match e {
+        OneOf::Left(e) => from_lsp(e),
+        OneOf::Right(e) => from_lsp(&e.text_edit),
+    }).collect();
+    Ok(EditOp::Edit { path: uri_to_path(&doc.text_document.uri)?, version: doc.text_document.version, edits })
+}
+
+fn resource_op(op: ResourceOp) -> anyhow::Result<EditOp> {
+    Ok(match op {
+        ResourceOp::Create(op) => {
+            let options = op.options.as_ref();
+            EditOp::Create {
+                path: uri_to_path(&op.uri)?,
+                overwrite: options.and_then(|o| o.overwrite) == Some(true),
+                ignore_if_exists: options.and_then(|o| o.ignore_if_exists) == Some(true),
+            }
+        }
+        ResourceOp::Rename(op) => {
+            let options = op.options.as_ref();
+            EditOp::Rename {
+                from: uri_to_path(&op.old_uri)?,
+                to: uri_to_path(&op.new_uri)?,
+                overwrite: options.and_then(|o| o.overwrite) == Some(true),
+                ignore_if_exists: options.and_then(|o| o.ignore_if_exists) == Some(true),
+            }
+        }
+        ResourceOp::Delete(op) => {
+            let options = op.options.as_ref();
+            EditOp::Delete {
+                path: uri_to_path(&op.uri)?,
+                recursive: options.and_then(|o| o.recursive) == Some(true),
+                ignore_if_not_exists: options.and_then(|o| o.ignore_if_not_exists) == Some(true),
+            }
+        }
+    })
+}
+
+/// Flatten `edit` into steps. `documentChanges` wins over `changes` when
+/// the server sends both, as the specification asks.
+pub fn operations(edit: WorkspaceEdit) -> anyhow::Result<Vec<EditOp>> {
+    match edit.document_changes {
+        Some(DocumentChanges::Edits(docs)) => docs.into_iter().map(document_edit).collect(),
+        Some(DocumentChanges::Operations(ops)) => ops.into_iter().map(|op| match op {
+            DocumentChangeOperation::Edit(doc) => document_edit(doc),
+            DocumentChangeOperation::Op(op) => resource_op(op),
+        }).collect(),
+        None => {
+            let mut ops = edit.changes.unwrap_or_default().into_iter()
+                .map(|(uri, edits)| Ok(EditOp::Edit { path: uri_to_path(&uri)?, version: None, edits: edits.iter().map(from_lsp).collect() }))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            // the map has no order of its own
+            ops.sort_by(|a, b| match (a, b) {
+                (EditOp::Edit { path: a, .. }, EditOp::Edit { path: b, .. }) => a.cmp(b),
+                _ => std::cmp::Ordering::Equal,
+            });
+            Ok(ops)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde_json::json;
+
+    use super::{operations, EditOp};
+
+    fn parse(value: serde_json::Value) -> Vec<EditOp> {
+        operations(serde_json::from_value(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn changes() {
+        let ops = parse(json!({ "changes": {
+            "file:///b.rs": [{ "range": { "start": { "line": 1, "character": 2 }, "end": { "line": 1, "character": 5 } }, "newText": "bar" }],
+            "file:///a.rs": [],
+        } }));
+        assert_eq!(ops, vec![
+            EditOp::Edit { path: PathBuf::from("/a.rs"), version: None, edits: vec![] },
+            EditOp::Edit { path: PathBuf::from("/b.rs"), version: None, edits: vec![((1, 2), (1, 5), "bar".to_owned())] },
+        ]);
+    }
+
+    #[test]
+    fn document_changes() {
+        let ops = parse(json!({
+            "changes": { "file:///ignored.rs": [] },
+            "documentChanges": [
+                { "kind": "create", "uri": "file:///new.rs", "options": { "ignoreIfExists": true } },
+                { "textDocument": { "uri": "file:///a%20b.rs", "version": 4 }, "edits": [
+                    { "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 0 } }, "newText": "x", "annotationId": "a" },
+                ] },
+                { "kind": "rename", "oldUri": "file:///old.rs", "newUri": "file:///renamed.rs" },
+                { "kind": "delete", "uri": "file:///dir", "options": { "recursive": true } },
+            ],
+        }));
+        assert_eq!(ops, vec![
+            EditOp::Create { path: PathBuf::from("/new.rs"), overwrite: false, ignore_if_exists: true },
+            EditOp::Edit { path: PathBuf::from("/a b.rs"), version: Some(4), edits: vec![((0, 0), (0, 0), "x".to_owned())] },
+            EditOp::Rename { from: PathBuf::from("/old.rs"), to: PathBuf::from("/renamed.rs"), overwrite: false, ignore_if_exists: false },
+            EditOp::Delete { path: PathBuf::from("/dir"), recursive: true, ignore_if_not_exists: false },
+        ]);
+    }
+}
@@ -5,15 +5,28 @@ pub mod viewer;
 pub mod buffer;
 pub mod editor;
 pub mod lsp;
+pub mod cli;
+pub mod language;
+pub mod fuzzy;
 
 use editor::Editor;
 
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() -> anyhow::Result<()> {
+    let args = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("editor: {}\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
     console_subscriber::init();
-    //let mut editor = Editor::new()?;
-    let mut editor = Editor::new_clangd().await?;
+    let mut editor = Editor::from_args(args).await?;
     editor.start().await?;
     Ok(())
 }
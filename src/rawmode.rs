@@ -28,3 +28,14 @@ impl Drop for RawMode {
         tcsetattr(STDIN_FILENO, TCSAFLUSH, &self.orig_term).expect("Failed to drop RawMode")
     }
 }
+
+/// Point stdin back at the controlling terminal, e.g. after the text of a
+/// buffer was read from a pipe.
+pub fn reopen_tty_as_stdin() -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let tty = std::fs::File::open("/dev/tty")?;
+    if unsafe { libc::dup2(tty.as_raw_fd(), STDIN_FILENO) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -57,6 +57,26 @@ impl Terminal {
         self.write(format!("\x1b[48;2;{};{};{}m", bg.r, bg.g, bg.b).as_bytes())
     }
 
+    pub fn set_reverse(&mut self) -> anyhow::Result<()> {
+        self.write(b"\x1b[7m")
+    }
+
+    pub fn set_underline(&mut self) -> anyhow::Result<()> {
+        self.write(b"\x1b[4m")
+    }
+
+    pub fn set_bold(&mut self) -> anyhow::Result<()> {
+        self.write(b"\x1b[1m")
+    }
+
+    pub fn set_italic(&mut self) -> anyhow::Result<()> {
+        self.write(b"\x1b[3m")
+    }
+
+    pub fn reset_style(&mut self) -> anyhow::Result<()> {
+        self.write(b"\x1b[0m")
+    }
+
     pub fn write(&mut self, buf: &[u8]) -> anyhow::Result<()> {
         Ok(self.stdout.write_all(buf)?)
     }
@@ -1,8 +1,13 @@
 pub mod text_viewer;
 pub mod hover_viewer;
 pub mod completion_viewer;
+pub mod prompt_viewer;
+pub mod location_list_viewer;
+pub mod code_action_viewer;
+pub mod signature_help_viewer;
+pub mod markdown;
 
-use crate::{lsp::client::ResponseReceiver, terminal::Terminal};
+use crate::{buffer::history::UndoTarget, lsp::client::ResponseReceiver, terminal::Terminal};
 
 #[derive(Debug, Clone)]
 pub struct ViewerRect {
@@ -25,11 +30,20 @@ pub trait Input {
     fn insert_char(&mut self, _: char) -> impl std::future::Future<Output=anyhow::Result<()>> { async { Ok(()) } }
     fn newline(&mut self) -> impl std::future::Future<Output=anyhow::Result<()>> { async { Ok(()) } }
     fn backspace(&mut self) -> impl std::future::Future<Output=anyhow::Result<()>> { async { Ok(()) } }
+    /// Returns `false` when there was nothing to undo.
+    fn undo(&mut self) -> impl std::future::Future<Output=anyhow::Result<bool>> { async { Ok(false) } }
+    fn redo(&mut self) -> impl std::future::Future<Output=anyhow::Result<bool>> { async { Ok(false) } }
+    fn undo_travel(&mut self, _: UndoTarget) -> impl std::future::Future<Output=anyhow::Result<bool>> { async { Ok(false) } }
     fn hover(&mut self) -> impl std::future::Future<Output = anyhow::Result<()>>;
     fn completion(&mut self) -> impl std::future::Future<Output = anyhow::Result<()>>;
     fn do_completion(&mut self) -> impl std::future::Future<Output = anyhow::Result<()>>;
     fn completion_next(&mut self) -> impl std::future::Future<Output = anyhow::Result<()>>;
     fn completion_prev(&mut self) -> impl std::future::Future<Output = anyhow::Result<()>>;
+    fn close_completion(&mut self) {}
+    /// Ask for or update signature help after `typed` was inserted, or after
+    /// another edit when it is `None`.
+    fn signature_help(&mut self, typed: Option<char>) -> impl std::future::Future<Output = anyhow::Result<()>>;
+    fn close_signature_help(&mut self) {}
 }
 
 pub trait Viewer: Draw + Input {}
@@ -0,0 +1,65 @@
+use lsp_types::CodeActionOrCommand;
+
+use crate::{lsp::method::code_action::title, terminal::Terminal};
+use super::{Draw, ViewerRect};
+
+/// Popup menu of the code actions offered at the cursor.
+pub struct CodeActionViewer {
+    actions: Vec<CodeActionOrCommand>,
+    titles: Vec<String>,
+    select: usize,
+    top: usize,
+}
+
+impl CodeActionViewer {
+    pub fn new(actions: Vec<CodeActionOrCommand>) -> Self {
+        let titles = actions.iter().map(title).collect();
+        Self { actions, titles, select: 0, top: 0 }
+    }
+
+    pub fn selected(&self) -> Option<&CodeActionOrCommand> {
+        self.actions.get(self.select)
+    }
+
+    pub fn select_next(&mut self) {
+        self.select = (self.select + 1) % self.actions.len();
+    }
+
+    pub fn select_prev(&mut self) {
+        self.select = (self.select + self.actions.len() - 1) % self.actions.len();
+    }
+
+    /// The size the menu would like, a border column on each side included.
+    pub fn wanted_size(&self) -> (usize, usize) {
+        let w = self.titles.iter().map(|t| t.chars().count()).max().unwrap_or(0) + 2;
+        (self.titles.len(), w)
+    }
+
+    fn fix_top(&mut self, rows: usize) {
+        if self.top > self.select {
+            self.top = self.select;
+        }
+        if rows > 0 && self.select >= self.top + rows {
+            self.top = self.select + 1 - rows;
+        }
+    }
+}
+
+impl Draw for CodeActionViewer {
+    fn draw_all(&mut self, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
+        self.fix_top(rect.h);
+        for (row, idx) in (self.top..self.titles.len()).take(rect.h).enumerate() {
+            terminal.set_cursor(rect.i + row, rect.j)?;
+            let line = format!("{}{:<width$}", if idx == self.select { ">" } else { " " }, self.titles[idx], width = rect.w.saturating_sub(1));
+            if idx == self.select {
+                terminal.set_reverse()?;
+            }
+            terminal.write(line.chars().take(rect.w).collect::<String>().as_bytes())?;
+            terminal.reset_style()?;
+        }
+        Ok(())
+    }
+    fn draw_cursor(&mut self, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
+        terminal.set_cursor(rect.i + self.select - self.top, rect.j)
+    }
+}
@@ -1,78 +1,216 @@
-use lsp_types::{CompletionResponse, CompletionTextEdit};
+use lsp_types::{CompletionItem, CompletionResponse, CompletionTextEdit, Documentation, InsertTextFormat, MarkupKind, TextEdit};
 use ropey::Rope;
-use crate::{buffer::{CursorPos, text_buffer::TextBuffer, Buffer}, terminal::Terminal};
-use super::{Draw, ViewerRect};
+use crate::{buffer::{CursorPos, text_buffer::TextBuffer, Buffer, edit::{Edit, end_of_insert, transform_all}, snippet::{self, Snippet}}, fuzzy::fuzzy_match, lsp::{method::completion::kind_label, position::{PositionEncoding, Positions}}, terminal::{Color, Terminal}};
+use super::{Draw, ViewerRect, hover_viewer::HoverViewer, markdown::{self, Line, Style}};
 
+const MATCHED: Color = Color { r: 97, g: 175, b: 239 };
+const DIM: Color = Color { r: 150, g: 150, b: 150 };
+/// Widest the list gets, leaving room for the documentation next to it.
+const LIST_WIDTH: usize = 60;
+/// Narrowest the documentation is shown.
+const DOC_MIN_WIDTH: usize = 20;
+const DOC_WIDTH: usize = 60;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Look {
+    Plain,
+    Matched,
+    Dim,
+}
+
+/// Start of the word the cursor is at the end of.
+pub fn word_start(rope: &Rope, cursor: CursorPos) -> CursorPos {
+    let line = rope.line(cursor.0).chars().take(cursor.1).collect::<Vec<_>>();
+    let word = line.iter().rev().take_while(|c| c.is_alphanumeric() || **c == '_').count();
+    (cursor.0, cursor.1 - word)
+}
+
+/// What accepting a completion did to the buffer.
+pub struct Accepted {
+    pub cursor: CursorPos,
+    /// The edits made, computed against the text before them.
+    pub edits: Vec<Edit>,
+    /// The snippet the item expanded to, and where it was inserted.
+    pub snippet: Option<(Snippet, CursorPos)>,
+}
+
+/// The items of the last completion response, filtered and ranked by what
+/// has been typed since it was asked for.
 pub struct CompletionViewer {
-    rope: Rope,
+    items: Vec<CompletionItem>,
+    is_incomplete: bool,
+    /// Where the completion was asked for.
+    origin: CursorPos,
+    /// The text the items' edits were computed for.
+    positions: Positions,
+    /// What the items are filtered by.
+    typed: String,
+    /// The items shown, best first, with the chars of their labels that matched.
+    matches: Vec<(usize, Vec<usize>)>,
+    /// Whether the server has been asked for the details of each item.
+    resolved: Vec<bool>,
+    /// The documentation window of the item it was made for.
+    doc: Option<(usize, Option<HoverViewer>)>,
     x: usize,
     select: usize,
-    resp: CompletionResponse,
-    len: usize,
     pub cursor: CursorPos,
 }
 
+/// The detail and documentation of `item`, as shown next to the list.
+fn documentation(item: &CompletionItem) -> Vec<Line> {
+    let mut lines = item.detail.iter().flat_map(|detail| detail.lines()).map(|line| vec![(Style::Code, line.to_owned())]).collect::<Vec<_>>();
+    let doc = match item.documentation {
+        Some(Documentation::String(ref text)) => markdown::plain(text),
+        Some(Documentation::MarkupContent(ref content)) if content.kind == MarkupKind::Markdown => markdown::parse(&content.value),
+        Some(Documentation::MarkupContent(ref content)) => markdown::plain(&content.value),
+        None => vec![],
+    };
+    if !lines.is_empty() && !doc.is_empty() {
+        lines.push(vec![]);
+    }
+    lines.extend(doc);
+    lines
+}
+
 impl CompletionViewer {
-    pub fn new(mut resp: CompletionResponse, cursor: CursorPos) -> Self {
-        let (text, len) = match resp {
-            CompletionResponse::Array(ref mut arr) => {
-                arr.sort_by_cached_key(|i| i.sort_text.to_owned());
-                (arr.iter().map(|a| a.label.to_owned()).collect::<Vec<_>>().join("\n"), arr.len())
-            }
-            CompletionResponse::List(ref mut list) => {
-                list.items.sort_by_cached_key(|i| i.sort_text.to_owned());
-                (list.items.iter().map(|a| a.label.to_owned()).collect::<Vec<_>>().join("\n"), list.items.len())
-            }
+    pub fn new(resp: CompletionResponse, cursor: CursorPos, positions: Positions) -> Self {
+        let (mut items, is_incomplete) = match resp {
+            CompletionResponse::Array(items) => (items, false),
+            CompletionResponse::List(list) => (list.items, list.is_incomplete),
         };
+        items.sort_by_cached_key(|i| i.sort_text.clone().unwrap_or_else(|| i.label.clone()));
         Self {
-            rope: Rope::from_str(&text),
+            matches: (0..items.len()).map(|idx| (idx, vec![])).collect(),
+            resolved: vec![false; items.len()],
+            doc: None,
+            items,
+            is_incomplete,
+            origin: cursor,
+            positions,
+            typed: String::new(),
             x: 0,
             select: 0,
-            resp,
-            len,
             cursor,
         }
     }
 
-    pub async fn do_completion<B: Buffer>(&self, buffer: &mut B) -> anyhow::Result<CursorPos> {
-        let item = match self.resp {
-            CompletionResponse::Array(ref arr) => {
-                arr.get(self.select)
-            }
-            CompletionResponse::List(ref list) => {
-                list.items.get(self.select)
+    /// The choices of a snippet tab stop at `start`, offered as completions.
+    pub fn choices(choices: &[String], start: CursorPos) -> Self {
+        // counted in chars, as the editor does
+        let positions = Positions::new(Rope::new(), PositionEncoding::Utf32);
+        let position = positions.to_lsp(start);
+        let items = choices.iter().enumerate().map(|(k, choice)| CompletionItem {
+            label: choice.clone(),
+            sort_text: Some(format!("{:08}", k)),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit { range: lsp_types::Range { start: position, end: position }, new_text: choice.clone() })),
+            ..Default::default()
+        }).collect::<Vec<_>>();
+        // there is nothing for the server to add to them
+        let resolved = vec![true; items.len()];
+        Self { resolved, ..Self::new(CompletionResponse::Array(items), start, positions) }
+    }
+
+    /// Whether the server wants to be asked again as the word grows.
+    pub fn is_incomplete(&self) -> bool {
+        self.is_incomplete
+    }
+
+    /// Index of the selected item, if any item matches.
+    pub fn selected(&self) -> Option<usize> {
+        self.matches.get(self.select).map(|&(idx, _)| idx)
+    }
+
+    pub fn item(&self, idx: usize) -> &CompletionItem {
+        &self.items[idx]
+    }
+
+    pub fn is_resolved(&self, idx: usize) -> bool {
+        self.resolved[idx]
+    }
+
+    /// Take in what resolving item `idx` filled in, or only note that it
+    /// was tried when it is `None`.
+    pub fn set_resolved(&mut self, idx: usize, resolved: Option<CompletionItem>) {
+        self.resolved[idx] = true;
+        if let Some(resolved) = resolved {
+            let item = &mut self.items[idx];
+            item.detail = resolved.detail.or(item.detail.take());
+            item.documentation = resolved.documentation.or(item.documentation.take());
+            item.additional_text_edits = resolved.additional_text_edits.or(item.additional_text_edits.take());
+            if self.doc.as_ref().is_some_and(|&(doc, _)| doc == idx) {
+                self.doc = None;
             }
+        }
+    }
+
+    /// Keep the items matching `typed`, the word before `cursor`, best first.
+    pub fn filter(&mut self, typed: &str, cursor: CursorPos) {
+        self.cursor = cursor;
+        if self.typed == typed {
+            return;
+        }
+        let mut scored = self.items.iter().enumerate().filter_map(|(idx, item)| {
+            let score = fuzzy_match(typed, item.filter_text.as_deref().unwrap_or(&item.label))?.score;
+            let positions = fuzzy_match(typed, &item.label).map_or(vec![], |m| m.positions);
+            Some((score, idx, positions))
+        }).collect::<Vec<_>>();
+        scored.sort_by_key(|&(score, idx, _)| (std::cmp::Reverse(score), idx));
+        self.matches = scored.into_iter().map(|(_, idx, positions)| (idx, positions)).collect();
+        self.typed = typed.to_owned();
+        self.x = 0;
+        self.select = 0;
+    }
+
+    /// Accept the selected item, if any.
+    pub fn do_completion<B: Buffer>(&self, buffer: &mut B) -> anyhow::Result<Option<Accepted>> {
+        let Some(item) = self.selected().map(|idx| &self.items[idx]) else {
+            return Ok(None);
         };
         eprintln!("complete = {:?}", item);
-        if let Some(item) = item {
-            let cursor = match item.text_edit.as_ref() {
-                Some(CompletionTextEdit::Edit(edit)) => {
-                    buffer.edit((edit.range.start.line as usize, edit.range.start.character as usize), (edit.range.end.line as usize, edit.range.end.character as usize), &edit.new_text).await?
-                }
-                None => { self.cursor }
-                _ => unimplemented!(),
-            };
-
-            /*
-            if let Some(edits) = item.additional_text_edits.as_ref() {
-                for edit in edits {
-                    buffer.edit((edit.range.start.line as usize, edit.range.start.character as usize), (edit.range.end.line as usize, edit.range.end.character as usize), &edit.new_text).await?;
-                }
+        let mut main = match item.text_edit.as_ref() {
+            Some(CompletionTextEdit::Edit(edit)) => self.positions.edit_from_lsp(edit),
+            // inserting leaves the rest of the word after the cursor alone
+            Some(CompletionTextEdit::InsertAndReplace(edit)) => {
+                self.positions.edit_from_lsp(&TextEdit { range: edit.insert, new_text: edit.new_text.clone() })
             }
-            */
-            Ok(cursor)
+            None => {
+                let text = item.insert_text.clone().unwrap_or_else(|| item.label.clone());
+                (word_start(&buffer.rope_clone(), self.cursor), self.cursor, text)
+            }
+        };
+        // the edit was made for the word as it was when asked for; it
+        // replaces what has been typed since, too
+        if main.1 == self.origin && self.cursor.0 == self.origin.0 && self.cursor.1 > self.origin.1 {
+            main.1 = self.cursor;
         }
-        else {
-            Ok(self.cursor)
+        let snippet = (item.insert_text_format == Some(InsertTextFormat::SNIPPET)).then(|| {
+            let rope = buffer.rope_clone();
+            snippet::expand(&main.2, &|name| snippet::variable(name, buffer.filename(), &rope, main.0))
+        });
+        if let Some(ref snippet) = snippet {
+            main.2 = snippet.text.clone();
         }
+        let additional = item.additional_text_edits.iter().flatten().map(|edit| self.positions.edit_from_lsp(edit)).collect::<Vec<_>>();
+        // the cursor goes after the completed text, wherever the
+        // additional edits (e.g. an import) move it
+        let start = transform_all(main.0, &additional);
+        let cursor = end_of_insert(start, &main.2);
+        let mut edits = additional;
+        edits.push(main);
+        buffer.apply_edits(&edits, self.cursor)?;
+        Ok(Some(Accepted { cursor, edits, snippet: snippet.map(|snippet| (snippet, start)) }))
     }
 
     pub fn select_next(&mut self) {
-        self.select = (self.select + 1) % self.len;
+        if !self.matches.is_empty() {
+            self.select = (self.select + 1) % self.matches.len();
+        }
     }
 
     pub fn select_prev(&mut self) {
-        self.select = (self.select + self.len - 1) % self.len;
+        if !self.matches.is_empty() {
+            self.select = (self.select + self.matches.len() - 1) % self.matches.len();
+        }
     }
 
     fn fix_top(&mut self, rect: &ViewerRect) {
@@ -85,26 +223,86 @@ impl CompletionViewer {
     }
 }
 
+impl CompletionViewer {
+    /// The row of the `k`th match: kind, label with the matched characters
+    /// marked, label details and detail.
+    fn row(&self, k: usize) -> Vec<(Look, char)> {
+        let (idx, ref positions) = self.matches[k];
+        let item = &self.items[idx];
+        let kind_width = self.matches.iter().map(|&(idx, _)| self.items[idx].kind.map_or(0, |kind| kind_label(kind).len())).max().unwrap_or(0);
+        let mut row = vec![(Look::Plain, if k == self.select { '>' } else { ' ' })];
+        if kind_width > 0 {
+            let kind = format!("{:<width$} ", item.kind.map_or("", kind_label), width = kind_width);
+            row.extend(kind.chars().map(|c| (Look::Dim, c)));
+        }
+        row.extend(item.label.chars().enumerate().map(|(j, c)| (if positions.contains(&j) { Look::Matched } else { Look::Plain }, c)));
+        let details = item.label_details.as_ref();
+        row.extend(details.and_then(|details| details.detail.as_deref()).unwrap_or("").chars().map(|c| (Look::Plain, c)));
+        let description = details.and_then(|details| details.description.as_deref()).or(item.detail.as_deref());
+        if let Some(description) = description.and_then(|text| text.lines().next()) {
+            row.extend("  ".chars().chain(description.chars()).map(|c| (Look::Dim, c)));
+        }
+        row
+    }
+
+    fn set_look(look: Look, terminal: &mut Terminal) -> anyhow::Result<()> {
+        match look {
+            Look::Plain => Ok(()),
+            Look::Matched => {
+                terminal.set_bold()?;
+                terminal.set_fg(MATCHED)
+            }
+            Look::Dim => terminal.set_fg(DIM),
+        }
+    }
+
+    /// Draw the documentation of the selected item at the right of the list, if there is room.
+    fn draw_doc(&mut self, rect: &ViewerRect, list_w: usize, terminal: &mut Terminal) -> anyhow::Result<()> {
+        let Some(idx) = self.selected() else {
+            return Ok(());
+        };
+        let room = rect.w - list_w;
+        if room < DOC_MIN_WIDTH {
+            return Ok(());
+        }
+        if self.doc.as_ref().is_none_or(|&(doc, _)| doc != idx) {
+            self.doc = Some((idx, HoverViewer::from_lines(documentation(&self.items[idx]), self.origin)));
+        }
+        let Some((_, Some(ref mut doc))) = self.doc else {
+            return Ok(());
+        };
+        let (h, w) = doc.wanted_size(room.min(DOC_WIDTH));
+        doc.draw_all(&ViewerRect { h: h.min(rect.h), w, i: rect.i, j: rect.j + list_w }, terminal)
+    }
+}
+
 impl Draw for CompletionViewer {
     fn draw_all(&mut self, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
+        if rect.h == 0 || rect.w == 0 || self.matches.is_empty() {
+            return Ok(());
+        }
         self.fix_top(rect);
-        for i in 0..rect.h {
-            if let Some(slice) = self.rope.get_line(self.x + i) {
-                let len = slice.len_chars();
-                terminal.set_cursor(rect.i + i, rect.j)?;
-                if 0 < len {
-                    terminal.write(format!("{}{}",
-                        if self.select == self.x + i { ">" } else { " " },
-                        slice.slice(0..(len - 1).min(0 + rect.w))).as_bytes()
-                    )?;
+        let rows = (self.x..self.matches.len().min(self.x + rect.h)).map(|k| self.row(k)).collect::<Vec<_>>();
+        let list_w = rows.iter().map(|row| row.len() + 1).max().unwrap_or(0).min(LIST_WIDTH).min(rect.w);
+        for (i, mut row) in rows.into_iter().enumerate() {
+            // padded, so that the text below does not show through
+            row.resize(list_w, (Look::Plain, ' '));
+            terminal.set_cursor(rect.i + i, rect.j)?;
+            let mut j = 0;
+            while j < row.len() {
+                let look = row[j].0;
+                let end = (j..row.len()).find(|&k| row[k].0 != look).unwrap_or(row.len());
+                Self::set_look(look, terminal)?;
+                terminal.write(row[j..end].iter().map(|&(_, c)| c).collect::<String>().as_bytes())?;
+                if look != Look::Plain {
+                    terminal.reset_style()?;
                 }
+                j = end;
             }
         }
-        Ok(())
+        self.draw_doc(rect, list_w, terminal)
     }
-    fn draw_cursor(&mut self, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
+    fn draw_cursor(&mut self, _rect: &ViewerRect, _terminal: &mut Terminal) -> anyhow::Result<()> {
         Ok(())
     }
 }
-
-
@@ -1,41 +1,112 @@
-use ropey::Rope;
+use crate::{buffer::CursorPos, lsp::method::hover::HoverResult, terminal::{Color, Terminal}};
 
-use crate::terminal::Terminal;
-
-use super::{Draw, ViewerRect};
+use super::{Draw, ViewerRect, markdown::{self, Line, Style}};
 
+const BACKGROUND: Color = Color { r: 40, g: 44, b: 52 };
+const CODE: Color = Color { r: 152, g: 195, b: 121 };
+const HEADING: Color = Color { r: 97, g: 175, b: 239 };
 
+/// Floating window with the hover text of the symbol at `pos`, wrapped to
+/// its width and scrolled when it does not fit.
 pub struct HoverViewer {
-    s: String,
+    lines: Vec<Line>,
+    /// `lines` wrapped to the width they were last laid out for.
+    wrapped: Vec<Line>,
+    wrapped_width: usize,
+    top: usize,
+    /// Rows of text shown when last drawn, to keep scrolling inside the text.
+    rows: usize,
+    pub pos: CursorPos,
 }
 
 impl HoverViewer {
-    pub fn new(s: String) -> Self {
-        Self { s }
+    /// `None` when the server has nothing to say.
+    pub fn new(hover: &HoverResult) -> Option<Self> {
+        let lines = if hover.markdown { markdown::parse(&hover.text) } else { markdown::plain(&hover.text) };
+        Self::from_lines(lines, hover.pos)
+    }
+
+    /// A window with `lines` already parsed, `None` when there are none.
+    pub fn from_lines(lines: Vec<Line>, pos: CursorPos) -> Option<Self> {
+        if lines.is_empty() {
+            return None;
+        }
+        Some(Self { lines, wrapped: vec![], wrapped_width: 0, top: 0, rows: 0, pos })
     }
-    pub fn empty() -> Self {
-        Self { s: String::new() }
+
+    fn layout(&mut self, width: usize) {
+        if self.wrapped_width != width {
+            self.wrapped = self.lines.iter().flat_map(|line| markdown::wrap(line, width)).collect();
+            self.wrapped_width = width;
+            self.top = self.top.min(self.wrapped.len().saturating_sub(1));
+        }
+    }
+
+    /// The size the window would like when it may be `max_w` wide, a
+    /// border column on each side included.
+    pub fn wanted_size(&mut self, max_w: usize) -> (usize, usize) {
+        let w = self.lines.iter().map(markdown::width).max().unwrap_or(0).min(max_w.saturating_sub(2)).max(1);
+        self.layout(w);
+        (self.wrapped.len(), w + 2)
+    }
+
+    pub fn scroll(&mut self, delta: isize) {
+        let max = self.wrapped.len().saturating_sub(self.rows);
+        self.top = self.top.saturating_add_signed(delta).min(max);
+    }
+
+    fn set_style(style: Style, terminal: &mut Terminal) -> anyhow::Result<()> {
+        match style {
+            Style::Plain | Style::Rule => Ok(()),
+            Style::Strong => terminal.set_bold(),
+            Style::Emphasis => terminal.set_italic(),
+            Style::Code => terminal.set_fg(CODE),
+            Style::Heading => {
+                terminal.set_bold()?;
+                terminal.set_fg(HEADING)
+            }
+            Style::Link => terminal.set_underline(),
+        }
     }
 }
 
 impl Draw for HoverViewer {
     fn draw_all(&mut self, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
-        if self.s.is_empty() {
+        if rect.h == 0 || rect.w < 3 {
             return Ok(());
         }
-        let rope = Rope::from_str(&self.s);
-        for i in 0..rect.h {
-            if let Some(slice) = rope.get_line(i) {
-                let len = slice.len_chars();
-                terminal.set_cursor(rect.i + i, rect.j)?;
-                if 0 < len {
-                    terminal.write(format!("{}", slice.slice(0..(len - 1).min(0 + rect.w))).as_bytes())?;
-                }
+        self.layout(rect.w - 2);
+        // the last row tells how far down the text is when it does not fit
+        let overflow = self.wrapped.len() > rect.h && rect.h > 1;
+        self.rows = if overflow { rect.h - 1 } else { rect.h };
+        self.scroll(0);
+        for row in 0..self.rows {
+            terminal.set_cursor(rect.i + row, rect.j)?;
+            terminal.set_bg(BACKGROUND)?;
+            terminal.write(b" ")?;
+            let line = self.wrapped.get(self.top + row).map(|line| line.as_slice()).unwrap_or(&[]);
+            for (style, text) in line {
+                Self::set_style(*style, terminal)?;
+                terminal.write(text.as_bytes())?;
+                terminal.reset_style()?;
+                terminal.set_bg(BACKGROUND)?;
             }
+            let used = line.iter().map(|(_, text)| text.chars().count()).sum::<usize>();
+            terminal.write(" ".repeat(rect.w - 1 - used).as_bytes())?;
+            terminal.reset_style()?;
+        }
+        if overflow {
+            let status = format!(" {}-{}/{} ", self.top + 1, self.top + self.rows, self.wrapped.len());
+            let status = format!("{:>width$}", status, width = rect.w).chars().take(rect.w).collect::<String>();
+            terminal.set_cursor(rect.i + self.rows, rect.j)?;
+            terminal.set_bg(BACKGROUND)?;
+            terminal.set_reverse()?;
+            terminal.write(status.as_bytes())?;
+            terminal.reset_style()?;
         }
         Ok(())
     }
-    fn draw_cursor(&mut self, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
+    fn draw_cursor(&mut self, _rect: &ViewerRect, _terminal: &mut Terminal) -> anyhow::Result<()> {
         Ok(())
     }
 }
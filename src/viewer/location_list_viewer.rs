@@ -0,0 +1,100 @@
+use crate::{lsp::method::goto::Location, terminal::Terminal};
+use super::{Draw, ViewerRect};
+
+/// One row of the list: where to jump and what to show after `path:line:col`,
+/// usually the line found there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListItem {
+    pub location: Location,
+    pub text: String,
+}
+
+/// A titled list of locations drawn as a panel, one `path:line:col: text` per row.
+/// Used for references, definitions with several targets, symbols and grep.
+pub struct LocationListViewer {
+    title: String,
+    items: Vec<ListItem>,
+    labels: Vec<String>,
+    select: usize,
+    top: usize,
+}
+
+/// `path` relative to the working directory when it is below it.
+fn display_path(path: &std::path::Path) -> String {
+    let relative = std::env::current_dir().ok().and_then(|cwd| path.strip_prefix(cwd).ok().map(|p| p.to_path_buf()));
+    relative.as_deref().unwrap_or(path).display().to_string()
+}
+
+impl LocationListViewer {
+    pub fn new(title: String, items: Vec<ListItem>) -> Self {
+        let labels = items.iter()
+            .map(|item| {
+                let loc = &item.location;
+                format!("{}:{}:{}: {}", display_path(&loc.path), loc.pos.0 + 1, loc.pos.1 + 1, item.text.trim().replace('\t', " "))
+            })
+            .collect();
+        Self { title, items, labels, select: 0, top: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn selected(&self) -> Option<&Location> {
+        self.items.get(self.select).map(|item| &item.location)
+    }
+
+    pub fn select_next(&mut self) {
+        if self.select + 1 < self.items.len() {
+            self.select += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.select = self.select.saturating_sub(1);
+    }
+
+    /// Rows needed to show every entry below the title.
+    pub fn wanted_height(&self) -> usize {
+        self.items.len() + 1
+    }
+
+    fn fix_top(&mut self, rows: usize) {
+        if self.top > self.select {
+            self.top = self.select;
+        }
+        if rows > 0 && self.select >= self.top + rows {
+            self.top = self.select + 1 - rows;
+        }
+    }
+}
+
+impl Draw for LocationListViewer {
+    fn draw_all(&mut self, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
+        let rows = rect.h.saturating_sub(1);
+        self.fix_top(rows);
+        let title = format!(" {} ({}/{})", self.title, self.select + 1, self.items.len());
+        terminal.set_cursor(rect.i, rect.j)?;
+        terminal.set_reverse()?;
+        terminal.write(format!("{:<width$}", title, width = rect.w).chars().take(rect.w).collect::<String>().as_bytes())?;
+        terminal.reset_style()?;
+        for (row, idx) in (self.top..self.labels.len()).take(rows).enumerate() {
+            terminal.set_cursor(rect.i + 1 + row, rect.j)?;
+            terminal.clear_cursor_line()?;
+            let line = format!("{} {}", if idx == self.select { ">" } else { " " }, self.labels[idx]);
+            if idx == self.select {
+                terminal.set_reverse()?;
+            }
+            terminal.write(line.chars().take(rect.w).collect::<String>().as_bytes())?;
+            terminal.reset_style()?;
+        }
+        Ok(())
+    }
+    fn draw_cursor(&mut self, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
+        terminal.set_cursor(rect.i + 1 + self.select - self.top, rect.j)
+    }
+}
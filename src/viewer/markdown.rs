@@ -0,0 +1,319 @@
+//! Just enough markdown for what language servers send as documentation:
+//! fenced code, headings, lists, rules, emphasis, inline code and links.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Plain,
+    Strong,
+    Emphasis,
+    Code,
+    Heading,
+    Link,
+    /// A horizontal rule, drawn as wide as the text.
+    Rule,
+}
+
+pub type Span = (Style, String);
+pub type Line = Vec<Span>;
+
+fn push(line: &mut Line, style: Style, c: char) {
+    match line.last_mut() {
+        Some((last, text)) if *last == style => text.push(c),
+        _ => line.push((style, c.to_string())),
+    }
+}
+
+fn append(line: &mut Line, spans: Line) {
+    for (style, text) in spans {
+        text.chars().for_each(|c| push(line, style, c));
+    }
+}
+
+fn heading(line: &str) -> Option<&str> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    match line[level..].strip_prefix(' ') {
+        Some(text) if (1..=6).contains(&level) => Some(text.trim_end_matches(['#', ' '])),
+        _ => None,
+    }
+}
+
+fn is_rule(line: &str) -> bool {
+    let marks = line.chars().filter(|&c| c != ' ').collect::<Vec<_>>();
+    marks.len() >= 3 && ['-', '*', '_'].iter().any(|&m| marks.iter().all(|&c| c == m))
+}
+
+/// The text of a list item and the marker it is shown with.
+fn list_item(line: &str) -> Option<(String, &str)> {
+    if let Some(text) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")).or_else(|| line.strip_prefix("+ ")) {
+        return Some(("• ".to_owned(), text));
+    }
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    let text = line[digits..].strip_prefix(". ").or_else(|| line[digits..].strip_prefix(") "))?;
+    (digits > 0).then(|| (format!("{}. ", &line[..digits]), text))
+}
+
+/// Whether the `*` or `_` run of `n` at `i` can close emphasis.
+fn closes(chars: &[char], i: usize, n: usize) -> bool {
+    let before = chars[..i].last().is_some_and(|c| !c.is_whitespace());
+    let after = chars.get(i + n).copied();
+    before && (chars[i] == '*' || !after.is_some_and(|c| c.is_alphanumeric()))
+}
+
+/// Whether the `*` or `_` run of `n` at `i` opens emphasis that is closed
+/// later on the line. `_` inside a word, as in `snake_case`, does not.
+fn opens(chars: &[char], i: usize, n: usize) -> bool {
+    let after = chars.get(i + n).is_some_and(|c| !c.is_whitespace());
+    let before = chars[..i].last().copied();
+    if !after || (chars[i] == '_' && before.is_some_and(|c| c.is_alphanumeric())) {
+        return false;
+    }
+    (i + n + 1..chars.len()).any(|k| run(chars, k) == n && chars[k] == chars[i] && closes(chars, k, n))
+}
+
+/// Length of the run of `chars[i]` starting at `i`.
+fn run(chars: &[char], i: usize) -> usize {
+    chars[i..].iter().take_while(|&&c| c == chars[i]).count()
+}
+
+fn inline(text: &str) -> Line {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut line = vec![];
+    let (mut strong, mut emphasis) = (false, false);
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let style = if strong { Style::Strong } else if emphasis { Style::Emphasis } else { Style::Plain };
+        if c == '\\' && chars.get(i + 1).is_some_and(|c| c.is_ascii_punctuation()) {
+            push(&mut line, style, chars[i + 1]);
+            i += 2;
+        }
+        else if c == '`' {
+            let n = run(&chars, i);
+            let close = (i + n..chars.len()).find(|&k| chars[k] == '`' && run(&chars, k) == n && chars[k - 1] != '`');
+            match close {
+                Some(end) => {
+                    let code = chars[i + n..end].iter().collect::<String>();
+                    let code = match code.strip_prefix(' ').and_then(|c| c.strip_suffix(' ')) {
+                        Some(inner) if !inner.trim().is_empty() => inner.to_owned(),
+                        _ => code,
+                    };
+                    code.chars().for_each(|c| push(&mut line, Style::Code, c));
+                    i = end + n;
+                }
+                None => {
+                    (0..n).for_each(|_| push(&mut line, style, '`'));
+                    i += n;
+                }
+            }
+        }
+        else if c == '*' || c == '_' {
+            let n = run(&chars, i).min(2);
+            let on = if n == 2 { strong } else { emphasis };
+            if (on && closes(&chars, i, n)) || (!on && opens(&chars, i, n)) {
+                if n == 2 { strong = !on } else { emphasis = !on }
+            }
+            else {
+                (0..n).for_each(|_| push(&mut line, style, c));
+            }
+            i += n;
+        }
+        else if c == '[' {
+            let link = chars[i..].iter().position(|&c| c == ']')
+                .filter(|&len| chars.get(i + len + 1) == Some(&'('))
+                .and_then(|len| Some((len, chars[i + len..].iter().position(|&c| c == ')')?)));
+            match link {
+                Some((len, to_paren)) => {
+                    chars[i + 1..i + len].iter().for_each(|&c| push(&mut line, Style::Link, c));
+                    i += len + to_paren + 1;
+                }
+                None => {
+                    push(&mut line, style, c);
+                    i += 1;
+                }
+            }
+        }
+        else {
+            push(&mut line, style, c);
+            i += 1;
+        }
+    }
+    line
+}
+
+/// Split `text` into styled lines. Lines of a paragraph are joined, to be
+/// wrapped again to the width of the window; runs of blank lines become one.
+pub fn parse(text: &str) -> Vec<Line> {
+    let mut lines: Vec<Line> = vec![];
+    let mut fence: Option<&str> = None;
+    // whether the next line of text continues the last line
+    let mut paragraph = false;
+    for raw in text.lines() {
+        let trimmed = raw.trim_start();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+            else {
+                lines.push(vec![(Style::Code, raw.trim_end().to_owned())]);
+            }
+            continue;
+        }
+        let hard_break = raw.ends_with("  ") || raw.ends_with('\\');
+        let indent = &raw[..raw.len() - trimmed.len()];
+        let trimmed = trimmed.trim_end().trim_end_matches('\\');
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fence = Some(&trimmed[..3]);
+            paragraph = false;
+        }
+        else if trimmed.is_empty() {
+            if lines.last().is_some_and(|line| !line.is_empty()) {
+                lines.push(vec![]);
+            }
+            paragraph = false;
+        }
+        else if let Some(heading) = heading(trimmed) {
+            lines.push(vec![(Style::Heading, heading.to_owned())]);
+            paragraph = false;
+        }
+        else if is_rule(trimmed) {
+            lines.push(vec![(Style::Rule, String::new())]);
+            paragraph = false;
+        }
+        else if let Some((marker, item)) = list_item(trimmed) {
+            let mut line = vec![(Style::Plain, format!("{}{}", indent, marker))];
+            append(&mut line, inline(item));
+            lines.push(line);
+            paragraph = !hard_break;
+        }
+        else {
+            let spans = inline(trimmed);
+            match lines.last_mut() {
+                Some(last) if paragraph => {
+                    push(last, Style::Plain, ' ');
+                    append(last, spans);
+                }
+                _ => lines.push(spans),
+            }
+            paragraph = !hard_break;
+        }
+    }
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+/// Lines of plain text, kept as they are.
+pub fn plain(text: &str) -> Vec<Line> {
+    text.trim_end().lines().map(|line| vec![(Style::Plain, line.to_owned())]).collect()
+}
+
+/// The width of `line` in columns.
+pub fn width(line: &Line) -> usize {
+    line.iter().map(|(_, text)| text.chars().count()).sum()
+}
+
+fn collect(chars: &[(Style, char)], indent: usize) -> Line {
+    let mut line = vec![];
+    if indent > 0 {
+        line.push((Style::Plain, " ".repeat(indent)));
+    }
+    chars.iter().for_each(|&(style, c)| push(&mut line, style, c));
+    line
+}
+
+/// Break `line` into lines at most `width` columns wide, at spaces where
+/// possible. The lines after the first of a list item are indented under
+/// its text.
+pub fn wrap(line: &Line, width: usize) -> Vec<Line> {
+    if width == 0 {
+        return vec![];
+    }
+    if line.first().is_some_and(|(style, _)| *style == Style::Rule) {
+        return vec![vec![(Style::Rule, "─".repeat(width))]];
+    }
+    let chars = line.iter().flat_map(|(style, text)| text.chars().map(|c| (*style, c))).collect::<Vec<_>>();
+    let leading = chars.iter().take_while(|(_, c)| *c == ' ').count();
+    let marker = chars[leading..].iter().position(|&(_, c)| c == ' ').filter(|&k| {
+        let marker = chars[leading..leading + k].iter().map(|(_, c)| c).collect::<String>();
+        marker == "•" || (marker.ends_with('.') && marker[..marker.len() - 1].chars().all(|c| c.is_ascii_digit()))
+    });
+    let indent = marker.map_or(0, |k| leading + k + 1).min(width / 2);
+    let mut lines = vec![];
+    let mut start = 0;
+    loop {
+        let (indent, avail) = if lines.is_empty() { (0, width) } else { (indent, width - indent) };
+        if chars.len() - start <= avail {
+            lines.push(collect(&chars[start..], indent));
+            return lines;
+        }
+        let end = start + avail;
+        match (start + 1..=end).rev().find(|&k| chars[k].1 == ' ') {
+            Some(k) => {
+                lines.push(collect(&chars[start..k], indent));
+                start = k + 1;
+            }
+            None => {
+                lines.push(collect(&chars[start..end], indent));
+                start = end;
+            }
+        }
+        if start == chars.len() {
+            return lines;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, wrap, Line, Style};
+
+    fn line(spans: &[(Style, &str)]) -> Line {
+        spans.iter().map(|&(style, text)| (style, text.to_owned())).collect()
+    }
+
+    #[test]
+    fn blocks() {
+        let lines = parse("# Title #\n\n\n```cpp\nint f(int *p);\n\n```\ntext that\ncontinues\n- item `a`\n2. second\n---\n\n");
+        assert_eq!(lines, vec![
+            line(&[(Style::Heading, "Title")]),
+            vec![],
+            line(&[(Style::Code, "int f(int *p);")]),
+            line(&[(Style::Code, "")]),
+            line(&[(Style::Plain, "text that continues")]),
+            line(&[(Style::Plain, "• item "), (Style::Code, "a")]),
+            line(&[(Style::Plain, "2. second")]),
+            line(&[(Style::Rule, "")]),
+        ]);
+    }
+
+    #[test]
+    fn inline_styles() {
+        let lines = parse("**bold** and *em* or _em_, snake_case_name, a * b, \\*not\\*, [link](http://x), ``a`b``");
+        assert_eq!(lines, vec![line(&[
+            (Style::Strong, "bold"),
+            (Style::Plain, " and "),
+            (Style::Emphasis, "em"),
+            (Style::Plain, " or "),
+            (Style::Emphasis, "em"),
+            (Style::Plain, ", snake_case_name, a * b, *not*, "),
+            (Style::Link, "link"),
+            (Style::Plain, ", "),
+            (Style::Code, "a`b"),
+        ])]);
+        // unclosed markers stay as they are
+        assert_eq!(parse("int *p"), vec![line(&[(Style::Plain, "int *p")])]);
+    }
+
+    #[test]
+    fn wrapping() {
+        let item = line(&[(Style::Plain, "• one "), (Style::Code, "two"), (Style::Plain, " three")]);
+        assert_eq!(wrap(&item, 9), vec![
+            line(&[(Style::Plain, "• one "), (Style::Code, "two")]),
+            line(&[(Style::Plain, "  three")]),
+        ]);
+        let word = line(&[(Style::Plain, "abcdefgh")]);
+        assert_eq!(wrap(&word, 3), vec![line(&[(Style::Plain, "abc")]), line(&[(Style::Plain, "def")]), line(&[(Style::Plain, "gh")])]);
+        assert_eq!(wrap(&line(&[(Style::Rule, "")]), 3), vec![line(&[(Style::Rule, "───")])]);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::terminal::Terminal;
+
+use super::{Draw, ViewerRect};
+
+/// One-line input shown on the bottom row, e.g. the `:` command line.
+#[derive(Default)]
+pub struct PromptViewer {
+    prefix: String,
+    text: Vec<char>,
+    cursor: usize,
+    left: usize,
+    history: Vec<String>,
+    history_idx: Option<usize>,
+    stash: Vec<char>,
+}
+
+impl PromptViewer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a new input. The history is kept across inputs.
+    pub fn open(&mut self, prefix: &str, init: &str) {
+        self.prefix = prefix.to_owned();
+        self.text = init.chars().collect();
+        self.cursor = self.text.len();
+        self.left = 0;
+        self.history_idx = None;
+    }
+
+    pub fn text(&self) -> String {
+        self.text.iter().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Finish the input and record it in the history.
+    pub fn take(&mut self) -> String {
+        let text = self.text();
+        if !text.is_empty() && self.history.last() != Some(&text) {
+            self.history.push(text.clone());
+        }
+        self.text.clear();
+        self.cursor = 0;
+        self.history_idx = None;
+        text
+    }
+
+    /// Abandon the input without recording it.
+    pub fn cancel(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+        self.history_idx = None;
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.text.remove(self.cursor);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.cursor < self.text.len() {
+            self.text.remove(self.cursor);
+        }
+    }
+
+    pub fn delete_word(&mut self) {
+        let end = self.cursor;
+        while self.cursor > 0 && self.text[self.cursor - 1].is_whitespace() {
+            self.cursor -= 1;
+        }
+        while self.cursor > 0 && !self.text[self.cursor - 1].is_whitespace() {
+            self.cursor -= 1;
+        }
+        self.text.drain(self.cursor..end);
+    }
+
+    pub fn delete_to_start(&mut self) {
+        self.text.drain(..self.cursor);
+        self.cursor = 0;
+    }
+
+    pub fn move_left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn move_right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.text.len());
+    }
+
+    pub fn move_home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn move_end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    pub fn history_prev(&mut self) {
+        let idx = match self.history_idx {
+            None if self.history.is_empty() => return,
+            None => {
+                self.stash = self.text.clone();
+                self.history.len() - 1
+            }
+            Some(0) => return,
+            Some(i) => i - 1,
+        };
+        self.history_idx = Some(idx);
+        self.text = self.history[idx].chars().collect();
+        self.cursor = self.text.len();
+    }
+
+    pub fn history_next(&mut self) {
+        match self.history_idx {
+            None => {}
+            Some(i) if i + 1 < self.history.len() => {
+                self.history_idx = Some(i + 1);
+                self.text = self.history[i + 1].chars().collect();
+                self.cursor = self.text.len();
+            }
+            Some(_) => {
+                self.history_idx = None;
+                self.text = std::mem::take(&mut self.stash);
+                self.cursor = self.text.len();
+            }
+        }
+    }
+
+    fn fix_left(&mut self, rect: &ViewerRect) {
+        let w = rect.w.saturating_sub(self.prefix.chars().count() + 1).max(1);
+        if self.left > self.cursor {
+            self.left = self.cursor;
+        }
+        if self.cursor >= self.left + w {
+            self.left = self.cursor - w + 1;
+        }
+    }
+}
+
+impl Draw for PromptViewer {
+    fn draw_all(&mut self, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
+        self.fix_left(rect);
+        terminal.set_cursor(rect.i, rect.j)?;
+        let line = self.prefix.chars()
+            .chain(self.text.iter().skip(self.left).copied())
+            .take(rect.w)
+            .collect::<String>();
+        terminal.write(line.as_bytes())
+    }
+    fn draw_cursor(&mut self, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
+        self.fix_left(rect);
+        terminal.set_cursor(rect.i, rect.j + self.prefix.chars().count() + self.cursor - self.left)
+    }
+}
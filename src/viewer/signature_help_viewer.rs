@@ -0,0 +1,78 @@
+use lsp_types::{Documentation, SignatureHelp};
+
+use crate::{lsp::method::signature_help::active_parameter_range, terminal::Terminal};
+use super::{Draw, ViewerRect};
+
+/// Popup with the signature of the call the cursor is in, its active
+/// parameter underlined.
+pub struct SignatureHelpViewer {
+    help: SignatureHelp,
+    label: String,
+    param: Option<(usize, usize)>,
+    /// First line of the active parameter's documentation.
+    doc: Option<String>,
+}
+
+impl SignatureHelpViewer {
+    /// `help` has at least one signature.
+    pub fn new(help: SignatureHelp) -> Self {
+        let active = (help.active_signature.unwrap_or(0) as usize).min(help.signatures.len() - 1);
+        let signature = &help.signatures[active];
+        let mut label = signature.label.clone();
+        if help.signatures.len() > 1 {
+            label.push_str(&format!("  ({}/{})", active + 1, help.signatures.len()));
+        }
+        let param = active_parameter_range(&help, signature);
+        let doc = signature.active_parameter.or(help.active_parameter)
+            .and_then(|idx| signature.parameters.as_ref()?.get(idx as usize)?.documentation.as_ref())
+            .map(|doc| match doc {
+                Documentation::String(s) => s.clone(),
+                Documentation::MarkupContent(content) => content.value.clone(),
+            })
+            .and_then(|doc| doc.lines().map(str::trim).find(|line| !line.is_empty()).map(str::to_owned));
+        Self { help, label, param, doc }
+    }
+
+    /// What the server sent, handed back to it when retriggering.
+    pub fn help(&self) -> &SignatureHelp {
+        &self.help
+    }
+
+    /// The size the popup would like, a border column on each side included.
+    pub fn wanted_size(&self) -> (usize, usize) {
+        let w = self.label.chars().count().max(self.doc.as_ref().map_or(0, |doc| doc.chars().count())) + 2;
+        (if self.doc.is_some() { 2 } else { 1 }, w)
+    }
+}
+
+impl Draw for SignatureHelpViewer {
+    fn draw_all(&mut self, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
+        if rect.h == 0 || rect.w == 0 {
+            return Ok(());
+        }
+        let label = format!(" {:<width$}", self.label, width = rect.w.saturating_sub(1)).chars().take(rect.w).collect::<Vec<_>>();
+        let text = |from: usize, to: usize| label[from..to].iter().collect::<String>();
+        let clamp = |k: usize| k.min(label.len());
+        let (start, end) = self.param.map_or((0, 0), |(start, end)| (clamp(start + 1), clamp(end + 1)));
+        terminal.set_cursor(rect.i, rect.j)?;
+        terminal.set_reverse()?;
+        terminal.write(text(0, start).as_bytes())?;
+        terminal.set_underline()?;
+        terminal.write(text(start, end).as_bytes())?;
+        terminal.reset_style()?;
+        terminal.set_reverse()?;
+        terminal.write(text(end, label.len()).as_bytes())?;
+        terminal.reset_style()?;
+        if let (Some(doc), true) = (self.doc.as_ref(), rect.h > 1) {
+            let line = format!(" {:<width$}", doc, width = rect.w.saturating_sub(1)).chars().take(rect.w).collect::<String>();
+            terminal.set_cursor(rect.i + 1, rect.j)?;
+            terminal.set_reverse()?;
+            terminal.write(line.as_bytes())?;
+            terminal.reset_style()?;
+        }
+        Ok(())
+    }
+    fn draw_cursor(&mut self, _rect: &ViewerRect, _terminal: &mut Terminal) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
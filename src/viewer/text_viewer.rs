@@ -1,17 +1,32 @@
-use std::{cell::RefCell, fs::File, io::BufReader, rc::Rc};
+use std::{cell::RefCell, rc::Rc, time::Duration};
 
-use lsp_types::{CompletionResponse, CompletionTextEdit};
+use anyhow::{anyhow, Context};
+use lsp_types::DiagnosticSeverity;
+use ropey::RopeSlice;
 
-use crate::{buffer::Buffer, lsp::{client::ResponseReceiver, method::{completion::CompletionFetch, hover::HoverFetch}}, terminal::Terminal};
-use super::{Draw, Input, Viewer, ViewerRect, hover_viewer::HoverViewer};
+use crate::{buffer::{Buffer, CursorPos, diagnostics, edit::{apply_order, Edit}, history::UndoTarget, snippet::{Snippet, SnippetSession}}, lsp::method::{completion::{CompletionFetch, CompletionResolveFetch}, hover::HoverFetch, signature_help::SignatureHelpFetch}, terminal::{Color, Terminal}};
+use super::{Draw, Input, Viewer, ViewerRect, completion_viewer::{word_start, Accepted, CompletionViewer}, hover_viewer::HoverViewer, signature_help_viewer::SignatureHelpViewer};
 
 pub struct TextViewer<B: Buffer> {
     buffer: Rc<RefCell<B>>,
     top: usize,
     left: usize,
     cursor: (usize, usize),
-    hover: HoverFetch,
+    /// The other end of the visual selection.
+    anchor: Option<CursorPos>,
+    hover: Option<HoverFetch>,
+    /// Closed when the cursor leaves the position it was asked for.
+    hover_popup: Option<HoverViewer>,
     completion: CompletionFetch,
+    /// Start of the word `completion` was asked for, filtered locally while it grows.
+    completion_start: Option<CursorPos>,
+    /// Details asked for the completion item with the index.
+    completion_resolve: Option<(usize, CompletionResolveFetch)>,
+    /// The snippet being filled in, its current tab stop highlighted.
+    snippet: Option<SnippetSession>,
+    /// Shown above the cursor while typing the arguments of a call.
+    signature: Option<SignatureHelpViewer>,
+    signature_fetch: Option<SignatureHelpFetch>,
 }
 
 impl<B: Buffer> TextViewer<B> {
@@ -22,12 +37,58 @@ impl<B: Buffer> TextViewer<B> {
                 top: 0,
                 left: 0,
                 cursor: (0, 0),
-                hover: HoverFetch::Got(None),
+                anchor: None,
+                hover: None,
+                hover_popup: None,
                 completion: CompletionFetch::Got(None),
+                completion_start: None,
+                completion_resolve: None,
+                snippet: None,
+                signature: None,
+                signature_fetch: None,
             }
         )
     }
 
+    pub fn buffer(&self) -> &Rc<RefCell<B>> {
+        &self.buffer
+    }
+
+    pub fn cursor(&self) -> CursorPos {
+        self.cursor
+    }
+
+    /// Move the cursor to `pos`, clamped to the buffer.
+    pub fn jump_to(&mut self, pos: CursorPos) {
+        self.cursor = pos;
+        self.clamp_cursor();
+    }
+
+    pub fn start_selection(&mut self) {
+        self.anchor = Some(self.cursor);
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.anchor = None;
+    }
+
+    /// The selected text as `start..end`; both the anchor and the cursor are included.
+    pub fn selection(&self) -> Option<(CursorPos, CursorPos)> {
+        let anchor = self.anchor?;
+        let (start, last) = if anchor <= self.cursor { (anchor, self.cursor) } else { (self.cursor, anchor) };
+        Some((start, (last.0, last.1 + 1)))
+    }
+
+    /// Keep the cursor inside the buffer, which may have been edited through another viewer.
+    fn clamp_cursor(&mut self) {
+        let buffer = self.buffer.borrow();
+        let lines = buffer.len_lines();
+        self.cursor.0 = self.cursor.0.min(lines - 1);
+        let len = buffer.len_line_chars(self.cursor.0);
+        let end = if self.cursor.0 + 1 < lines { len.saturating_sub(1) } else { len };
+        self.cursor.1 = self.cursor.1.min(end);
+    }
+
     fn fix_top_left(&mut self, rect: &ViewerRect) {
         if self.top > self.cursor.0 {
             self.top = self.cursor.0;
@@ -43,65 +104,419 @@ impl<B: Buffer> TextViewer<B> {
             self.left = self.cursor.1 - rect.w + 1;
         }
     }
+
+    /// The terminal row and column of the cursor, e.g. to place a popup below it.
+    pub fn cursor_on_screen(&mut self, rect: &ViewerRect) -> (usize, usize) {
+        let rect = &self.text_rect(rect);
+        self.fix_top_left(rect);
+        assert!(self.top <= self.cursor.0);
+        assert!(self.cursor.0 < self.top + rect.h);
+        assert!(self.left <= self.cursor.1);
+        assert!(self.cursor.1 < self.left + rect.w);
+
+        (self.cursor.0 - self.top + rect.i, self.cursor.1 - self.left + rect.j)
+    }
+}
+
+/// Columns taken by the sign column while the buffer has diagnostics.
+const SIGN_WIDTH: usize = 2;
+/// Background of the snippet tab stop being filled in.
+const TAB_STOP: Color = Color { r: 60, g: 70, b: 90 };
+
+pub fn diagnostic_color(severity: DiagnosticSeverity) -> Color {
+    match severity {
+        DiagnosticSeverity::ERROR => Color { r: 255, g: 80, b: 80 },
+        DiagnosticSeverity::WARNING => Color { r: 230, g: 180, b: 40 },
+        DiagnosticSeverity::INFORMATION => Color { r: 80, g: 160, b: 255 },
+        _ => Color { r: 150, g: 150, b: 150 },
+    }
+}
+
+impl<B: Buffer> TextViewer<B> {
+    /// The part of `rect` left for the text next to the sign column.
+    fn text_rect(&self, rect: &ViewerRect) -> ViewerRect {
+        let signs = if self.buffer.borrow().diagnostics().is_empty() { 0 } else { SIGN_WIDTH.min(rect.w.saturating_sub(1)) };
+        ViewerRect { h: rect.h, w: rect.w - signs, i: rect.i, j: rect.j + signs }
+    }
+
+    fn draw_line(&self, i: usize, slice: RopeSlice, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
+        let buffer = self.buffer.borrow();
+        let diagnostics = buffer.diagnostics();
+        let row = rect.i + i - self.top;
+        let text_rect = self.text_rect(rect);
+        if text_rect.j > rect.j {
+            terminal.set_cursor(row, rect.j)?;
+            if let Some(severity) = diagnostics::sign(diagnostics, i) {
+                terminal.set_fg(diagnostic_color(severity))?;
+                terminal.write(diagnostics::label(severity)[..1].to_uppercase().as_bytes())?;
+                terminal.reset_style()?;
+            }
+        }
+
+        let len = slice.len_chars();
+        let len = if len > 0 && slice.char(len - 1) == '\n' { len - 1 } else { len };
+        let end = len.min(self.left + text_rect.w);
+        let marks = diagnostics::line_marks(diagnostics, i, len);
+        let selection = self.selection();
+        let selected = |j: usize| selection.is_some_and(|(start, end)| start <= (i, j) && (i, j) < end);
+        let tab_stop = self.snippet.as_ref().map_or(&[][..], |session| session.current());
+        let in_tab_stop = |j: usize| tab_stop.iter().any(|&(start, end)| start <= (i, j) && (i, j) < end);
+        terminal.set_cursor(row, text_rect.j)?;
+        let mut j = self.left;
+        while j < end {
+            let (mark, sel, stop) = (marks[j], selected(j), in_tab_stop(j));
+            let run_end = (j..end).find(|&k| marks[k] != mark || selected(k) != sel || in_tab_stop(k) != stop).unwrap_or(end);
+            if let Some(severity) = mark {
+                terminal.set_underline()?;
+                terminal.set_fg(diagnostic_color(severity))?;
+            }
+            if sel {
+                terminal.set_reverse()?;
+            }
+            if stop {
+                terminal.set_bg(TAB_STOP)?;
+            }
+            terminal.write(slice.slice(j..run_end).to_string().as_bytes())?;
+            if mark.is_some() || sel || stop {
+                terminal.reset_style()?;
+            }
+            j = run_end;
+        }
+        Ok(())
+    }
 }
 
 impl<B: Buffer> Draw for TextViewer<B> {
     fn draw_all(&mut self, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
-        self.fix_top_left(rect);
+        self.clamp_cursor();
+        let text_rect = self.text_rect(rect);
+        self.fix_top_left(&text_rect);
         let rope = self.buffer.borrow().rope_clone();
         for i in self.top..self.top + rect.h {
             if let Some(slice) = rope.get_line(i) {
-                let len = slice.len_chars();
-                terminal.set_cursor(rect.i + i - self.top, rect.j)?;
-                if len > 0 && self.left <= len - 1 {
-                    terminal.write(format!("{}", slice.slice(self.left..(len - 1).min(self.left + rect.w))).as_bytes())?;
-                }
+                self.draw_line(i, slice, rect, terminal)?;
             }
         }
-        /*
-        if let Some(&Some(ref hover)) = self.hover.try_get_result()? {
-            if hover.pos == self.cursor {
-                let mut view = HoverViewer::new(hover.text.to_owned());
-                view.draw_all(&ViewerRect {
-                    h: rect.h - self.cursor.0 - 1,
-                    w: rect.w - self.cursor.1,
-                    i: rect.i + self.cursor.0 + 1,
-                    j: rect.j + self.cursor.1,
-                }, terminal)?;
-            }
+        let rect = &text_rect;
+
+        let completion_shown = self.completion_shown();
+
+        self.poll_signature_help();
+        if let Some(ref mut signature) = self.signature {
+            let (row, col) = (self.cursor.0 - self.top, self.cursor.1 - self.left);
+            let (h, w) = signature.wanted_size();
+            let w = w.min(rect.w);
+            // opposite the completion list, which is below the cursor; without
+            // one, below the cursor when there is no room above
+            let (h, i) = if h <= row || completion_shown { (h.min(row), row - h.min(row)) } else { (h.min(rect.h.saturating_sub(row + 1)), row + 1) };
+            signature.draw_all(&ViewerRect { h, w, i: rect.i + i, j: rect.j + col.min(rect.w.saturating_sub(w)) }, terminal)?;
         }
-        */
 
-        if let Some(&mut Some(ref mut completion)) = self.completion.try_get_result_mut()? {
+        if let Ok(Some(Some(completion))) = self.completion.try_get_result_mut() {
             if completion.cursor == self.cursor {
                 completion.draw_all(
                     &ViewerRect {
-                        h: rect.h - self.cursor.0 - 1,
-                        w: rect.w - self.cursor.1,
-                        i: rect.i + self.cursor.0 + 1,
-                        j: rect.j + self.cursor.1,
+                        h: rect.h - (self.cursor.0 - self.top) - 1,
+                        w: rect.w - (self.cursor.1 - self.left),
+                        i: rect.i + (self.cursor.0 - self.top) + 1,
+                        j: rect.j + (self.cursor.1 - self.left),
                     }, terminal)?;
             }
         }
-        Ok(())
+        self.draw_hover(rect, terminal)
     }
     fn draw_cursor(&mut self, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
-        self.fix_top_left(rect);
-        assert!(self.top <= self.cursor.0);
-        assert!(self.cursor.0 < self.top + rect.h);
-        assert!(self.left <= self.cursor.1);
-        assert!(self.cursor.1 < self.left + rect.w);
-
-        terminal.set_cursor(self.cursor.0 - self.top + rect.i, self.cursor.1 - self.left + rect.j)?;
-        Ok(())
+        let (i, j) = self.cursor_on_screen(rect);
+        terminal.set_cursor(i, j)
     }
 }
 
 impl<B: Buffer> TextViewer<B> {
+    /// Widest the hover window gets, so that prose stays readable.
+    const HOVER_WIDTH: usize = 80;
+    /// How long accepting a completion waits for the item to be resolved.
+    const RESOLVE_TIMEOUT: Duration = Duration::from_millis(500);
+
+    /// Whether the completion list is open at the cursor.
+    pub fn completion_shown(&mut self) -> bool {
+        let cursor = self.cursor;
+        matches!(self.completion.try_get_result_mut(), Ok(Some(Some(completion))) if completion.cursor == cursor)
+    }
+
+    /// Filter the completion list once it arrives.
+    pub fn poll_completion(&mut self) -> anyhow::Result<()> {
+        // a failed completion closes the list, not the editor
+        self.filter_completion().inspect_err(|_| self.close_completion()).context("completion failed")
+    }
+
+    /// Show the answer to the hover request once it arrives.
+    pub fn poll_hover(&mut self) -> anyhow::Result<()> {
+        // the text changed under a hover still on its way
+        let version = self.buffer.borrow().version();
+        if let Some(fetch) = self.hover.take_if(|fetch| fetch.is_stale(version)) {
+            fetch.abort();
+        }
+        if let Some(fetch) = self.hover.as_mut() {
+            match fetch.try_get_result() {
+                Ok(None) => {
+                    if fetch.is_cancelled() {
+                        self.hover = None;
+                        return Err(anyhow!("Hover request cancelled"));
+                    }
+                }
+                Ok(Some(hover)) => {
+                    self.hover_popup = hover.as_ref().and_then(HoverViewer::new);
+                    self.hover = None;
+                }
+                Err(e) => {
+                    self.hover = None;
+                    return Err(e.context("hover failed"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Draw the hover window below the cursor, or above it when there is more room there.
+    fn draw_hover(&mut self, rect: &ViewerRect, terminal: &mut Terminal) -> anyhow::Result<()> {
+        if self.hover_popup.as_ref().is_some_and(|popup| popup.pos != self.cursor) {
+            self.hover_popup = None;
+        }
+        let Some(ref mut popup) = self.hover_popup else {
+            return Ok(());
+        };
+        let (row, col) = (self.cursor.0 - self.top, self.cursor.1 - self.left);
+        let (h, w) = popup.wanted_size(rect.w.min(Self::HOVER_WIDTH));
+        let w = w.min(rect.w);
+        let (below, above) = (rect.h.saturating_sub(row + 1), row);
+        let (h, i) = if h <= below || below >= above { (h.min(below), row + 1) } else { (h.min(above), row - h.min(above)) };
+        popup.draw_all(&ViewerRect { h, w, i: rect.i + i, j: rect.j + col.min(rect.w.saturating_sub(w)) }, terminal)
+    }
+
+    /// Scroll the hover window, if one is shown, by `delta` lines.
+    pub fn scroll_hover(&mut self, delta: isize) {
+        if let Some(ref mut popup) = self.hover_popup {
+            popup.scroll(delta);
+        }
+    }
+
+    pub fn close_hover(&mut self) {
+        if let Some(fetch) = self.hover.take() {
+            fetch.abort();
+        }
+        self.hover_popup = None;
+    }
+
+    /// Show the answer to the latest signature help request once it arrives.
+    fn poll_signature_help(&mut self) {
+        let Some(fetch) = self.signature_fetch.as_mut() else {
+            return;
+        };
+        match fetch.try_get_result_mut() {
+            Ok(None) => {
+                // called off because the text changed; the next keystroke asks again
+                if fetch.is_cancelled() {
+                    self.signature_fetch = None;
+                }
+            }
+            Ok(Some(signature)) => {
+                self.signature = signature.take();
+                self.signature_fetch = None;
+            }
+            // a failed update closes the popup, not the editor
+            Err(_) => self.close_signature_help(),
+        }
+    }
+
+    /// Filter the completion list by the word typed since it was asked for.
+    /// It is hidden while the cursor is before the start of that word.
+    fn filter_completion(&mut self) -> anyhow::Result<()> {
+        let Some(start) = self.completion_start else {
+            return Ok(());
+        };
+        if start.0 != self.cursor.0 || start.1 > self.cursor.1 {
+            return Ok(());
+        }
+        if let Some(&mut Some(ref mut completion)) = self.completion.try_get_result_mut()? {
+            let typed = self.buffer.borrow().rope_clone().line(start.0).chars().skip(start.1).take(self.cursor.1 - start.1).collect::<String>();
+            completion.filter(&typed, self.cursor);
+        }
+        Ok(())
+    }
+
+    /// Start filling in `snippet`, inserted at `start`, at its first tab stop.
+    fn start_snippet(&mut self, snippet: &Snippet, start: CursorPos) {
+        self.snippet = Some(SnippetSession::new(snippet, start));
+        self.enter_tab_stop();
+    }
+
+    /// Put the cursor at the current tab stop. Reaching the final one ends
+    /// the snippet; the choices of a stop are offered as completions.
+    fn enter_tab_stop(&mut self) {
+        let Some(ref session) = self.snippet else {
+            return;
+        };
+        let (start, _) = session.current()[0];
+        self.cursor = start;
+        if session.is_last() {
+            self.snippet = None;
+        }
+        else if !session.choices().is_empty() {
+            let choices = CompletionViewer::choices(session.choices(), start);
+            self.close_completion();
+            self.completion = CompletionFetch::Got(Some(choices));
+            self.completion_start = Some(start);
+        }
+    }
+
+    /// Move to the next or the previous tab stop of the snippet being
+    /// filled in. Returns `false` when there is none.
+    pub fn jump_tab_stop(&mut self, forward: bool) -> bool {
+        let Some(ref mut session) = self.snippet else {
+            return false;
+        };
+        if session.jump(forward) {
+            self.close_completion();
+            self.enter_tab_stop();
+        }
+        true
+    }
+
+    pub fn end_snippet(&mut self) {
+        self.snippet = None;
+    }
+
+    /// Delete the placeholder of the tab stop just entered, as typing over it does.
+    /// Returns `false` when there is none.
+    async fn replace_placeholder(&mut self) -> anyhow::Result<bool> {
+        let Some(ref mut session) = self.snippet else {
+            return Ok(false);
+        };
+        let Some((start, end)) = session.take_placeholder().filter(|_| session.contains(self.cursor)) else {
+            return Ok(false);
+        };
+        self.buffer.borrow_mut().edit(start, end, "")?;
+        self.cursor = start;
+        self.follow_snippet(&[(start, end, String::new())]).await?;
+        Ok(true)
+    }
+
+    /// Keep the tab stops of the snippet on their text after `edits`, and
+    /// copy the current one to its mirrors. Moving the cursor out of it
+    /// ends the snippet.
+    async fn follow_snippet(&mut self, edits: &[Edit]) -> anyhow::Result<()> {
+        let Some(ref mut session) = self.snippet else {
+            return Ok(());
+        };
+        apply_order(edits).iter().for_each(|edit| session.edited(edit));
+        if !session.contains(self.cursor) {
+            self.snippet = None;
+            return Ok(());
+        }
+        let rope = self.buffer.borrow().rope_clone();
+        let (start, end) = session.current()[0];
+        let idx = |pos: CursorPos| rope.line_to_char(pos.0) + pos.1;
+        let mirrors = session.mirror_edits(&rope.slice(idx(start)..idx(end)).to_string(), &rope);
+        if !mirrors.is_empty() {
+            self.cursor = self.buffer.borrow_mut().apply_edits(&mirrors, self.cursor)?;
+            apply_order(&mirrors).iter().for_each(|edit| session.edited(edit));
+        }
+        Ok(())
+    }
+
+    /// Take in the details of the completion item asked for before, and ask
+    /// for those of the selected item when the server has more to tell.
+    pub async fn resolve_completion(&mut self) -> anyhow::Result<()> {
+        self.filter_completion()?;
+        let Some(&mut Some(ref mut completion)) = self.completion.try_get_result_mut()? else {
+            return Ok(());
+        };
+        if let Some((idx, ref mut fetch)) = self.completion_resolve {
+            match fetch.try_get_result() {
+                Ok(None) => {
+                    // called off, the item is completed as it is
+                    if fetch.is_cancelled() {
+                        completion.set_resolved(idx, None);
+                        self.completion_resolve = None;
+                    }
+                }
+                Ok(Some(item)) => {
+                    completion.set_resolved(idx, Some(item.clone()));
+                    self.completion_resolve = None;
+                }
+                Err(e) => {
+                    completion.set_resolved(idx, None);
+                    self.completion_resolve = None;
+                    return Err(e.context("resolving completion failed"));
+                }
+            }
+        }
+        let Some(idx) = completion.selected() else {
+            return Ok(());
+        };
+        if completion.is_resolved(idx) || self.completion_resolve.as_ref().is_some_and(|&(pending, _)| pending == idx) {
+            return Ok(());
+        }
+        match self.buffer.borrow().resolve_completion(completion.item(idx).clone())? {
+            Some(fetch) => {
+                if let Some((_, old)) = self.completion_resolve.replace((idx, fetch)) {
+                    old.abort();
+                }
+            }
+            None => completion.set_resolved(idx, None),
+        }
+        Ok(())
+    }
+
+    /// Wait a little for the selected completion item to be resolved, for
+    /// the edits some servers only send then, such as an `#include` to add.
+    async fn await_resolve(&mut self) -> anyhow::Result<()> {
+        let Some(&mut Some(ref mut completion)) = self.completion.try_get_result_mut()? else {
+            return Ok(());
+        };
+        let Some(idx) = completion.selected().filter(|&idx| !completion.is_resolved(idx)) else {
+            return Ok(());
+        };
+        let fetch = match self.completion_resolve.take() {
+            Some((pending, fetch)) if pending == idx && !fetch.is_cancelled() => Some(fetch),
+            other => {
+                if let Some((_, old)) = other {
+                    old.abort();
+                }
+                self.buffer.borrow().resolve_completion(completion.item(idx).clone())?
+            }
+        };
+        // an item not resolved in time is completed as it is
+        if let Some(fetch) = fetch {
+            if let Ok(Some(item)) = fetch.await_within(Self::RESOLVE_TIMEOUT).await {
+                completion.set_resolved(idx, Some(item));
+            }
+        }
+        Ok(())
+    }
+
     async fn do_completion_raw(&mut self) -> anyhow::Result<()> {
-        if let Some(&Some(ref completion)) = self.completion.try_get_result()? {
+        // accepting a completion on a placeholder replaces it
+        self.replace_placeholder().await?;
+        self.filter_completion()?;
+        self.await_resolve().await?;
+        if let Some(Some(completion)) = self.completion.try_get_result()? {
             if completion.cursor == self.cursor {
-                self.cursor = completion.do_completion(&mut *self.buffer.borrow_mut()).await?;
+                let result = {
+                    let mut buffer = self.buffer.borrow_mut();
+                    buffer.begin_undo_group(self.cursor);
+                    let result = completion.do_completion(&mut *buffer);
+                    if let Ok(Some(ref accepted)) = result {
+                        self.cursor = accepted.cursor;
+                    }
+                    buffer.end_undo_group(self.cursor);
+                    result
+                };
+                match result? {
+                    Some(Accepted { snippet: Some((snippet, start)), .. }) => self.start_snippet(&snippet, start),
+                    Some(Accepted { edits, .. }) => self.follow_snippet(&edits).await?,
+                    None => {}
+                }
             }
         }
         Ok(())
@@ -113,7 +528,6 @@ impl<B: Buffer> Input for TextViewer<B> {
         if self.cursor.1 > 0 {
             self.cursor.1 -= 1;
         }
-        self.hover = HoverFetch::Got(None);
         Ok(())
     }
     fn move_right(&mut self) -> anyhow::Result<()> {
@@ -137,34 +551,95 @@ impl<B: Buffer> Input for TextViewer<B> {
         Ok(())
     }
     async fn insert_char(&mut self, c: char) -> anyhow::Result<()> {
-        self.cursor = self.buffer.borrow_mut().insert_char(self.cursor, c).await?;
-        Ok(())
+        self.replace_placeholder().await?;
+        let before = self.cursor;
+        self.cursor = self.buffer.borrow_mut().insert_char(self.cursor, c)?;
+        self.follow_snippet(&[(before, before, c.to_string())]).await
     }
     async fn newline(&mut self) -> anyhow::Result<()> {
-        self.cursor = self.buffer.borrow_mut().newline(self.cursor).await?;
-        Ok(())
+        self.replace_placeholder().await?;
+        let before = self.cursor;
+        self.cursor = self.buffer.borrow_mut().newline(self.cursor)?;
+        self.follow_snippet(&[(before, before, "\n".to_owned())]).await
     }
     async fn backspace(&mut self) -> anyhow::Result<()> {
-        self.cursor = self.buffer.borrow_mut().backspace(self.cursor).await?;
+        // backspace on a placeholder deletes all of it
+        if self.replace_placeholder().await? {
+            return Ok(());
+        }
+        let before = self.cursor;
+        self.cursor = self.buffer.borrow_mut().backspace(self.cursor)?;
+        if self.cursor != before {
+            self.follow_snippet(&[(self.cursor, before, String::new())]).await?;
+        }
         Ok(())
     }
 
+    async fn undo(&mut self) -> anyhow::Result<bool> {
+        let cursor = self.buffer.borrow_mut().undo()?;
+        if let Some(cursor) = cursor {
+            self.cursor = cursor;
+            self.clamp_cursor();
+        }
+        Ok(cursor.is_some())
+    }
+    async fn redo(&mut self) -> anyhow::Result<bool> {
+        let cursor = self.buffer.borrow_mut().redo()?;
+        if let Some(cursor) = cursor {
+            self.cursor = cursor;
+            self.clamp_cursor();
+        }
+        Ok(cursor.is_some())
+    }
+    async fn undo_travel(&mut self, target: UndoTarget) -> anyhow::Result<bool> {
+        let cursor = self.buffer.borrow_mut().undo_travel(target)?;
+        if let Some(cursor) = cursor {
+            self.cursor = cursor;
+            self.clamp_cursor();
+        }
+        Ok(cursor.is_some())
+    }
 
     async fn hover(&mut self) -> anyhow::Result<()> {
-        self.hover = self.buffer.borrow_mut().hover(self.cursor).await?.unwrap_or(HoverFetch::Got(None));
+        let fetch = self.buffer.borrow_mut().hover(self.cursor)?;
+        if let Some(old) = std::mem::replace(&mut self.hover, fetch) {
+            old.abort();
+        }
         Ok(())
     }
 
     async fn completion(&mut self) -> anyhow::Result<()> {
-        self.completion = self.buffer.borrow_mut().completion(self.cursor).await?.unwrap_or(CompletionFetch::Got(None));
-        Ok(())
+        let start = word_start(&self.buffer.borrow().rope_clone(), self.cursor);
+        // the last list is filtered locally while the same word grows,
+        // unless the server said it is incomplete
+        let version = self.buffer.borrow().version();
+        let reusable = match self.completion.try_get_result() {
+            Ok(Some(Some(completion))) => !completion.is_incomplete(),
+            // one still on its way is asked again for the text as it is now,
+            // one called off in any case
+            Ok(_) => !self.completion.is_stale(version) && !self.completion.is_cancelled(),
+            Err(_) => false,
+        };
+        if self.completion_start == Some(start) && reusable {
+            return Ok(());
+        }
+        let fetch = self.buffer.borrow_mut().completion(self.cursor)?.unwrap_or(CompletionFetch::Got(None));
+        std::mem::replace(&mut self.completion, fetch).abort();
+        if let Some((_, fetch)) = self.completion_resolve.take() {
+            fetch.abort();
+        }
+        self.completion_start = Some(start);
+        self.filter_completion()
     }
 
     async fn do_completion(&mut self) -> anyhow::Result<()> {
-        self.do_completion_raw().await
+        let result = self.do_completion_raw().await;
+        self.close_completion();
+        result
     }
 
     async fn completion_next(&mut self) -> anyhow::Result<()> {
+        self.filter_completion()?;
         if let Some(&mut Some(ref mut completion)) = self.completion.try_get_result_mut()? {
             completion.select_next();
         }
@@ -172,11 +647,39 @@ impl<B: Buffer> Input for TextViewer<B> {
     }
 
     async fn completion_prev(&mut self) -> anyhow::Result<()> {
+        self.filter_completion()?;
         if let Some(&mut Some(ref mut completion)) = self.completion.try_get_result_mut()? {
             completion.select_prev();
         }
         Ok(())
     }
+
+    async fn signature_help(&mut self, typed: Option<char>) -> anyhow::Result<()> {
+        if typed == Some(')') {
+            self.close_signature_help();
+            return Ok(());
+        }
+        let fetch = self.buffer.borrow_mut().signature_help(self.cursor, typed, self.signature.as_ref().map(|s| s.help()))?;
+        if let Some(old) = fetch.and_then(|fetch| self.signature_fetch.replace(fetch)) {
+            old.abort();
+        }
+        Ok(())
+    }
+
+    fn close_completion(&mut self) {
+        std::mem::replace(&mut self.completion, CompletionFetch::Got(None)).abort();
+        if let Some((_, fetch)) = self.completion_resolve.take() {
+            fetch.abort();
+        }
+        self.completion_start = None;
+    }
+
+    fn close_signature_help(&mut self) {
+        self.signature = None;
+        if let Some(fetch) = self.signature_fetch.take() {
+            fetch.abort();
+        }
+    }
 }
 
 impl<B: Buffer> Viewer for TextViewer<B> {}